@@ -0,0 +1,251 @@
+use std::{path::PathBuf, sync::Arc};
+
+use futures::{stream, Stream, StreamExt};
+use reqwest::{header::CONTENT_ENCODING, Client};
+
+use crate::{
+    corpus::{Corpus, Error},
+    download::{
+        self, decode_content, default_headers, mirror_url, sha256, DEFAULT_MIRROR,
+        DEFAULT_USER_AGENT,
+    },
+};
+
+/// A [`Corpus`] that downloads crates with an async client, so that many more downloads can be in
+/// flight at once than there are threads.
+///
+/// Extraction is still synchronous, and runs on tokio's blocking thread pool. Unlike the
+/// blocking [`Corpus`], the tarball cache and resumable downloads are not used.
+#[derive(Debug, Clone)]
+pub struct AsyncCorpus {
+    client: Client,
+    corpus: Arc<Corpus>,
+    mirror: String,
+}
+
+impl AsyncCorpus {
+    pub fn new(corpus: Corpus) -> Result<Self, Error> {
+        Ok(Self {
+            client: Self::client(DEFAULT_USER_AGENT, None)?,
+            corpus: Arc::new(corpus),
+            mirror: DEFAULT_MIRROR.to_string(),
+        })
+    }
+
+    /// Sets the `User-Agent` header sent with each download, along with a `From` header if a
+    /// contact is given.
+    pub fn with_identity(mut self, user_agent: &str, contact: Option<&str>) -> Result<Self, Error> {
+        self.client = Self::client(user_agent, contact)?;
+        Ok(self)
+    }
+
+    /// Downloads `.crate` files from the given base URL rather than [`DEFAULT_MIRROR`].
+    pub fn with_mirror(mut self, mirror: String) -> Self {
+        self.mirror = mirror;
+        self
+    }
+
+    fn client(user_agent: &str, contact: Option<&str>) -> Result<Client, Error> {
+        Ok(Client::builder()
+            .user_agent(user_agent)
+            .default_headers(default_headers(contact)?)
+            .build()
+            .map_err(download::Error::from)?)
+    }
+
+    pub fn corpus(&self) -> &Corpus {
+        &self.corpus
+    }
+
+    /// Populates a crate version, verifying the downloaded tarball against the SHA-256 checksum
+    /// from the index.
+    ///
+    /// As with [`Corpus::populate_many`], a newly populated version's manifest is checked if the
+    /// corpus has [`Corpus::with_manifest_verification`] set.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn populate(&self, name: &str, num: &str, cksum: &str) -> Result<PathBuf, Error> {
+        if let Some(path) = self.corpus.populated_path(name, num)? {
+            return Ok(path);
+        }
+
+        let data = self.download(name, num, cksum).await?;
+
+        let corpus = self.corpus.clone();
+        let (name, num) = (name.to_string(), num.to_string());
+        tokio::task::spawn_blocking(move || {
+            let path = corpus.populate_with(&name, &num, |_path, _data| {}, || Ok(data))?;
+            corpus.check_manifest(&name, &num)?;
+            Ok(path)
+        })
+        .await?
+    }
+
+    /// Populates each of the given `(name, num, cksum)` crate versions, with at most `concurrency`
+    /// in progress at once.
+    ///
+    /// Results are yielded as each version completes, which won't necessarily be in the order
+    /// they were given.
+    pub fn populate_all<I>(
+        &self,
+        versions: I,
+        concurrency: usize,
+    ) -> impl Stream<Item = (String, String, Result<PathBuf, Error>)> + '_
+    where
+        I: IntoIterator<Item = (String, String, String)>,
+        I::IntoIter: 'static,
+    {
+        stream::iter(versions)
+            .map(move |(name, num, cksum)| async move {
+                let result = self.populate(&name, &num, &cksum).await;
+                (name, num, result)
+            })
+            .buffer_unordered(concurrency)
+    }
+
+    async fn download(&self, name: &str, num: &str, cksum: &str) -> Result<Vec<u8>, Error> {
+        let resp = self
+            .client
+            .get(mirror_url(&self.mirror, name, num))
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(download::Error::from)?;
+        let encoding = resp
+            .headers()
+            .get(CONTENT_ENCODING)
+            .map(|value| value.to_str().unwrap_or_default().to_string());
+        let body = resp.bytes().await.map_err(download::Error::from)?;
+        let data = decode_content(encoding.as_deref(), body.to_vec())?;
+
+        let actual = sha256(&data);
+        if actual != cksum {
+            return Err(download::Error::ChecksumMismatch {
+                name: name.to_string(),
+                num: num.to_string(),
+                expected: cksum.to_string(),
+                actual,
+            }
+            .into());
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use flate2::{write::GzEncoder, Compression};
+    use googletest::prelude::*;
+    use tar::{Builder, Header};
+
+    use super::*;
+
+    #[test]
+    fn test_populate() -> anyhow::Result<()> {
+        let krate = tarball("foo", "1.0.0")?;
+        let mut found =
+            format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", krate.len()).into_bytes();
+        found.extend_from_slice(&krate);
+        let missing = b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n".to_vec();
+
+        let temp = tempfile::tempdir()?;
+        let corpus = AsyncCorpus::new(Corpus::new(temp.path().to_path_buf())?)?
+            .with_mirror(serve(vec![found.clone(), missing, found])?);
+        let cksum = sha256(&krate);
+        tokio::runtime::Runtime::new()?.block_on(async {
+            let path = corpus.populate("foo", "1.0.0", &cksum).await?;
+            assert_that!(path.join("Cargo.toml").is_file(), eq(true));
+
+            // Failures are returned, rather than panicking.
+            assert_that!(
+                corpus.populate("foo", "2.0.0", &cksum).await,
+                err(matches_pattern!(Error::Download(anything())))
+            );
+            assert_that!(
+                corpus.populate("foo", "3.0.0", &sha256(b"")).await,
+                err(matches_pattern!(Error::Download(matches_pattern!(
+                    download::Error::ChecksumMismatch { .. }
+                ))))
+            );
+
+            anyhow::Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_populate_verify_manifest() -> anyhow::Result<()> {
+        let krate = tarball_in("foo-1.0.1", "foo", "1.0.0")?;
+        let mut found =
+            format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", krate.len()).into_bytes();
+        found.extend_from_slice(&krate);
+
+        let temp = tempfile::tempdir()?;
+        let corpus =
+            AsyncCorpus::new(Corpus::new(temp.path().to_path_buf())?.with_manifest_verification())?
+                .with_mirror(serve(vec![found])?);
+        tokio::runtime::Runtime::new()?.block_on(async {
+            assert_that!(
+                corpus.populate("foo", "1.0.1", &sha256(&krate)).await,
+                err(matches_pattern!(Error::Vault(matches_pattern!(
+                    vault::Error::ManifestMismatch { .. }
+                ))))
+            );
+
+            anyhow::Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    /// Serves each canned HTTP response in turn, one per connection, on a local port, returning
+    /// its base URL.
+    fn serve(responses: Vec<Vec<u8>>) -> anyhow::Result<String> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        std::thread::spawn(move || -> std::io::Result<()> {
+            for response in responses {
+                let (mut stream, _) = listener.accept()?;
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                stream.write_all(&response)?;
+            }
+            Ok(())
+        });
+        Ok(url)
+    }
+
+    /// Builds a gzipped `.crate` file containing only a manifest.
+    fn tarball(name: &str, num: &str) -> anyhow::Result<Vec<u8>> {
+        tarball_in(&format!("{name}-{num}"), name, num)
+    }
+
+    /// Like [`tarball`], but with the manifest in the given directory, which needn't match it.
+    fn tarball_in(dir: &str, name: &str, num: &str) -> anyhow::Result<Vec<u8>> {
+        let mut builder = Builder::new(Vec::new());
+        let manifest = format!("[package]\nname = \"{name}\"\nversion = \"{num}\"\n");
+        let mut header = Header::new_gnu();
+        header.set_size(manifest.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(
+            &mut header,
+            format!("{dir}/Cargo.toml"),
+            manifest.as_bytes(),
+        )?;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner()?)?;
+        Ok(encoder.finish()?)
+    }
+}
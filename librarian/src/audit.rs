@@ -0,0 +1,248 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+use vault::Vault;
+
+use crate::{cache::TarballCache, download};
+
+/// A list of known-good `.crate` checksums, read from lines of `name version sha256`.
+///
+/// Blank lines and lines starting with `#` are ignored.
+#[derive(Debug, Default, Clone)]
+pub struct Baseline(BTreeMap<(String, String), String>);
+
+impl Baseline {
+    #[tracing::instrument(err)]
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let mut checksums = BTreeMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name, num, cksum] = fields.as_slice() else {
+                return Err(Error::InvalidBaselineLine(i + 1, line.to_string()));
+            };
+            checksums.insert(
+                (name.to_ascii_lowercase(), num.to_string()),
+                cksum.to_ascii_lowercase(),
+            );
+        }
+
+        Ok(Self(checksums))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A discrepancy between a corpus and a [`Baseline`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Finding {
+    /// The version's checksum differs from the baseline.
+    Mismatch {
+        name: String,
+        num: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// The version is in the corpus, but not the baseline.
+    MissingFromBaseline { name: String, num: String },
+
+    /// The version is in the baseline, but not the corpus.
+    MissingFromCorpus { name: String, num: String },
+
+    /// The version is in both, but there's no checksum to compare: it wasn't populated into a
+    /// content addressed corpus, and its tarball isn't cached.
+    Unverifiable { name: String, num: String },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mismatch {
+                name,
+                num,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{name} {num}: checksum mismatch: expected {expected}, got {actual}"
+            ),
+            Self::MissingFromBaseline { name, num } => {
+                write!(f, "{name} {num}: not in baseline")
+            }
+            Self::MissingFromCorpus { name, num } => write!(f, "{name} {num}: not in corpus"),
+            Self::Unverifiable { name, num } => write!(f, "{name} {num}: no checksum available"),
+        }
+    }
+}
+
+/// Compares the crate versions in a corpus against a baseline.
+///
+/// A version's checksum is taken from where it is stored in a content addressed vault, or failing
+/// that, recomputed from the tarball cache if one is given. Versions with neither are reported as
+/// [`Finding::Unverifiable`].
+#[tracing::instrument(skip(vault, baseline, cache), err)]
+pub fn audit(
+    vault: &Vault,
+    baseline: &Baseline,
+    cache: Option<&TarballCache>,
+) -> Result<Vec<Finding>, Error> {
+    let mut expected = baseline.0.clone();
+    let mut findings = Vec::new();
+
+    for version in vault.iter_crate_versions() {
+        let version = version?;
+        let (name, num) = (version.crate_name, version.version);
+        let Some(cksum) = expected.remove(&(name.to_ascii_lowercase(), num.clone())) else {
+            findings.push(Finding::MissingFromBaseline { name, num });
+            continue;
+        };
+
+        let actual = match vault.recorded_checksum(&name, &num)? {
+            Some(actual) => Some(actual),
+            None => match cache {
+                Some(cache) => cache
+                    .get(&name, &num)
+                    .map_err(|e| Error::Io(cache.path(&name, &num), e))?
+                    .map(|data| download::sha256(&data)),
+                None => None,
+            },
+        };
+
+        match actual {
+            Some(actual) if actual != cksum => findings.push(Finding::Mismatch {
+                name,
+                num,
+                expected: cksum,
+                actual,
+            }),
+            Some(_actual) => {}
+            None => findings.push(Finding::Unverifiable { name, num }),
+        }
+    }
+
+    findings.extend(
+        expected
+            .into_keys()
+            .map(|(name, num)| Finding::MissingFromCorpus { name, num }),
+    );
+    findings.sort();
+
+    Ok(findings)
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid baseline line {0}: {1:?}")]
+    InvalidBaselineLine(usize, String),
+
+    #[error("io error at {0:?}: {1:?}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    #[error("vault error: {0:?}")]
+    Vault(#[from] vault::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let baseline = Baseline::parse("# comment\n\nSerde 1.0.0 ABC\nfoo 0.1.0 def\n")?;
+        assert_that!(baseline.len(), eq(2));
+        assert_that!(
+            baseline.0.get(&("serde".to_string(), "1.0.0".to_string())),
+            some(eq("abc"))
+        );
+
+        assert_that!(
+            Baseline::parse("foo 0.1.0\n"),
+            err(matches_pattern!(Error::InvalidBaselineLine(
+                eq(1),
+                eq("foo 0.1.0")
+            )))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_audit() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().join("corpus"));
+        let cache = TarballCache::new(temp.path().join("cache"))?;
+
+        for (name, num) in [
+            ("good", "1.0.0"),
+            ("bad", "1.0.0"),
+            ("extra", "1.0.0"),
+            ("lost", "1.0.0"),
+        ] {
+            let path = vault.crate_version_path(&name.parse()?, &num.parse()?)?;
+            std::fs::create_dir_all(&path)?;
+            std::fs::write(
+                path.join("Cargo.toml"),
+                format!("[package]\nname = \"{name}\"\nversion = \"{num}\"\n"),
+            )?;
+        }
+        cache.insert("good", "1.0.0", b"good")?;
+        cache.insert("bad", "1.0.0", b"tampered")?;
+
+        let baseline = Baseline::parse(&format!(
+            "good 1.0.0 {}\nbad 1.0.0 {}\nlost 1.0.0 {}\ngone 1.0.0 {}\n",
+            download::sha256(b"good"),
+            download::sha256(b"bad"),
+            download::sha256(b"lost"),
+            download::sha256(b"gone"),
+        ))?;
+
+        assert_that!(
+            audit(&vault, &baseline, Some(&cache))?,
+            eq(vec![
+                Finding::Mismatch {
+                    name: "bad".to_string(),
+                    num: "1.0.0".to_string(),
+                    expected: download::sha256(b"bad"),
+                    actual: download::sha256(b"tampered"),
+                },
+                Finding::MissingFromBaseline {
+                    name: "extra".to_string(),
+                    num: "1.0.0".to_string(),
+                },
+                Finding::MissingFromCorpus {
+                    name: "gone".to_string(),
+                    num: "1.0.0".to_string(),
+                },
+                Finding::Unverifiable {
+                    name: "lost".to_string(),
+                    num: "1.0.0".to_string(),
+                },
+            ])
+        );
+
+        Ok(())
+    }
+}
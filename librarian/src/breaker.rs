@@ -0,0 +1,237 @@
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The backoff after the breaker first trips, unless overridden with
+/// [`CircuitBreaker::with_backoff`].
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The longest the breaker will back off for, unless overridden with
+/// [`CircuitBreaker::with_backoff`].
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// Pauses downloads across a whole run when too many of them are failing, so that an outage
+/// doesn't have every remaining crate version hammering the CDN.
+///
+/// Callers [`wait`](CircuitBreaker::wait) before each download, and
+/// [`record`](CircuitBreaker::record) whether it succeeded afterwards with the [`Permit`] that
+/// `wait` returned. Once the failure rate over the most recent downloads reaches the threshold,
+/// the breaker trips, and callers are held until the backoff has elapsed. A single caller is then
+/// let through as a probe: if it succeeds, downloads resume, and if not, the backoff is doubled and
+/// the breaker trips again.
+///
+/// Only downloads that started since the breaker last tripped or closed are counted, so that a
+/// download that was already in flight can't trip the breaker again, or stand in for the probe.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    window: usize,
+    threshold: f64,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    /// The outcomes of the most recent downloads, with `true` for success.
+    recent: VecDeque<bool>,
+    phase: Phase,
+    backoff: Duration,
+    /// Incremented whenever the breaker trips or closes, so that outcomes of downloads that
+    /// started before then can be told apart.
+    generation: u64,
+}
+
+/// Permission to download, returned by [`CircuitBreaker::wait`], to be handed back to
+/// [`CircuitBreaker::record`] along with the outcome.
+#[derive(Debug)]
+#[must_use = "the outcome of the download should be recorded with the permit"]
+pub struct Permit {
+    generation: u64,
+    probe: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Closed,
+    Open { until: Instant },
+    Probing,
+}
+
+impl CircuitBreaker {
+    /// Creates a breaker that trips when at least `threshold` (between 0 and 1) of the last
+    /// `window` downloads failed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn new(window: usize, threshold: f64) -> Self {
+        assert!(window > 0, "circuit breaker window must be non-zero");
+        Self {
+            window,
+            threshold,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            state: Mutex::new(State {
+                recent: VecDeque::with_capacity(window),
+                phase: Phase::Closed,
+                backoff: DEFAULT_INITIAL_BACKOFF,
+                generation: 0,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Sets how long the breaker pauses for when it first trips, and the limit that the backoff
+    /// doubles up to while probes keep failing.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self.state.get_mut().expect("circuit breaker lock").backoff = initial;
+        self
+    }
+
+    /// Blocks while the breaker is tripped, returning once the caller may download.
+    pub fn wait(&self) -> Permit {
+        let mut state = self.state.lock().expect("circuit breaker lock");
+        loop {
+            match state.phase {
+                Phase::Closed => {
+                    return Permit {
+                        generation: state.generation,
+                        probe: false,
+                    }
+                }
+                Phase::Open { until } => {
+                    let now = Instant::now();
+                    if now >= until {
+                        // This caller gets to be the probe.
+                        state.phase = Phase::Probing;
+                        return Permit {
+                            generation: state.generation,
+                            probe: true,
+                        };
+                    }
+                    state = self
+                        .changed
+                        .wait_timeout(state, until - now)
+                        .expect("circuit breaker lock")
+                        .0;
+                }
+                Phase::Probing => {
+                    state = self.changed.wait(state).expect("circuit breaker lock");
+                }
+            }
+        }
+    }
+
+    /// Records the outcome of a download, given the permit it was started with.
+    pub fn record(&self, permit: Permit, success: bool) {
+        let mut state = self.state.lock().expect("circuit breaker lock");
+        // Downloads that began before the breaker last tripped or closed don't tell us anything
+        // new.
+        if permit.generation != state.generation {
+            return;
+        }
+        match state.phase {
+            Phase::Closed => {}
+            Phase::Open { .. } => return,
+            // Only the probe gets to decide whether downloads resume.
+            Phase::Probing if !permit.probe => return,
+            Phase::Probing => {
+                if success {
+                    tracing::info!("probe download succeeded; resuming downloads");
+                    state.phase = Phase::Closed;
+                    state.generation += 1;
+                    state.recent.clear();
+                    state.backoff = self.initial_backoff;
+                } else {
+                    state.backoff = (state.backoff * 2).min(self.max_backoff);
+                    self.trip(&mut state);
+                }
+                self.changed.notify_all();
+                return;
+            }
+        }
+
+        if state.recent.len() == self.window {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(success);
+
+        let failures = state.recent.iter().filter(|success| !**success).count();
+        if state.recent.len() == self.window
+            && failures as f64 / self.window as f64 >= self.threshold
+        {
+            self.trip(&mut state);
+        }
+    }
+
+    fn trip(&self, state: &mut State) {
+        tracing::warn!(
+            backoff = ?state.backoff,
+            "too many downloads are failing; pausing downloads"
+        );
+        state.phase = Phase::Open {
+            until: Instant::now() + state.backoff,
+        };
+        state.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let backoff = Duration::from_millis(20);
+        let breaker = CircuitBreaker::new(4, 0.5).with_backoff(backoff, backoff * 3);
+
+        // One failure in four isn't enough to trip it.
+        for success in [true, false, true, true] {
+            let permit = breaker.wait();
+            breaker.record(permit, success);
+        }
+        assert_that!(current_phase(&breaker), eq(Phase::Closed));
+
+        // This download is still in flight when the breaker trips.
+        let stale = breaker.wait();
+        let permit = breaker.wait();
+        breaker.record(permit, false);
+        assert_that!(
+            current_phase(&breaker),
+            matches_pattern!(Phase::Open { until: anything() })
+        );
+
+        // The probe fails, so the backoff doubles.
+        let started = Instant::now();
+        let probe = breaker.wait();
+        assert_that!(started.elapsed(), ge(backoff));
+        assert_that!(current_phase(&breaker), eq(Phase::Probing));
+        // The download that was in flight finishing doesn't count as the probe.
+        breaker.record(stale, true);
+        assert_that!(current_phase(&breaker), eq(Phase::Probing));
+        breaker.record(probe, false);
+
+        let started = Instant::now();
+        let probe = breaker.wait();
+        assert_that!(started.elapsed(), ge(backoff * 2));
+        breaker.record(probe, true);
+        assert_that!(current_phase(&breaker), eq(Phase::Closed));
+
+        // The window was cleared, so a single failure doesn't trip it again.
+        let permit = breaker.wait();
+        breaker.record(permit, false);
+        assert_that!(current_phase(&breaker), eq(Phase::Closed));
+    }
+
+    fn current_phase(breaker: &CircuitBreaker) -> Phase {
+        breaker.state.lock().expect("lock").phase
+    }
+}
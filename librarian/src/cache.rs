@@ -0,0 +1,227 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tempfile::NamedTempFile;
+use walkdir::WalkDir;
+
+/// An on-disk cache of downloaded `.crate` files, keyed by crate name and version.
+///
+/// The cache itself doesn't validate its contents: callers are expected to verify cached tarballs
+/// against the index checksum before trusting them.
+#[derive(Debug)]
+pub struct TarballCache(PathBuf);
+
+impl TarballCache {
+    #[tracing::instrument(err)]
+    pub fn new(path: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+
+    pub fn path(&self, name: &str, num: &str) -> PathBuf {
+        self.0.join(name).join(format!("{name}-{num}.crate"))
+    }
+
+    /// Returns the cached tarball, if any.
+    ///
+    /// Cache hits update the modification time of the entry, which is used to select the least
+    /// recently used entries in [`TarballCache::evict`].
+    pub fn get(&self, name: &str, num: &str) -> std::io::Result<Option<Vec<u8>>> {
+        let path = self.path(name, num);
+        match std::fs::read(&path) {
+            Ok(data) => {
+                touch(&path)?;
+                Ok(Some(data))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like [`TarballCache::get`], but returns the cached tarball open for reading, rather than
+    /// reading it into memory.
+    pub fn open(&self, name: &str, num: &str) -> std::io::Result<Option<File>> {
+        let path = self.path(name, num);
+        match File::open(&path) {
+            Ok(file) => {
+                touch(&path)?;
+                Ok(Some(file))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn insert(&self, name: &str, num: &str, data: &[u8]) -> std::io::Result<()> {
+        let path = self.path(name, num);
+        let parent = path.parent().unwrap_or(&self.0);
+        std::fs::create_dir_all(parent)?;
+
+        // Write to a temporary file first so that concurrent readers never see a partial entry.
+        let mut file = NamedTempFile::new_in(parent)?;
+        file.write_all(data)?;
+        file.persist(&path)?;
+
+        Ok(())
+    }
+
+    /// Like [`TarballCache::insert`], but copies the tarball from a file rather than memory.
+    pub fn insert_file(&self, name: &str, num: &str, src: &Path) -> std::io::Result<()> {
+        let path = self.path(name, num);
+        std::fs::create_dir_all(path.parent().unwrap_or(&self.0))?;
+        copy_atomically(src, &path)
+    }
+
+    /// Copies a cached tarball to `dest`, returning false if it isn't cached.
+    ///
+    /// With the `reflink` feature, the copy is a copy-on-write clone where the filesystem
+    /// supports it (btrfs, XFS, APFS), so it takes no extra space. Otherwise, or if the
+    /// filesystem doesn't support it, the file is copied normally.
+    pub fn copy_to(&self, name: &str, num: &str, dest: &Path) -> std::io::Result<bool> {
+        let path = self.path(name, num);
+        if !path.is_file() {
+            return Ok(false);
+        }
+
+        copy_atomically(&path, dest)?;
+        Ok(true)
+    }
+
+    pub fn remove(&self, name: &str, num: &str) -> std::io::Result<()> {
+        match std::fs::remove_file(self.path(name, num)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes the least recently used entries until the cache is no larger than `max_bytes`,
+    /// returning the number of bytes removed.
+    #[tracing::instrument(err)]
+    pub fn evict(&self, max_bytes: u64) -> std::io::Result<u64> {
+        let mut entries = Vec::new();
+        for entry in WalkDir::new(&self.0).min_depth(2).max_depth(2) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                let metadata = entry.metadata()?;
+                entries.push((metadata.modified()?, metadata.len(), entry.into_path()));
+            }
+        }
+
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        let mut removed = 0;
+        entries.sort();
+        for (_modified, len, path) in entries {
+            if total <= max_bytes {
+                break;
+            }
+
+            remove_entry(&path)?;
+            total -= len;
+            removed += len;
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Updates the modification time of a cache entry, marking it as recently used.
+fn touch(path: &Path) -> std::io::Result<()> {
+    File::options()
+        .append(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+/// Copies a file, going through a temporary path as in [`TarballCache::insert`] so that `dest`
+/// is never partially written.
+fn copy_atomically(src: &Path, dest: &Path) -> std::io::Result<()> {
+    let parent = dest.parent().unwrap_or(Path::new("."));
+    let temp = NamedTempFile::new_in(parent)?.into_temp_path();
+    std::fs::remove_file(&temp)?;
+    copy_file(src, &temp)?;
+    temp.persist(dest)?;
+
+    Ok(())
+}
+
+fn remove_entry(path: &Path) -> std::io::Result<()> {
+    std::fs::remove_file(path)?;
+
+    // Tidy up the crate directory if that was the last version in it.
+    if let Some(parent) = path.parent() {
+        if std::fs::read_dir(parent)?.next().is_none() {
+            std::fs::remove_dir(parent)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "reflink")]
+fn copy_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    reflink_copy::reflink_or_copy(from, to).map(|_| ())
+}
+
+#[cfg(not(feature = "reflink"))]
+fn copy_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::copy(from, to).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_cache() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let cache = TarballCache::new(temp.path().to_path_buf())?;
+
+        assert_that!(cache.get("foo", "1.0.0")?, none());
+
+        cache.insert("foo", "1.0.0", b"foo 1")?;
+        cache.insert("foo", "2.0.0", b"foo 2")?;
+        cache.insert("bar", "1.0.0", b"bar 1")?;
+        assert_that!(cache.get("foo", "1.0.0")?, some(eq(b"foo 1".to_vec())));
+
+        cache.remove("foo", "2.0.0")?;
+        cache.remove("foo", "2.0.0")?;
+        assert_that!(cache.get("foo", "2.0.0")?, none());
+
+        // Make bar the least recently used entry.
+        File::options()
+            .append(true)
+            .open(cache.path("bar", "1.0.0"))?
+            .set_modified(SystemTime::now() - Duration::from_secs(60))?;
+
+        assert_that!(cache.evict(5)?, eq(5));
+        assert_that!(cache.get("bar", "1.0.0")?, none());
+        assert_that!(temp.path().join("bar").exists(), eq(false));
+        assert_that!(cache.get("foo", "1.0.0")?, some(anything()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_to() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let cache = TarballCache::new(temp.path().join("cache"))?;
+        let dest = temp.path().join("foo-1.0.0.crate");
+
+        assert_that!(cache.copy_to("foo", "1.0.0", &dest)?, eq(false));
+        assert_that!(dest.exists(), eq(false));
+
+        cache.insert("foo", "1.0.0", b"foo 1")?;
+        assert_that!(cache.copy_to("foo", "1.0.0", &dest)?, eq(true));
+        assert_that!(std::fs::read(&dest)?, eq(b"foo 1".to_vec()));
+
+        Ok(())
+    }
+}
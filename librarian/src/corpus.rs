@@ -1,16 +1,234 @@
-use std::{io::ErrorKind, path::PathBuf};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fs::File,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant, SystemTime},
+};
 
-use flate2::read::GzDecoder;
-use reqwest::blocking::Client;
-use tar::Archive;
-use tempfile::tempdir_in;
+use flate2::{bufread::GzDecoder, write::GzEncoder, Compression};
+use fs2::FileExt;
+use rayon::iter::{ParallelBridge, ParallelIterator};
+use tar::{Archive, Builder, Entry};
+use tempfile::{tempdir_in, NamedTempFile};
 use thiserror::Error;
-use vault::Vault;
+use vault::{CrateName, CrateVersionNum, Layout, Manifest, Vault};
+
+use crate::{
+    breaker::CircuitBreaker,
+    cache::TarballCache,
+    download::{self, Downloader},
+    history::{self, VersionHistory},
+    index::{self, Index},
+    progress::Progress,
+    telemetry,
+    throttle::BandwidthLimiter,
+    timing::{ResultStream, TimingLog, Timings},
+};
+
+/// The advisory lock file held at the root of a corpus while it is being modified.
+const LOCK_FILE: &str = ".librarian.lock";
+
+/// The file at the root of a corpus recording the index commit it was last synced to.
+const SYNCED_FILE: &str = ".librarian-synced";
+
+/// The directory, relative to the corpus root, that crate versions are extracted into when
+/// extraction is resumable.
+const EXTRACTING_DIR: &str = ".extracting";
+
+/// The journal of completed entries kept alongside each resumable extraction.
+const JOURNAL_FILE: &str = "completed";
+
+/// The `.crate` file size above which [`Corpus::populate_sized`] streams downloads to disk, unless
+/// overridden with [`Corpus::with_streaming_threshold`].
+pub const DEFAULT_STREAMING_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// The size of the chunks that each file is copied in as it's extracted.
+const EXTRACT_CHUNK_SIZE: usize = 64 * 1024;
+
+thread_local! {
+    /// The buffer that each file is copied through as it's extracted, reused between files (and
+    /// crates) rather than allocated afresh for each one.
+    static EXTRACT_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Calls `f` with this thread's extraction buffer, which is [`EXTRACT_CHUNK_SIZE`] bytes long.
+///
+/// The buffer is taken out of the pool while `f` runs, so a nested call just gets a fresh buffer
+/// rather than panicking.
+fn with_extract_buffer<T>(f: impl FnOnce(&mut [u8]) -> T) -> T {
+    EXTRACT_BUFFER.with(|pool| {
+        let mut buffer = pool.take();
+        buffer.resize(EXTRACT_CHUNK_SIZE, 0);
+        let result = f(&mut buffer);
+        pool.replace(buffer);
+        result
+    })
+}
+
+/// Removes `count` components from an archive entry path, after the first, which is the
+/// `{name}-{version}` directory. Returns `None` if nothing would be left beneath that directory.
+fn strip_components(path: &Path, count: usize) -> Option<PathBuf> {
+    let mut components = path
+        .components()
+        .filter(|component| *component != Component::CurDir);
+    let root = components.next()?;
+    let rest: PathBuf = components.skip(count).collect();
+    (!rest.as_os_str().is_empty()).then(|| Path::new(&root).join(rest))
+}
+
+/// Creates the directories leading up to `entry_path` within `dest`, refusing to go through any
+/// symlink that an earlier entry in the archive may have planted along the way.
+fn create_parents(dest: &Path, entry_path: &Path) -> Result<(), Error> {
+    let Some(parent) = entry_path.parent() else {
+        return Ok(());
+    };
+    let mut path = dest.to_path_buf();
+    for component in parent.components() {
+        if component == Component::CurDir {
+            continue;
+        }
+        path.push(component);
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => return Err(Error::UnsafePath(entry_path.to_path_buf())),
+            Err(e) if e.kind() == ErrorKind::NotFound => std::fs::create_dir(&path)?,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// A transform applied to the top-level `Cargo.toml` of each crate version as it is populated.
+pub type ManifestTransform = fn(&mut toml::Table);
+
+/// A validator run over the root directory of each crate version once it has been extracted, but
+/// before it is moved into the corpus.
+pub type PostExtractHook = fn(&Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+/// What [`Corpus::populate_many`] does when a crate version fails to populate.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    /// Carry on with the remaining versions.
+    #[default]
+    KeepGoing,
+
+    /// Stop starting new versions.
+    FailFast,
+}
+
+/// What happens when a tarball contains more than one entry for the same path.
+///
+/// Versions are always extracted into a fresh directory, so duplicates can only come from within
+/// the tarball itself, which `cargo package` never produces.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Later entries replace earlier ones, as `tar` does by default.
+    #[default]
+    Allow,
+
+    /// Fail with [`Error::DuplicateEntry`].
+    Error,
+
+    /// Keep the first entry, and ignore any later ones.
+    Skip,
+}
+
+/// How the modes of extracted files and directories are set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ModeNormalization {
+    /// Files keep the permission bits recorded in the tarball.
+    #[default]
+    Preserve,
+
+    /// Files are made `0644`, or `0755` if the tarball marks them as executable by anyone, and
+    /// directories `0755`.
+    Normalize,
+
+    /// Files are made `0644` and directories `0755`, regardless of the tarball.
+    StripExecutable,
+}
+
+/// The owner and group that extracted files and directories are changed to. Leaving either as
+/// `None` keeps it unchanged.
+///
+/// Changing the owner requires root (or `CAP_CHOWN`); an unprivileged process can only change the
+/// group to one of its own groups.
+#[cfg(unix)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Ownership {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+#[cfg(unix)]
+impl Ownership {
+    /// Changes the ownership of each path from `dest` down to `dest/entry_path`, not including
+    /// `dest` itself. Paths in `done` are skipped, and paths that are changed are added to it, so
+    /// that directories shared by many entries are only changed once.
+    ///
+    /// Symlinks are changed rather than followed. Entries that weren't extracted, such as those
+    /// with unsafe paths, are ignored.
+    fn apply(
+        self,
+        dest: &Path,
+        entry_path: &Path,
+        done: &mut HashSet<PathBuf>,
+    ) -> std::io::Result<()> {
+        let mut path = dest.to_path_buf();
+        for component in entry_path.components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => continue,
+                _ => return Ok(()),
+            }
+            if done.contains(&path) {
+                continue;
+            }
+
+            match std::os::unix::fs::lchown(&path, self.uid, self.gid) {
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+                result => result?,
+            }
+            done.insert(path.clone());
+        }
+
+        Ok(())
+    }
+}
+
+impl ModeNormalization {
+    fn file_mode(self, mode: u32) -> u32 {
+        match self {
+            Self::Preserve => mode & 0o777,
+            Self::Normalize if mode & 0o111 != 0 => 0o755,
+            Self::Normalize | Self::StripExecutable => 0o644,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Corpus {
-    client: Client,
+    circuit_breaker: Option<CircuitBreaker>,
+    downloader: Downloader,
+    history: Option<VersionHistory>,
+    index: Option<Index>,
     vault: Vault,
+    manifest_transform: Option<ManifestTransform>,
+    post_extract: Option<PostExtractHook>,
+    mode_normalization: ModeNormalization,
+    #[cfg(unix)]
+    ownership: Option<Ownership>,
+    overwrite_policy: OverwritePolicy,
+    result_stream: Option<ResultStream>,
+    resumable_extraction: bool,
+    streaming_threshold: u64,
+    strip_components: usize,
+    timing_log: Option<TimingLog>,
+    verify_manifests: bool,
 }
 
 impl Corpus {
@@ -19,64 +237,2563 @@ impl Corpus {
         std::fs::create_dir_all(&path)?;
 
         Ok(Self {
-            client: Client::new(),
+            circuit_breaker: None,
+            // Partial downloads are kept alongside the vault, which ignores anything without a
+            // manifest.
+            downloader: Downloader::new()?.with_partial_dir(path.join(".partial")),
+            history: None,
+            index: None,
             vault: Vault::new(path),
+            manifest_transform: None,
+            post_extract: None,
+            mode_normalization: ModeNormalization::default(),
+            #[cfg(unix)]
+            ownership: None,
+            overwrite_policy: OverwritePolicy::default(),
+            result_stream: None,
+            resumable_extraction: false,
+            streaming_threshold: DEFAULT_STREAMING_THRESHOLD,
+            strip_components: 0,
+            timing_log: None,
+            verify_manifests: false,
         })
     }
 
+    /// Sets the `User-Agent` header sent with each download, along with a `From` header if a
+    /// contact is given.
+    ///
+    /// See [`Downloader::with_identity`].
+    pub fn with_identity(mut self, user_agent: &str, contact: Option<&str>) -> Result<Self, Error> {
+        self.downloader = self.downloader.with_identity(user_agent, contact)?;
+        Ok(self)
+    }
+
+    /// Rewrites the top-level `Cargo.toml` of each populated crate version with the given
+    /// transform before it is moved into the corpus.
+    ///
+    /// The original manifest is preserved as `Cargo.toml.orig`, unless the crate already shipped
+    /// one (as `cargo package` normally does). Old crates that were published with a lowercase
+    /// `cargo.toml` have that transformed instead, with the original kept as `cargo.toml.orig`.
+    pub fn with_manifest_transform(mut self, transform: ManifestTransform) -> Self {
+        self.manifest_transform = Some(transform);
+        self
+    }
+
+    /// Runs the given hook over each crate version after it has been extracted (and its manifest
+    /// transformed, if there's a transform), but before it is moved into the corpus.
+    ///
+    /// If the hook returns an error, the version is not populated, and the error is returned as
+    /// [`Error::PostExtract`].
+    pub fn with_post_extract(mut self, hook: PostExtractHook) -> Self {
+        self.post_extract = Some(hook);
+        self
+    }
+
+    /// Sets how the modes of extracted files and directories are normalized.
+    ///
+    /// By default, files keep the modes recorded in the tarball.
+    pub fn with_mode_normalization(mut self, normalization: ModeNormalization) -> Self {
+        self.mode_normalization = normalization;
+        self
+    }
+
+    /// Changes the ownership of each extracted file and directory as it is extracted, so that a
+    /// corpus populated as root can be handed to an unprivileged account without a separate
+    /// recursive `chown`.
+    ///
+    /// This needs the privileges described on [`Ownership`]; without them, every version will
+    /// fail to populate. The bucket directories above each version aren't changed.
+    #[cfg(unix)]
+    pub fn with_ownership(mut self, ownership: Ownership) -> Self {
+        self.ownership = Some(ownership);
+        self
+    }
+
+    /// Sets what happens when a tarball contains duplicate entries for the same path.
+    ///
+    /// By default, later entries silently replace earlier ones.
+    pub fn with_overwrite_policy(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite_policy = policy;
+        self
+    }
+
+    /// Extracts each crate version into a fixed directory under `.extracting` in the corpus,
+    /// journalling each file as it is completed, rather than into a fresh temporary directory.
+    ///
+    /// If populating a version is interrupted, the next attempt skips the files that the journal
+    /// records as complete, provided they still have the expected size, instead of starting over.
+    /// `on_file` isn't called for skipped files. This is only worthwhile for very large crates.
+    pub fn with_resumable_extraction(mut self) -> Self {
+        self.resumable_extraction = true;
+        self
+    }
+
+    /// Sets the `.crate` file size, in bytes, above which [`Corpus::populate_sized`] streams the
+    /// download to a temporary file and extracts it from there, rather than buffering it in
+    /// memory.
+    pub fn with_streaming_threshold(mut self, bytes: u64) -> Self {
+        self.streaming_threshold = bytes;
+        self
+    }
+
+    /// Strips `count` leading directories from the path of each file extracted from a crate
+    /// version, beneath the `{name}-{version}` directory that every `.crate` file has, like
+    /// `tar --strip-components`.
+    ///
+    /// This is for crates that packaged their sources inside an extra directory. As with `tar`,
+    /// entries with no more than `count` directories beneath the crate root are skipped, which
+    /// includes the top-level manifest. Defaults to 0, which extracts everything as it is.
+    pub fn with_strip_components(mut self, count: usize) -> Self {
+        self.strip_components = count;
+        self
+    }
+
+    /// Pauses [`Corpus::populate_many`] while too many downloads are failing: see
+    /// [`CircuitBreaker`].
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(breaker);
+        self
+    }
+
+    /// Writes the result of each crate version to the given stream as soon as
+    /// [`Corpus::populate_many`] finishes it.
+    pub fn with_result_stream(mut self, stream: ResultStream) -> Self {
+        self.result_stream = Some(stream);
+        self
+    }
+
+    /// Writes how long each crate version took to download and extract to the given log.
+    ///
+    /// Versions that were already populated aren't logged.
+    pub fn with_timing_log(mut self, log: TimingLog) -> Self {
+        self.timing_log = Some(log);
+        self
+    }
+
+    /// Commits each newly populated version into a git repository in its crate's directory: see
+    /// [`VersionHistory`].
+    ///
+    /// Nothing is committed for a [`Layout::ArchiveOnly`] vault, since versions aren't extracted.
+    pub fn with_history(mut self) -> Self {
+        self.history = Some(VersionHistory::new());
+        self
+    }
+
+    /// Checks that each crate version is in the given index before downloading it, failing with
+    /// [`Error::NotInIndex`] rather than waiting for a 404 from the CDN.
+    ///
+    /// Versions already in the corpus aren't checked.
+    pub fn with_index(mut self, index: Index) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Sets how crate versions are stored in the corpus: see [`Layout`].
+    ///
+    /// A corpus should always be opened with the layout it was populated with. With
+    /// [`Layout::ArchiveOnly`], tarballs are stored exactly as they were downloaded, so manifest
+    /// transforms, post-extract hooks, and mode normalization don't apply.
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.vault = self.vault.with_layout(layout);
+        self
+    }
+
+    /// Maintains the vault's contents index as versions are populated and discarded: see
+    /// [`Vault::with_contents_index`].
+    pub fn with_contents_index(mut self) -> Self {
+        self.vault = self.vault.with_contents_index();
+        self
+    }
+
+    /// Checks that each crate version populated by [`Corpus::populate_many`] has a manifest
+    /// naming the expected crate and version.
+    ///
+    /// See [`Vault::verify_manifest_matches`].
+    pub fn with_manifest_verification(mut self) -> Self {
+        self.verify_manifests = true;
+        self
+    }
+
+    /// Uses the given cache for downloaded tarballs.
+    ///
+    /// The cache is only used for crate versions populated with a checksum, such as with
+    /// [`Corpus::populate_verified`]: see [`Downloader::with_tarball_cache`].
+    pub fn with_tarball_cache(mut self, cache: TarballCache) -> Self {
+        self.downloader = self.downloader.with_tarball_cache(cache);
+        self
+    }
+
+    /// Caps the aggregate throughput of downloads made by this corpus: see
+    /// [`Downloader::with_bandwidth_limit`].
+    pub fn with_bandwidth_limit(mut self, limiter: BandwidthLimiter) -> Self {
+        self.downloader = self.downloader.with_bandwidth_limit(limiter);
+        self
+    }
+
+    /// Downloads from the given base URLs in order of preference, falling back to the next one
+    /// when a mirror fails: see [`Downloader::with_mirrors`].
+    pub fn with_mirrors(mut self, mirrors: Vec<String>) -> Self {
+        self.downloader = self.downloader.with_mirrors(mirrors);
+        self
+    }
+
+    pub fn tarball_cache(&self) -> Option<&TarballCache> {
+        self.downloader.tarball_cache()
+    }
+
+    pub fn vault(&self) -> &Vault {
+        &self.vault
+    }
+
     pub fn path(&self, krate: &str, num: &str) -> Result<PathBuf, Error> {
-        Ok(self.vault.crate_version_path(krate, num)?)
+        Ok(self
+            .vault
+            .canonical_version_path(&CrateName::new(krate)?, &CrateVersionNum::new(num)?)?)
     }
 
+    /// Takes an exclusive advisory lock on the corpus, which is held until the returned guard is
+    /// dropped.
+    ///
+    /// This only excludes other callers of this method, such as a second `librarian populate` run
+    /// against the same corpus: reading the corpus doesn't require the lock. If another process
+    /// holds it, [`Error::CorpusLocked`] is returned immediately, including the process ID that it
+    /// recorded in the lock file.
     #[tracing::instrument(err)]
-    pub fn populate(&self, name: &str, num: &str) -> Result<PathBuf, Error> {
+    pub fn lock(&self) -> Result<CorpusLock, Error> {
+        let path = self.vault.join(LOCK_FILE);
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        match file.try_lock_exclusive() {
+            Ok(()) => {}
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                let mut pid = String::new();
+                file.read_to_string(&mut pid)?;
+                return Err(Error::CorpusLocked {
+                    path,
+                    pid: pid.trim().parse().ok(),
+                });
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+
+        Ok(CorpusLock { _file: file })
+    }
+
+    /// Returns the index commit recorded by the last [`Corpus::record_synced`], if any.
+    #[tracing::instrument(err)]
+    pub fn last_synced(&self) -> Result<Option<String>, Error> {
+        match std::fs::read_to_string(self.vault.join(SYNCED_FILE)) {
+            Ok(oid) => Ok(Some(oid.trim().to_string())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Records that the corpus contains every version in the index as of the given commit, so
+    /// that the next sync only needs to populate versions changed since then.
+    #[tracing::instrument(err)]
+    pub fn record_synced(&self, oid: &str) -> Result<(), Error> {
+        std::fs::write(self.vault.join(SYNCED_FILE), format!("{oid}\n"))?;
+        Ok(())
+    }
+
+    /// Removes every crate version from the corpus, leaving the root directory (and any hidden
+    /// files within it, such as `.librarianignore`) in place.
+    ///
+    /// As a safeguard against pointing this at the wrong directory, nothing is removed unless
+    /// every other entry in the root is a single character bucket directory, as created by
+    /// [`Corpus::populate`]. The object directory of a [`Layout::ContentAddressed`] corpus is
+    /// removed along with the buckets.
+    #[tracing::instrument(err)]
+    pub fn clear(&self) -> Result<(), Error> {
+        let mut buckets = Vec::new();
+        for entry in std::fs::read_dir(&*self.vault)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if name.as_encoded_bytes().starts_with(b".") {
+                continue;
+            }
+
+            if entry.file_type()?.is_dir() && name.to_str().is_some_and(|s| s.chars().count() == 1)
+            {
+                buckets.push(entry.path());
+            } else {
+                return Err(Error::NotACorpus(entry.path()));
+            }
+        }
+
+        for bucket in buckets {
+            std::fs::remove_dir_all(bucket)?;
+        }
+        match std::fs::remove_dir_all(self.vault.objects_path()) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        self.vault.reindex()?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(err)]
+    pub fn populate(&self, name: &CrateName, num: &CrateVersionNum) -> Result<PathBuf, Error> {
+        self.populate_inner(name, num, None, |_path, _data| {})
+    }
+
+    /// Populates a crate version, verifying the downloaded tarball against the SHA-256 checksum
+    /// from the index.
+    #[tracing::instrument(err)]
+    pub fn populate_verified(
+        &self,
+        name: &CrateName,
+        num: &CrateVersionNum,
+        cksum: &str,
+    ) -> Result<PathBuf, Error> {
+        self.populate_sized(name, num, cksum, None)
+    }
+
+    /// Like [`Corpus::populate_verified`], but given the size of the `.crate` file from the index,
+    /// if it's known.
+    ///
+    /// Tarballs larger than the streaming threshold are streamed to a temporary file and
+    /// extracted from there, rather than being buffered in memory. So is every tarball when a
+    /// tarball cache is set, since it's written to disk anyway.
+    #[tracing::instrument(err)]
+    pub fn populate_sized(
+        &self,
+        name: &CrateName,
+        num: &CrateVersionNum,
+        cksum: &str,
+        size: Option<u64>,
+    ) -> Result<PathBuf, Error> {
+        self.populate_sized_str(name, num, cksum, size)
+    }
+
+    /// Like [`Corpus::populate_sized`], for names and versions that came from the index rather
+    /// than from a caller.
+    fn populate_sized_str(
+        &self,
+        name: &str,
+        num: &str,
+        cksum: &str,
+        size: Option<u64>,
+    ) -> Result<PathBuf, Error> {
+        self.populate_with(
+            name,
+            num,
+            |_path, _data| {},
+            || self.download_sized(name, num, cksum, size),
+        )
+    }
+
+    /// Downloads and verifies a `.crate` file, streaming it to a temporary file if it's larger
+    /// than the streaming threshold or there's a tarball cache, as described for
+    /// [`Corpus::populate_sized`].
+    fn download_sized(
+        &self,
+        name: &str,
+        num: &str,
+        cksum: &str,
+        size: Option<u64>,
+    ) -> Result<Tarball, Error> {
+        Ok(
+            if self.tarball_cache().is_none()
+                && size.is_none_or(|size| size <= self.streaming_threshold)
+            {
+                self.downloader.download(name, num, Some(cksum))?.into()
+            } else {
+                Tarball::File(
+                    self.downloader
+                        .download_to_file(name, num, cksum, &self.vault)?,
+                )
+            },
+        )
+    }
+
+    /// Populates each of the given `(name, num, cksum, size)` crate versions in parallel,
+    /// verifying them against their checksums as with [`Corpus::populate_sized`].
+    ///
+    /// Versions are dispatched in the order given. Whatever was written for a version that fails
+    /// is removed, and the failures are returned alongside the crate name and version. With
+    /// [`OnError::FailFast`], no more versions are started after the first failure, although
+    /// versions already in progress are allowed to finish.
+    #[tracing::instrument(skip(self, versions, progress))]
+    pub fn populate_many<I>(
+        &self,
+        versions: I,
+        on_error: OnError,
+        progress: &dyn Progress,
+    ) -> Vec<(String, String, Error)>
+    where
+        I: IntoIterator<Item = (String, String, String, Option<u64>)>,
+    {
+        // A size hint is only a lower bound, and the queue depth gauge would go negative as
+        // versions beyond it were dequeued, so count them properly.
+        let versions: Vec<_> = versions.into_iter().collect();
+        progress.start("Downloading crates", Some(versions.len() as u64));
+        telemetry::queue_depth(versions.len());
+
+        // Bridging from a sequential iterator means versions are dispatched in order, rather
+        // than rayon splitting the list into chunks up front.
+        let stop = AtomicBool::new(false);
+        let failures = versions
+            .into_iter()
+            .par_bridge()
+            .filter_map(|(name, num, cksum, size)| {
+                telemetry::dequeued();
+                if stop.load(Ordering::Relaxed) {
+                    return None;
+                }
+
+                let existing = (self.result_stream.is_some() || self.circuit_breaker.is_some())
+                    && matches!(self.populated_path(&name, &num), Ok(Some(_)));
+                // Versions that are already populated aren't downloaded, so they neither wait on
+                // the breaker nor say anything about the health of the CDN.
+                let permit = match &self.circuit_breaker {
+                    Some(breaker) if !existing => Some((breaker, breaker.wait())),
+                    _ => None,
+                };
+                let started = Instant::now();
+                let result = self.populate_sized_str(&name, &num, &cksum, size);
+                if let Some((breaker, permit)) = permit {
+                    // Only download failures say anything about the health of the CDN.
+                    breaker.record(permit, !matches!(result, Err(Error::Download(_))));
+                }
+                let result = result.and_then(|_path| self.check_manifest(&name, &num));
+                progress.advance(1);
+                if let Some(stream) = &self.result_stream {
+                    stream.record(&name, &num, existing, size, started.elapsed(), &result);
+                }
+
+                let e = result.err()?;
+                telemetry::version_failed(&e);
+                if on_error == OnError::FailFast {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                self.discard(&name, &num);
+                Some((name, num, e))
+            })
+            .collect();
+        progress.finish();
+
+        failures
+    }
+
+    /// Removes whatever was written for a crate version that failed to populate.
+    ///
+    /// The download happens before the version directory is created, so there may be nothing to
+    /// clean up.
+    pub fn discard(&self, name: &str, num: &str) {
+        let result = self
+            .populated_path(name, num)
+            .and_then(|path| match path {
+                Some(path) => Ok(self.vault.storage().remove(&path)?),
+                None => Ok(()),
+            })
+            .and_then(|()| Ok(self.vault.forget(name, num)?));
+        if let Err(e) = result {
+            tracing::warn!(?name, ?num, ?e, "error cleaning up failed version");
+        }
+    }
+
+    /// Repacks an extracted crate version into a `.crate` file at [`Vault::archive_path`], then
+    /// removes the extracted files, reclaiming the space they used.
+    ///
+    /// The archive contains every file in the version directory, under a `{name}-{num}/` prefix as
+    /// cargo would package it, and can be read by opening the corpus with
+    /// [`Layout::ArchiveOnly`]. It is **not** byte for byte identical to the `.crate` file that was
+    /// downloaded, so its checksum won't match the one in the index.
+    ///
+    /// Versions stored in the [`Layout::ContentAddressed`] layout can't be compacted, since their
+    /// contents may be shared, and fail with [`Error::NotADirectory`]. Versions that have already
+    /// been compacted are left as they are.
+    #[tracing::instrument(err)]
+    pub fn compact(&self, name: &str, num: &str) -> Result<PathBuf, Error> {
+        let path = self.path(name, num)?;
+        if !std::fs::symlink_metadata(&path)?.is_dir() {
+            return Err(Error::NotADirectory(path));
+        }
+        let archive = self.vault.archive_path(name, num)?;
+        if archive.is_file() {
+            return Ok(archive);
+        }
+
+        let temp = NamedTempFile::new_in(&self.vault)?;
+        let mut builder = Builder::new(GzEncoder::new(temp.as_file(), Compression::default()));
+        builder.follow_symlinks(false);
+        let prefix = PathBuf::from(format!("{name}-{num}"));
+        for entry in walkdir::WalkDir::new(&path)
+            .min_depth(1)
+            .sort_by_file_name()
+        {
+            let entry = entry.map_err(std::io::Error::from)?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(&path).unwrap_or(entry.path());
+            builder.append_path_with_name(entry.path(), prefix.join(relative))?;
+        }
+        builder.into_inner()?.finish()?;
+
+        // The archive is stored inside the version directory, so the extracted files are moved
+        // aside to make room for it, and moved back if it can't be stored. They're only removed
+        // once the archive is safely in place.
+        let old = tempdir_in(&self.vault)?;
+        let extracted = old.path().join("version");
+        std::fs::rename(&path, &extracted)?;
+        let stored = std::fs::create_dir(&path)
+            .map_err(Error::from)
+            .and_then(|()| temp.persist(&archive).map_err(|e| Error::from(e.error)));
+        if let Err(e) = stored {
+            let restored = std::fs::remove_dir(&path)
+                .or_else(|e| match e.kind() {
+                    ErrorKind::NotFound => Ok(()),
+                    _ => Err(e),
+                })
+                .and_then(|()| std::fs::rename(&extracted, &path));
+            if let Err(restore_error) = restored {
+                // Leave the extracted files where they are, rather than letting the temporary
+                // directory take the only copy of the version with it.
+                tracing::error!(
+                    ?extracted,
+                    ?restore_error,
+                    "unable to restore compacted version"
+                );
+                std::mem::forget(old);
+            }
+            return Err(e);
+        }
+        self.vault.record(name, num, &archive)?;
+
+        Ok(archive)
+    }
+
+    pub(crate) fn check_manifest(&self, name: &str, num: &str) -> Result<(), Error> {
+        if !self.verify_manifests {
+            return Ok(());
+        }
+
+        if self.vault.layout() == Layout::ArchiveOnly {
+            let data = self
+                .vault
+                .storage()
+                .read(&self.vault.archive_path(name, num)?)?;
+            let manifest = Self::read_manifest(name, num, data.as_slice())?;
+            if manifest.crate_name() != name || manifest.crate_version() != num {
+                return Err(vault::Error::ManifestMismatch {
+                    expected: format!("{name}@{num}"),
+                    found: format!("{}@{}", manifest.crate_name(), manifest.crate_version()),
+                }
+                .into());
+            }
+        } else {
+            self.vault.verify_manifest_matches(name, num)?;
+        }
+        Ok(())
+    }
+
+    /// Downloads a crate version and checks it against the SHA-256 checksum from the index,
+    /// without extracting it or writing anything to disk.
+    #[tracing::instrument(err)]
+    pub fn verify_download(&self, name: &str, num: &str, cksum: &str) -> Result<(), Error> {
+        self.downloader.verify(name, num, cksum)?;
+        Ok(())
+    }
+
+    /// Populates a crate version, invoking `on_file` with the path (relative to the crate root)
+    /// and contents of each regular file as it is extracted.
+    ///
+    /// Contents are streamed, so `on_file` is called once for each chunk of a file, in order. An
+    /// empty file is passed as a single empty chunk.
+    ///
+    /// `on_file` is called before the crate version is moved into place in the corpus. If the
+    /// version has already been populated, it is not called at all.
+    #[tracing::instrument(skip(on_file), err)]
+    pub fn populate_streaming<F>(&self, name: &str, num: &str, on_file: F) -> Result<PathBuf, Error>
+    where
+        F: FnMut(&Path, &[u8]),
+    {
+        self.populate_inner(name, num, None, on_file)
+    }
+
+    fn populate_inner<F>(
+        &self,
+        name: &str,
+        num: &str,
+        cksum: Option<&str>,
+        on_file: F,
+    ) -> Result<PathBuf, Error>
+    where
+        F: FnMut(&Path, &[u8]),
+    {
+        self.populate_with(name, num, on_file, || {
+            Ok(self.downloader.download(name, num, cksum)?)
+        })
+    }
+
+    /// Downloads a crate version and returns its top-level manifest, without extracting anything
+    /// else or writing to the corpus.
+    ///
+    /// The tarball is only read as far as the manifest.
+    #[tracing::instrument(err)]
+    pub fn populate_manifest_only(&self, name: &str, num: &str) -> Result<Manifest, Error> {
+        self.check_in_index(name, num)?;
+        let data = self.downloader.download(name, num, None)?;
+        Self::read_manifest(name, num, data.as_slice())
+    }
+
+    /// Returns [`Error::NotInIndex`] if an index was given with [`Corpus::with_index`], and the
+    /// crate version isn't in it.
+    fn check_in_index(&self, name: &str, num: &str) -> Result<(), Error> {
+        let Some(index) = &self.index else {
+            return Ok(());
+        };
+
+        match index.version_summaries(name) {
+            Ok(versions) if versions.iter().any(|version| version.num == num) => Ok(()),
+            Ok(_) | Err(index::Error::NotFound(_)) => Err(Error::NotInIndex {
+                name: name.to_string(),
+                num: num.to_string(),
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Downloads a crate version and extracts only its top-level manifest and README into the
+    /// corpus, for building a lightweight corpus of crate metadata and documentation.
+    ///
+    /// The README is whatever `package.readme` points to, defaulting to `README.md`; it's skipped
+    /// if the crate doesn't contain it. The `.crate` file is downloaded and verified exactly as
+    /// [`Corpus::populate_sized`] would, and the result is stored according to the vault's
+    /// [`Layout`], except that [`Layout::ArchiveOnly`] stores the whole archive, since that's all
+    /// it stores. No other [`Corpus`] options are applied.
+    #[tracing::instrument(err)]
+    pub fn populate_docs_only(
+        &self,
+        name: &str,
+        num: &str,
+        cksum: &str,
+        size: Option<u64>,
+    ) -> Result<PathBuf, Error> {
+        if self.vault.layout() == Layout::ArchiveOnly {
+            return self.populate_sized_str(name, num, cksum, size);
+        }
+        if let Some(path) = self.populated_path(name, num)? {
+            return Ok(path);
+        }
+
+        self.check_in_index(name, num)?;
+        let tarball = self.download_sized(name, num, cksum, size)?;
+        self.extract_docs(name, num, &tarball)
+    }
+
+    fn extract_docs(&self, name: &str, num: &str, tarball: &Tarball) -> Result<PathBuf, Error> {
+        // The manifest isn't necessarily the first entry, so the tarball is read twice: once to
+        // find the README, then again to extract.
+        let manifest = Self::read_manifest(name, num, tarball.reader()?)?;
+        let mut wanted: Vec<PathBuf> = vec!["Cargo.toml".into(), "cargo.toml".into()];
+        if let Some(readme) = manifest.readme() {
+            wanted.push(
+                Path::new(readme)
+                    .components()
+                    .filter(|component| *component != Component::CurDir)
+                    .collect(),
+            );
+        }
+
         let temp = tempdir_in(&self.vault)?;
+        let extracted = temp.path().join(format!("{name}-{num}"));
+        let mut archive = Archive::new(GzDecoder::new(tarball.reader()?));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let entry_path: PathBuf = entry
+                .path()?
+                .components()
+                .skip(1)
+                .filter(|component| *component != Component::CurDir)
+                .collect();
+            if wanted.contains(&entry_path) {
+                let entry_path = entry.path()?.into_owned();
+                Self::extract_entry(
+                    &mut entry,
+                    &entry_path,
+                    temp.path(),
+                    &extracted,
+                    self.mode_normalization,
+                    &mut |_path, _data| {},
+                )?;
+            }
+        }
 
         let path = self.path(name, num)?;
-        let path = match std::fs::metadata(&path) {
+        std::fs::create_dir_all(path.parent().expect("version path parent"))?;
+        match self.vault.layout() {
+            Layout::ByVersion => std::fs::rename(extracted, &path)?,
+            Layout::ContentAddressed => self.store_object(&extracted, &tarball.sha256()?, &path)?,
+            Layout::ArchiveOnly => unreachable!("archives are stored without being extracted"),
+        }
+        self.vault.record(name, num, &path)?;
+
+        Ok(path)
+    }
+
+    /// Copies a single file, given relative to the crate root, out of a downloaded `.crate` file
+    /// into `out`, returning false if the crate doesn't contain a regular file at that path.
+    ///
+    /// The tarball is only read as far as the file, and nothing is written to disk.
+    pub fn extract_file<W: Write>(data: &[u8], path: &Path, mut out: W) -> Result<bool, Error> {
+        let path: PathBuf = path
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+
+        let mut archive = Archive::new(GzDecoder::new(data));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let entry_path: PathBuf = entry
+                .path()?
+                .components()
+                .skip(1)
+                .filter(|component| *component != Component::CurDir)
+                .collect();
+            if entry_path == path {
+                std::io::copy(&mut entry, &mut out)?;
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn read_manifest<R: BufRead>(name: &str, num: &str, reader: R) -> Result<Manifest, Error> {
+        let mut archive = Archive::new(GzDecoder::new(reader));
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?;
+            let mut components = path.components();
+            let is_manifest = matches!(
+                (components.next(), components.next(), components.next()),
+                (Some(Component::Normal(_)), Some(Component::Normal(file)), None)
+                    if file == "Cargo.toml" || file == "cargo.toml"
+            );
+            if is_manifest && entry.header().entry_type().is_file() {
+                return Ok(Manifest::parse_reader(entry)?);
+            }
+        }
+
+        Err(Error::MissingManifest {
+            name: name.to_string(),
+            num: num.to_string(),
+        })
+    }
+
+    /// Returns the path to a crate version, populating it first if it isn't already in the
+    /// corpus.
+    ///
+    /// Concurrent calls for the same version, whether from this process or another, share a
+    /// single download: each waits on a lock file until the version has been completely
+    /// extracted, then returns the same path.
+    #[tracing::instrument(err)]
+    pub fn get_or_populate(&self, name: &str, num: &str) -> Result<PathBuf, Error> {
+        self.get_or_populate_with(name, num, || {
+            Ok(self.downloader.download(name, num, None)?)
+        })
+    }
+
+    fn get_or_populate_with<T, D>(
+        &self,
+        name: &str,
+        num: &str,
+        download: D,
+    ) -> Result<PathBuf, Error>
+    where
+        T: Into<Tarball>,
+        D: FnOnce() -> Result<T, Error>,
+    {
+        if let Some(path) = self.populated_path(name, num)? {
+            return Ok(path);
+        }
+
+        let lock_path = self
+            .vault
+            .join(".locks")
+            .join(format!("{}-{num}.lock", name.to_ascii_lowercase()));
+        std::fs::create_dir_all(lock_path.parent().expect("lock file parent"))?;
+        let lock = File::create(&lock_path)?;
+        lock.lock_exclusive()?;
+
+        // Another caller may have populated the version while we were waiting for the lock.
+        if let Some(path) = self.populated_path(name, num)? {
+            return Ok(path);
+        }
+
+        let path = self.populate_with(name, num, |_path, _data| {}, download)?;
+
+        // Anyone still waiting on the lock will find the version complete once they have it, as
+        // will anyone who arrives later, so nobody needs the lock file any more. It's left behind
+        // on failure, since whoever gets the lock next will retry the version.
+        std::fs::remove_file(&lock_path)?;
+
+        Ok(path)
+    }
+
+    /// Returns the path to a crate version if it has already been populated.
+    ///
+    /// A version directory that's empty, or that still has a resumable extraction journal, was
+    /// left behind by an interrupted attempt, so the version doesn't count as populated.
+    pub fn populated_path(&self, name: &str, num: &str) -> Result<Option<PathBuf>, Error> {
+        let path = self.path(name, num)?;
+        match std::fs::metadata(&path) {
             Ok(metadata) if metadata.is_dir() => {
-                return Ok(path);
+                if std::fs::read_dir(&path)?.next().is_none()
+                    || self.extracting_path(name, num).exists()
+                {
+                    Ok(None)
+                } else {
+                    Ok(Some(path))
+                }
             }
-            Ok(_metadata) => {
-                return Err(Error::NotADirectory(path));
+            Ok(_metadata) => Err(Error::NotADirectory(path)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the directory that a crate version is extracted into with
+    /// [`Corpus::with_resumable_extraction`].
+    fn extracting_path(&self, name: &str, num: &str) -> PathBuf {
+        self.vault
+            .join(EXTRACTING_DIR)
+            .join(format!("{name}-{num}"))
+    }
+
+    /// Populates a crate version from a `.crate` file provided by `download`, which is only
+    /// called if the version hasn't already been populated.
+    pub(crate) fn populate_with<F, T, D>(
+        &self,
+        name: &str,
+        num: &str,
+        on_file: F,
+        download: D,
+    ) -> Result<PathBuf, Error>
+    where
+        F: FnMut(&Path, &[u8]),
+        T: Into<Tarball>,
+        D: FnOnce() -> Result<T, Error>,
+    {
+        let mut timings = Timings::default();
+        let mut result = self.populate_timed(name, num, on_file, download, &mut timings);
+        if let (Ok(_), Some(bytes)) = (&result, timings.bytes) {
+            telemetry::version_populated(bytes);
+        }
+        if result.is_ok() && timings.download.is_some() {
+            let stored = match self.vault.layout() {
+                Layout::ArchiveOnly => self.vault.archive_path(name, num).map_err(Error::from),
+                Layout::ByVersion | Layout::ContentAddressed => self.path(name, num),
+            };
+            if let Err(e) = stored.and_then(|stored| Ok(self.vault.record(name, num, &stored)?)) {
+                result = Err(e);
+            }
+        }
+
+        // Versions that were already populated aren't interesting.
+        if let Some(log) = &self.timing_log {
+            if timings.download.is_some() || result.is_err() {
+                log.record(name, num, &timings, &result);
             }
-            Err(e) if e.kind() == ErrorKind::NotFound => {
+        }
+        result
+    }
+
+    fn populate_timed<F, T, D>(
+        &self,
+        name: &str,
+        num: &str,
+        mut on_file: F,
+        download: D,
+        timings: &mut Timings,
+    ) -> Result<PathBuf, Error>
+    where
+        F: FnMut(&Path, &[u8]),
+        T: Into<Tarball>,
+        D: FnOnce() -> Result<T, Error>,
+    {
+        let temp = tempdir_in(&self.vault)?;
+
+        if let Some(path) = self.populated_path(name, num)? {
+            return Ok(path);
+        }
+        self.check_in_index(name, num)?;
+        let path = self.path(name, num)?;
+        let path = match self.vault.layout() {
+            // The archive is persisted into the version directory.
+            Layout::ArchiveOnly => {
                 std::fs::create_dir_all(&path)?;
                 std::fs::canonicalize(path)?
             }
-            Err(e) => {
-                return Err(e.into());
+            // The version path is only created once the version has been extracted in full, by
+            // moving or linking it into place, so that an interrupted attempt can't leave behind
+            // something that looks populated.
+            Layout::ByVersion | Layout::ContentAddressed => {
+                let parent = path.parent().expect("version path parent");
+                std::fs::create_dir_all(parent)?;
+                std::fs::canonicalize(parent)?.join(path.file_name().expect("version path name"))
             }
         };
 
-        let resp = self
-            .client
-            .get(format!(
-                "https://static.crates.io/crates/{name}/{name}-{num}.crate"
-            ))
-            .send()?;
+        let started = Instant::now();
+        let tarball = download();
+        timings.download = Some(started.elapsed());
+        let tarball = tarball?.into();
+        timings.bytes = Some(tarball.len()?);
+        timings.extract_started = Some(Instant::now());
+
+        if self.vault.layout() == Layout::ArchiveOnly {
+            tarball.persist(&self.vault.archive_path(name, num)?, temp.path())?;
+            return Ok(path);
+        }
 
-        let mut zr = GzDecoder::new(resp);
+        let (dest, mut journal) = if self.resumable_extraction {
+            let dest = self.extracting_path(name, num);
+            std::fs::create_dir_all(&dest)?;
+            let journal = ExtractionJournal::open(&dest.join(JOURNAL_FILE))?;
+            (dest, Some(journal))
+        } else {
+            (temp.path().to_path_buf(), None)
+        };
+        let extracted = dest.join(format!("{name}-{num}"));
+        let mut zr = GzDecoder::new(tarball.reader()?);
         let mut archive = Archive::new(&mut zr);
         archive.set_overwrite(true);
-        archive.unpack(&temp)?;
+        let mut stats = ExtractionStats::default();
+        let mut seen = HashSet::new();
+        #[cfg(unix)]
+        let mut owned = HashSet::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_path = match self.strip_components {
+                0 => entry.path()?.into_owned(),
+                count => match strip_components(&entry.path()?, count) {
+                    Some(path) => path,
+                    None => continue,
+                },
+            };
+            if !entry.header().entry_type().is_dir() {
+                let entry_path: PathBuf = entry_path
+                    .components()
+                    .filter(|component| *component != Component::CurDir)
+                    .collect();
+                if seen.contains(&entry_path) {
+                    match self.overwrite_policy {
+                        OverwritePolicy::Allow => {}
+                        OverwritePolicy::Error => return Err(Error::DuplicateEntry(entry_path)),
+                        OverwritePolicy::Skip => {
+                            tracing::warn!(?name, ?num, ?entry_path, "skipping duplicate entry");
+                            continue;
+                        }
+                    }
+                }
+                seen.insert(entry_path);
+            }
+            stats.record(&entry)?;
+
+            let is_file = entry.header().entry_type().is_file();
+            if let Some(journal) = &journal {
+                if is_file && journal.is_complete(&entry_path, &dest, entry.size()) {
+                    continue;
+                }
+            }
+            Self::extract_entry(
+                &mut entry,
+                &entry_path,
+                &dest,
+                &extracted,
+                self.mode_normalization,
+                &mut on_file,
+            )?;
+            #[cfg(unix)]
+            if let Some(ownership) = self.ownership {
+                ownership.apply(&dest, &entry_path, &mut owned)?;
+            }
+            if let Some(journal) = &mut journal {
+                if is_file {
+                    journal.record(&entry_path)?;
+                }
+            }
+        }
+        tracing::debug!(
+            ?name,
+            ?num,
+            files = stats.files,
+            dirs = stats.dirs.len(),
+            symlinks = stats.symlinks,
+            max_depth = stats.max_depth,
+            total_bytes = stats.total_bytes,
+            "extracted crate version"
+        );
+
+        if let Some(transform) = self.manifest_transform {
+            Self::transform_manifest(&extracted, transform)?;
+        }
 
-        std::fs::rename(temp.path().join(format!("{name}-{num}")), &path)?;
+        if let Some(hook) = self.post_extract {
+            if let Err(e) = hook(&extracted) {
+                return Err(Error::PostExtract(e));
+            }
+        }
+
+        // An interrupted attempt may have left an empty directory, or a version that was moved
+        // into place before its journal was removed, where the version is about to go.
+        remove_incomplete(&path)?;
+        match self.vault.layout() {
+            Layout::ByVersion => std::fs::rename(extracted, &path)?,
+            Layout::ContentAddressed => self.store_object(&extracted, &tarball.sha256()?, &path)?,
+            Layout::ArchiveOnly => unreachable!("archives are stored without being extracted"),
+        }
+        if let Some(history) = &self.history {
+            history.record(path.parent().expect("version path parent"), num, &path)?;
+        }
+        // The journal has to go whether or not extraction was resumable this time, since its
+        // presence marks the version as incomplete.
+        match std::fs::remove_dir_all(self.extracting_path(name, num)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
         Ok(path)
     }
+
+    /// Moves an extracted crate version into the object directory for its checksum, unless it's
+    /// already there, then links the version path to it.
+    fn store_object(&self, extracted: &Path, cksum: &str, path: &Path) -> Result<(), Error> {
+        let object = self.vault.object_path(cksum);
+        if !object.is_dir() {
+            std::fs::create_dir_all(self.vault.objects_path())?;
+            if let Err(e) = std::fs::rename(extracted, &object) {
+                // Another version with the same content may have beaten us to it.
+                if !object.is_dir() {
+                    return Err(e.into());
+                }
+            }
+        }
+
+        // The link is relative, so that the corpus can be moved without breaking it.
+        let depth = path
+            .strip_prefix(&*self.vault)
+            .map_err(|_e| Error::UnsafePath(path.to_path_buf()))?
+            .components()
+            .count();
+        let mut target: PathBuf = std::iter::repeat_n("..", depth - 1).collect();
+        target.push(object.strip_prefix(&*self.vault).unwrap_or(&object));
+        std::os::unix::fs::symlink(target, path)?;
+
+        Ok(())
+    }
+
+    /// Extracts an entry to `entry_path` within `dest`, which is normally the entry's own path,
+    /// but may have had components stripped from it.
+    fn extract_entry<R, F>(
+        entry: &mut Entry<'_, R>,
+        entry_path: &Path,
+        dest: &Path,
+        root: &Path,
+        normalization: ModeNormalization,
+        on_file: &mut F,
+    ) -> Result<(), Error>
+    where
+        R: Read,
+        F: FnMut(&Path, &[u8]),
+    {
+        let entry_type = entry.header().entry_type();
+        let is_safe = entry_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !entry_type.is_file() {
+            // tar knows how to safely handle everything else, provided it's going where the
+            // archive says.
+            let unpacked = if entry.path()? == entry_path {
+                entry.unpack_in(dest)?
+            } else if is_safe {
+                Self::unpack_stripped(entry, entry_path, dest)?
+            } else {
+                return Err(Error::UnsafePath(entry_path.to_path_buf()));
+            };
+            if unpacked && entry_type.is_dir() && normalization != ModeNormalization::Preserve {
+                let path = dest.join(entry_path);
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+            }
+            return Ok(());
+        }
+
+        if !is_safe {
+            return Err(Error::UnsafePath(entry_path.to_path_buf()));
+        }
+
+        let path = dest.join(entry_path);
+        let relative = path.strip_prefix(root).unwrap_or(entry_path);
+        create_parents(dest, entry_path)?;
+        if std::fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_symlink()) {
+            return Err(Error::UnsafePath(entry_path.to_path_buf()));
+        }
+        let mut file = File::create(&path)?;
+
+        // Files are streamed a chunk at a time, so that a large file in a crate doesn't have to
+        // fit in memory.
+        with_extract_buffer(|buf| -> Result<(), Error> {
+            let mut empty = true;
+            loop {
+                let len = match entry.read(buf) {
+                    Ok(0) => break,
+                    Ok(len) => len,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                on_file(relative, &buf[..len]);
+                file.write_all(&buf[..len])?;
+                empty = false;
+            }
+            if empty {
+                on_file(relative, &[]);
+            }
+            Ok(())
+        })?;
+
+        file.set_permissions(std::fs::Permissions::from_mode(
+            normalization.file_mode(entry.header().mode()?),
+        ))?;
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(entry.header().mtime()?))?;
+
+        Ok(())
+    }
+
+    /// Unpacks a non-file entry at `entry_path`, which has had components stripped from the
+    /// entry's own path, so tar can't be left to place it. Hard link targets are stripped by the
+    /// same number of components.
+    ///
+    /// Returns `false` for entry types that have no business in a crate, such as device nodes,
+    /// which are skipped.
+    fn unpack_stripped<R: Read>(
+        entry: &mut Entry<'_, R>,
+        entry_path: &Path,
+        dest: &Path,
+    ) -> Result<bool, Error> {
+        let unsafe_path = || Error::UnsafePath(entry_path.to_path_buf());
+        let entry_type = entry.header().entry_type();
+        let path = dest.join(entry_path);
+        create_parents(dest, entry_path)?;
+
+        if entry_type.is_dir() {
+            match std::fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => return Err(unsafe_path()),
+                Err(e) if e.kind() == ErrorKind::NotFound => std::fs::create_dir(&path)?,
+                Err(e) => return Err(e.into()),
+            }
+        } else if entry_type.is_hard_link() {
+            let stripped = entry
+                .path()?
+                .components()
+                .filter(|component| *component != Component::CurDir)
+                .count()
+                .saturating_sub(entry_path.components().count());
+            let target = entry
+                .link_name()?
+                .and_then(|target| strip_components(&target, stripped))
+                .filter(|target| {
+                    target
+                        .components()
+                        .all(|component| matches!(component, Component::Normal(_)))
+                })
+                .ok_or_else(unsafe_path)?;
+            create_parents(dest, &target)?;
+            std::fs::hard_link(dest.join(target), &path)?;
+        } else if entry_type.is_symlink() {
+            // Symlinks may point anywhere: nothing is ever written through one.
+            let target = entry.link_name()?.ok_or_else(unsafe_path)?;
+            std::os::unix::fs::symlink(target, &path)?;
+        } else {
+            tracing::warn!(
+                ?entry_path,
+                ?entry_type,
+                "skipping unsupported archive entry"
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    #[tracing::instrument(skip(transform), err)]
+    fn transform_manifest(root: &Path, transform: ManifestTransform) -> Result<(), Error> {
+        // As in Vault::version_manifest_path, Cargo.toml is preferred, but some old crates were
+        // published with a cargo.toml instead.
+        let Some(file_name) = ["Cargo.toml", "cargo.toml"]
+            .into_iter()
+            .find(|file_name| root.join(file_name).is_file())
+        else {
+            tracing::warn!(?root, "no top-level manifest to transform");
+            return Ok(());
+        };
+        let path = root.join(file_name);
+        let content = std::fs::read_to_string(&path)?;
+
+        let orig = root.join(format!("{file_name}.orig"));
+        if !orig.exists() {
+            std::fs::write(&orig, &content)?;
+        }
+
+        let mut table: toml::Table = content.parse()?;
+        transform(&mut table);
+        std::fs::write(&path, toml::to_string(&table)?)?;
+
+        Ok(())
+    }
+}
+
+/// A downloaded `.crate` file, either buffered in memory or streamed to a temporary file.
+#[derive(Debug)]
+pub(crate) enum Tarball {
+    Memory(Vec<u8>),
+    File(NamedTempFile),
+}
+
+impl Tarball {
+    fn len(&self) -> Result<u64, Error> {
+        Ok(match self {
+            Self::Memory(data) => data.len() as u64,
+            Self::File(file) => file.as_file().metadata()?.len(),
+        })
+    }
+
+    fn reader(&self) -> Result<Box<dyn BufRead + '_>, Error> {
+        Ok(match self {
+            Self::Memory(data) => Box::new(data.as_slice()),
+            Self::File(file) => Box::new(BufReader::new(file.reopen()?)),
+        })
+    }
+
+    fn sha256(&self) -> Result<String, Error> {
+        Ok(match self {
+            Self::Memory(data) => download::sha256(data),
+            Self::File(file) => {
+                download::sha256_reader(file.reopen()?, download::DEFAULT_VERIFY_BUFFER_SIZE)?
+            }
+        })
+    }
+
+    /// Moves the tarball to `path`, writing it to a temporary file in `temp_dir` first if it's in
+    /// memory, so that it appears there atomically.
+    fn persist(self, path: &Path, temp_dir: &Path) -> Result<(), Error> {
+        let file = match self {
+            Self::Memory(data) => {
+                let mut file = NamedTempFile::new_in(temp_dir)?;
+                file.write_all(&data)?;
+                file
+            }
+            Self::File(file) => file,
+        };
+        file.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+impl From<Vec<u8>> for Tarball {
+    fn from(data: Vec<u8>) -> Self {
+        Self::Memory(data)
+    }
+}
+
+/// An append-only list of the regular files that have been completely extracted from an archive,
+/// one path per line, as they appear in the archive.
+#[derive(Debug)]
+struct ExtractionJournal {
+    completed: HashSet<PathBuf>,
+    file: File,
+}
+
+impl ExtractionJournal {
+    fn open(path: &Path) -> Result<Self, Error> {
+        let completed = match std::fs::read_to_string(path) {
+            Ok(content) => content.lines().map(PathBuf::from).collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let file = File::options().create(true).append(true).open(path)?;
+
+        Ok(Self { completed, file })
+    }
+
+    /// Returns true if the entry at `entry_path` was completed by an earlier attempt, and the
+    /// file extracted within `dest` still has the expected size.
+    fn is_complete(&self, entry_path: &Path, dest: &Path, size: u64) -> bool {
+        self.completed.contains(entry_path)
+            && std::fs::symlink_metadata(dest.join(entry_path))
+                .is_ok_and(|metadata| metadata.is_file() && metadata.len() == size)
+    }
+
+    fn record(&mut self, entry_path: &Path) -> Result<(), Error> {
+        // A partially written line would be a path that doesn't match any entry, so it's harmless
+        // if we're interrupted here.
+        writeln!(self.file, "{}", entry_path.display())?;
+        self.completed.insert(entry_path.to_path_buf());
+        Ok(())
+    }
+}
+
+/// Removes whatever is at `path`, if anything, without following symlinks.
+fn remove_incomplete(path: &Path) -> Result<(), Error> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path)?,
+        Ok(_metadata) => std::fs::remove_file(path)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+/// Counts of what a crate version extracted to, which are logged to help spot pathological crates
+/// that will be slow to walk.
+#[derive(Debug, Default)]
+struct ExtractionStats {
+    files: u64,
+    /// Every directory seen, whether it had its own entry in the archive or not.
+    dirs: HashSet<PathBuf>,
+    symlinks: u64,
+    /// The deepest entry, counted in path components below the crate root.
+    max_depth: usize,
+    total_bytes: u64,
+}
+
+impl ExtractionStats {
+    fn record<R: Read>(&mut self, entry: &Entry<'_, R>) -> Result<(), Error> {
+        // Every entry is nested within a `{name}-{num}` directory, which we don't count.
+        let path = entry.path()?;
+        let mut components = path.components();
+        components.next();
+        let path = components.as_path();
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() {
+            self.dirs.insert(path.to_path_buf());
+        } else if entry_type.is_symlink() {
+            self.symlinks += 1;
+        } else if entry_type.is_file() {
+            self.files += 1;
+            self.total_bytes += entry.size();
+        }
+
+        self.dirs.extend(
+            path.ancestors()
+                .skip(1)
+                .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                .map(Path::to_path_buf),
+        );
+        self.max_depth = self.max_depth.max(path.components().count());
+
+        Ok(())
+    }
+}
+
+/// An exclusive lock on a corpus, as returned by [`Corpus::lock`]. The lock is released when this
+/// is dropped.
+#[derive(Debug)]
+pub struct CorpusLock {
+    _file: File,
 }
 
 #[derive(Error, Debug)]
 pub enum Error {
+    #[error("corpus is locked by another process (pid {pid:?}): {path:?}")]
+    CorpusLocked { path: PathBuf, pid: Option<u32> },
+
+    #[error("download error: {0:?}")]
+    Download(#[from] download::Error),
+
+    #[error("archive contains more than one entry for {0:?}")]
+    DuplicateEntry(PathBuf),
+
+    #[error("history error: {0:?}")]
+    History(#[from] history::Error),
+
+    #[error("index error: {0:?}")]
+    Index(#[from] index::Error),
+
     #[error("io error: {0:?}")]
     Io(#[from] std::io::Error),
 
+    #[error("no top-level manifest in {name} {num}")]
+    MissingManifest { name: String, num: String },
+
     #[error("path exists, but is not a directory: {0:?}")]
     NotADirectory(PathBuf),
 
-    #[error("reqwest error: {0:?}")]
-    Reqwest(#[from] reqwest::Error),
+    #[error("refusing to clear a directory that doesn't look like a corpus: found {0:?}")]
+    NotACorpus(PathBuf),
+
+    #[error("{name} {num} is not in the index")]
+    NotInIndex { name: String, num: String },
+
+    #[error("post-extract hook failed: {0:?}")]
+    PostExtract(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[cfg(feature = "async")]
+    #[error("extraction task failed: {0:?}")]
+    Task(#[from] tokio::task::JoinError),
+
+    #[error("TOML parse error: {0:?}")]
+    TomlDe(#[from] toml::de::Error),
+
+    #[error("TOML serialisation error: {0:?}")]
+    TomlSer(#[from] toml::ser::Error),
+
+    #[error("archive entry has an unsafe path: {0:?}")]
+    UnsafePath(PathBuf),
 
     #[error("vault error: {0:?}")]
     Vault(#[from] vault::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use flate2::{write::GzEncoder, Compression};
+    use googletest::prelude::*;
+    use tar::{Builder, Header};
+
+    use super::*;
+
+    #[test]
+    fn test_populate_many_on_error() -> anyhow::Result<()> {
+        use crate::progress::NoProgress;
+
+        let temp = tempfile::tempdir()?;
+        // Nothing should be listening on the discard port, so every download fails.
+        let corpus = Corpus::new(temp.path().to_path_buf())?
+            .with_mirrors(vec!["http://127.0.0.1:9".to_string()]);
+        let versions = || {
+            (0..3)
+                .map(|i| ("foo".to_string(), format!("1.0.{i}"), String::new(), None))
+                .collect::<Vec<_>>()
+        };
+
+        // With a single worker, versions are populated one at a time, so nothing else can be in
+        // progress when the first one fails.
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build()?;
+        let failures =
+            pool.install(|| corpus.populate_many(versions(), OnError::FailFast, &NoProgress));
+        assert_that!(
+            failures
+                .iter()
+                .map(|(name, num, _e)| (name.as_str(), num.as_str()))
+                .collect::<Vec<_>>(),
+            eq(vec![("foo", "1.0.0")])
+        );
+
+        let failures =
+            pool.install(|| corpus.populate_many(versions(), OnError::KeepGoing, &NoProgress));
+        assert_that!(failures, len(eq(3)));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_populate_many_metrics() -> anyhow::Result<()> {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        use crate::progress::NoProgress;
+
+        let temp = tempfile::tempdir()?;
+        // Nothing should be listening on the discard port, so every download fails.
+        let corpus = Corpus::new(temp.path().to_path_buf())?
+            .with_mirrors(vec!["http://127.0.0.1:9".to_string()]);
+        // The filter hides how many versions there are from the size hint.
+        let versions = (0..3)
+            .map(|i| ("foo".to_string(), format!("1.0.{i}"), String::new(), None))
+            .filter(|_| true);
+
+        // The recorder is only installed for this thread, so the pool's only worker has to be
+        // this thread too.
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .use_current_thread()
+            .build()?;
+        let failures = metrics::with_local_recorder(&recorder, || {
+            pool.install(|| corpus.populate_many(versions, OnError::KeepGoing, &NoProgress))
+        });
+        assert_that!(failures, len(eq(3)));
+
+        let values = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _unit, _description, value)| (key.key().name().to_string(), value))
+            .collect::<Vec<_>>();
+        assert_that!(
+            values,
+            contains(eq((
+                "librarian_populate_queue_depth".to_string(),
+                DebugValue::Gauge(0.0.into())
+            )))
+        );
+        assert_that!(
+            values,
+            contains(eq((
+                "librarian_populate_errors_total".to_string(),
+                DebugValue::Counter(3)
+            )))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transform_manifest() -> anyhow::Result<()> {
+        fn add_edition(table: &mut toml::Table) {
+            if let Some(toml::Value::Table(package)) = table.get_mut("package") {
+                package.insert("edition".into(), "2021".into());
+            }
+        }
+
+        let temp = tempfile::tempdir()?;
+        for (dir, file_name) in [("upper", "Cargo.toml"), ("lower", "cargo.toml")] {
+            let root = temp.path().join(dir);
+            std::fs::create_dir(&root)?;
+            std::fs::write(root.join(file_name), "[package]\nname = \"foo\"\n")?;
+
+            Corpus::transform_manifest(&root, add_edition)?;
+            assert_that!(
+                std::fs::read_to_string(root.join(file_name))?,
+                contains_substring("edition = \"2021\"")
+            );
+            assert_that!(
+                std::fs::read_to_string(root.join(format!("{file_name}.orig")))?,
+                eq("[package]\nname = \"foo\"\n")
+            );
+        }
+
+        // Nothing to transform isn't an error.
+        Corpus::transform_manifest(temp.path(), add_edition)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_entry() -> anyhow::Result<()> {
+        let mut builder = Builder::new(Vec::new());
+        append_file(&mut builder, "foo-1.0.0/Cargo.toml", b"[package]\n", 0o644)?;
+        append_file(
+            &mut builder,
+            "foo-1.0.0/src/lib.rs",
+            b"fn foo() {}\n",
+            0o755,
+        )?;
+        append_file(&mut builder, "foo-1.0.0/empty", b"", 0o644)?;
+        let big = vec![b'x'; EXTRACT_CHUNK_SIZE * 2 + 1];
+        append_file(&mut builder, "foo-1.0.0/big", &big, 0o644)?;
+        let data = builder.into_inner()?;
+
+        let temp = tempfile::tempdir()?;
+        let root = temp.path().join("foo-1.0.0");
+        let mut seen: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+        let mut chunks = 0;
+        let mut archive = Archive::new(data.as_slice());
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_path = entry.path()?.into_owned();
+            Corpus::extract_entry(
+                &mut entry,
+                &entry_path,
+                temp.path(),
+                &root,
+                ModeNormalization::Preserve,
+                &mut |path, data| {
+                    chunks += 1;
+                    match seen.last_mut() {
+                        Some((last, contents)) if last.as_path() == path => {
+                            contents.extend_from_slice(data)
+                        }
+                        _ => seen.push((path.to_path_buf(), data.to_vec())),
+                    }
+                },
+            )?;
+        }
+
+        assert_that!(
+            seen,
+            elements_are![
+                eq((PathBuf::from("Cargo.toml"), b"[package]\n".to_vec())),
+                eq((PathBuf::from("src/lib.rs"), b"fn foo() {}\n".to_vec())),
+                eq((PathBuf::from("empty"), Vec::new())),
+                eq((PathBuf::from("big"), big.clone()))
+            ]
+        );
+        // The big file is streamed in three chunks.
+        assert_that!(chunks, eq(6));
+        assert_that!(std::fs::read(root.join("big"))?, eq(&big));
+        assert_that!(
+            std::fs::read_to_string(root.join("src/lib.rs"))?,
+            eq("fn foo() {}\n")
+        );
+        assert_that!(
+            std::fs::metadata(root.join("src/lib.rs"))?
+                .permissions()
+                .mode()
+                & 0o777,
+            eq(0o755)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_through_symlink() -> anyhow::Result<()> {
+        let outside = tempfile::tempdir()?;
+        for (link, file) in [
+            ("foo-1.0.0/link", "foo-1.0.0/link/x"),
+            ("foo-1.0.0/x", "foo-1.0.0/x"),
+        ] {
+            let mut builder = Builder::new(Vec::new());
+            let mut header = Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            header.set_mode(0o777);
+            builder.append_link(&mut header, link, outside.path().join("x"))?;
+            append_file(&mut builder, file, b"oops\n", 0o644)?;
+            let data = builder.into_inner()?;
+
+            let temp = tempfile::tempdir()?;
+            let root = temp.path().join("foo-1.0.0");
+            let mut archive = Archive::new(data.as_slice());
+            let mut result = Ok(());
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let entry_path = entry.path()?.into_owned();
+                result = Corpus::extract_entry(
+                    &mut entry,
+                    &entry_path,
+                    temp.path(),
+                    &root,
+                    ModeNormalization::Preserve,
+                    &mut |_, _| {},
+                );
+            }
+
+            assert_that!(
+                result,
+                err(matches_pattern!(Error::UnsafePath(eq(PathBuf::from(file)))))
+            );
+            assert_that!(std::fs::read_dir(outside.path())?.count(), eq(0));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mode_normalization() {
+        assert_that!(ModeNormalization::Preserve.file_mode(0o100777), eq(0o777));
+        assert_that!(ModeNormalization::Normalize.file_mode(0o666), eq(0o644));
+        assert_that!(ModeNormalization::Normalize.file_mode(0o744), eq(0o755));
+        assert_that!(
+            ModeNormalization::StripExecutable.file_mode(0o755),
+            eq(0o644)
+        );
+    }
+
+    #[test]
+    fn test_ownership() -> anyhow::Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = tempfile::tempdir()?;
+        std::fs::create_dir_all(temp.path().join("foo-1.0.0/src"))?;
+        std::fs::write(temp.path().join("foo-1.0.0/src/lib.rs"), "")?;
+
+        // Only root can give files away, so change them to the group they already have, which is
+        // always allowed.
+        let gid = std::fs::metadata(temp.path())?.gid();
+        let ownership = Ownership {
+            uid: None,
+            gid: Some(gid),
+        };
+        let mut done = HashSet::new();
+        ownership.apply(temp.path(), Path::new("foo-1.0.0/src/lib.rs"), &mut done)?;
+        ownership.apply(temp.path(), Path::new("./foo-1.0.0/missing.rs"), &mut done)?;
+        ownership.apply(temp.path(), Path::new("../escape.rs"), &mut done)?;
+
+        assert_that!(
+            done,
+            unordered_elements_are![
+                eq(temp.path().join("foo-1.0.0")),
+                eq(temp.path().join("foo-1.0.0/src")),
+                eq(temp.path().join("foo-1.0.0/src/lib.rs"))
+            ]
+        );
+        assert_that!(
+            std::fs::metadata(temp.path().join("foo-1.0.0/src/lib.rs"))?.gid(),
+            eq(gid)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_docs() -> anyhow::Result<()> {
+        let mut builder = Builder::new(Vec::new());
+        append_file(
+            &mut builder,
+            "foo-1.0.0/src/lib.rs",
+            b"fn foo() {}\n",
+            0o644,
+        )?;
+        append_file(&mut builder, "foo-1.0.0/README.md", b"ignored\n", 0o644)?;
+        append_file(&mut builder, "foo-1.0.0/docs/intro.md", b"# foo\n", 0o644)?;
+        append_file(
+            &mut builder,
+            "foo-1.0.0/Cargo.toml",
+            b"[package]\nname = \"foo\"\nversion = \"1.0.0\"\nreadme = \"./docs/intro.md\"\n",
+            0o644,
+        )?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner()?)?;
+        let data = encoder.finish()?;
+
+        for layout in [Layout::ByVersion, Layout::ContentAddressed] {
+            let temp = tempfile::tempdir()?;
+            let corpus = Corpus::new(temp.path().to_path_buf())?.with_layout(layout);
+            // Streamed tarballs are read from disk rather than memory.
+            let mut file = NamedTempFile::new_in(temp.path())?;
+            file.write_all(&data)?;
+            let path = corpus.extract_docs("foo", "1.0.0", &Tarball::File(file))?;
+
+            let mut files: Vec<PathBuf> = walkdir::WalkDir::new(&path)
+                .follow_links(true)
+                .into_iter()
+                .filter_map(std::result::Result::ok)
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.path().strip_prefix(&path).map(Path::to_path_buf))
+                .collect::<std::result::Result<_, _>>()?;
+            files.sort();
+            assert_that!(
+                files,
+                eq(vec![
+                    PathBuf::from("Cargo.toml"),
+                    PathBuf::from("docs/intro.md")
+                ])
+            );
+            assert_that!(
+                std::fs::read_to_string(path.join("docs/intro.md"))?,
+                eq("# foo\n")
+            );
+            assert_that!(
+                std::fs::symlink_metadata(&path)?.is_symlink(),
+                eq(layout == Layout::ContentAddressed)
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extraction_stats() -> anyhow::Result<()> {
+        let mut builder = Builder::new(Vec::new());
+        append_file(&mut builder, "foo-1.0.0/Cargo.toml", b"[package]\n", 0o644)?;
+        append_file(
+            &mut builder,
+            "foo-1.0.0/src/lib.rs",
+            b"fn foo() {}\n",
+            0o644,
+        )?;
+        append_file(&mut builder, "foo-1.0.0/tests/a/b/c.rs", b"", 0o644)?;
+        let data = builder.into_inner()?;
+
+        let mut stats = ExtractionStats::default();
+        let mut archive = Archive::new(data.as_slice());
+        for entry in archive.entries()? {
+            stats.record(&entry?)?;
+        }
+
+        assert_that!(stats.files, eq(3));
+        assert_that!(stats.dirs.len(), eq(4));
+        assert_that!(stats.symlinks, eq(0));
+        assert_that!(stats.max_depth, eq(4));
+        assert_that!(stats.total_bytes, eq(22));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?;
+
+        let lock = corpus.lock()?;
+        assert_that!(
+            corpus.lock(),
+            err(matches_pattern!(Error::CorpusLocked {
+                path: eq(temp.path().join(LOCK_FILE)),
+                pid: some(eq(std::process::id())),
+            }))
+        );
+
+        drop(lock);
+        assert_that!(corpus.lock(), ok(anything()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?;
+
+        std::fs::create_dir_all(temp.path().join("f/fo/foo/1.0.0"))?;
+        std::fs::create_dir_all(temp.path().join("b/ba/bar/1.0.0"))?;
+        std::fs::write(temp.path().join(".librarianignore"), "evil\n")?;
+        corpus.clear()?;
+
+        let remaining: Vec<_> = std::fs::read_dir(temp.path())?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<std::io::Result<_>>()?;
+        assert_that!(remaining, elements_are![eq(".librarianignore")]);
+
+        std::fs::create_dir_all(temp.path().join("f/fo/foo/1.0.0"))?;
+        std::fs::write(temp.path().join("important.txt"), "")?;
+        assert_that!(
+            corpus.clear(),
+            err(matches_pattern!(Error::NotACorpus(eq(temp
+                .path()
+                .join("important.txt")))))
+        );
+        assert_that!(temp.path().join("f/fo/foo/1.0.0").is_dir(), eq(true));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_or_populate() -> anyhow::Result<()> {
+        let data = tarball("foo", "1.0.0")?;
+
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?;
+
+        let downloads = AtomicUsize::new(0);
+        let paths = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        corpus.get_or_populate_with("foo", "1.0.0", || {
+                            downloads.fetch_add(1, Ordering::SeqCst);
+                            Ok(data.clone())
+                        })
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("thread panicked"))
+                .collect::<std::result::Result<Vec<_>, Error>>()
+        })?;
+
+        assert_that!(downloads.load(Ordering::SeqCst), eq(1));
+        let expected = corpus.path("foo", "1.0.0")?;
+        assert_that!(paths, each(eq(expected.clone())));
+        assert_that!(expected.join("Cargo.toml").is_file(), eq(true));
+        assert_that!(
+            std::fs::read_dir(temp.path().join(".locks"))?.count(),
+            eq(0)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contents_index() -> anyhow::Result<()> {
+        let data = tarball("foo", "1.0.0")?;
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?.with_contents_index();
+
+        let path = corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data))?;
+        assert_that!(corpus.vault().contains("foo", "1.0.0")?, eq(true));
+        assert_that!(
+            corpus.vault().lookup("foo", "1.0.0")?,
+            some(eq(corpus.path("foo", "1.0.0")?))
+        );
+        assert_that!(path.is_dir(), eq(true));
+
+        corpus.discard("foo", "1.0.0");
+        assert_that!(corpus.vault().contains("foo", "1.0.0")?, eq(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_index() -> anyhow::Result<()> {
+        let index_dir = tempfile::tempdir()?;
+        let index = Index::new(index_dir.path())?;
+        let index_file = index.crate_path("foo")?;
+        std::fs::create_dir_all(index_file.parent().expect("index file parent"))?;
+        std::fs::write(
+            &index_file,
+            r#"{"name":"foo","vers":"1.0.0","cksum":"","yanked":false}"#,
+        )?;
+
+        let data = tarball("foo", "1.0.0")?;
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?.with_index(index);
+
+        for (name, num) in [("foo", "2.0.0"), ("bar", "1.0.0")] {
+            assert_that!(
+                corpus.populate_with(
+                    name,
+                    num,
+                    |_path, _data| {},
+                    || -> std::result::Result<Vec<u8>, Error> {
+                        panic!("{name} {num} should not be downloaded")
+                    }
+                ),
+                err(matches_pattern!(Error::NotInIndex {
+                    name: eq(name),
+                    num: eq(num),
+                }))
+            );
+        }
+        assert_that!(
+            corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data))?,
+            eq(corpus.path("foo", "1.0.0")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_file() -> anyhow::Result<()> {
+        let data = tarball("foo", "1.0.0")?;
+
+        let mut out = Vec::new();
+        assert_that!(
+            Corpus::extract_file(&data, Path::new("./Cargo.toml"), &mut out)?,
+            eq(true)
+        );
+        assert_that!(
+            String::from_utf8(out)?,
+            eq("[package]\nname = \"foo\"\nversion = \"1.0.0\"\n")
+        );
+        assert_that!(
+            Corpus::extract_file(&data, Path::new("src/lib.rs"), std::io::sink())?,
+            eq(false)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_manifest() -> anyhow::Result<()> {
+        let manifest = Corpus::read_manifest("foo", "1.0.0", tarball("foo", "1.0.0")?.as_slice())?;
+        assert_that!(manifest.crate_name(), eq("foo"));
+        assert_that!(manifest.crate_version(), eq("1.0.0"));
+
+        let mut builder = Builder::new(Vec::new());
+        append_file(
+            &mut builder,
+            "foo-1.0.0/sub/Cargo.toml",
+            b"[package]\n",
+            0o644,
+        )?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner()?)?;
+        assert_that!(
+            Corpus::read_manifest("foo", "1.0.0", encoder.finish()?.as_slice()),
+            err(matches_pattern!(Error::MissingManifest {
+                name: eq("foo"),
+                num: eq("1.0.0"),
+            }))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_extract() -> anyhow::Result<()> {
+        fn require_lib(
+            root: &Path,
+        ) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            if root.join("src/lib.rs").is_file() {
+                Ok(())
+            } else {
+                Err("no src/lib.rs".into())
+            }
+        }
+
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?.with_post_extract(require_lib);
+
+        let data = tarball("foo", "1.0.0")?;
+        assert_that!(
+            corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data)),
+            err(matches_pattern!(Error::PostExtract(anything())))
+        );
+        assert_that!(corpus.populated_path("foo", "1.0.0")?, none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_addressed() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?.with_layout(Layout::ContentAddressed);
+
+        // The same content, republished under a second version.
+        let data = tarball("foo", "1.0.0")?;
+        let a = corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data.clone()))?;
+        let b = corpus.populate_with("foo", "1.0.1", |_path, _data| {}, || Ok(data.clone()))?;
+
+        let object = PathBuf::from("../../../.objects").join(download::sha256(&data));
+        assert_that!(std::fs::read_link(&a)?, eq(object.clone()));
+        assert_that!(std::fs::read_link(&b)?, eq(object));
+        assert_that!(
+            std::fs::read_to_string(b.join("Cargo.toml"))?,
+            contains_substring("foo")
+        );
+
+        let found: Vec<PathBuf> = corpus
+            .vault()
+            .iter_crate_versions()
+            .map(|result| result.map(|version| version.path))
+            .collect::<std::result::Result<_, _>>()?;
+        assert_that!(found, eq(vec![a.join("Cargo.toml"), b.join("Cargo.toml")]));
+
+        corpus.clear()?;
+        assert_that!(corpus.vault().objects_path().exists(), eq(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overwrite_policy() -> anyhow::Result<()> {
+        let mut builder = Builder::new(Vec::new());
+        append_file(&mut builder, "foo-1.0.0/Cargo.toml", b"[package]\n", 0o644)?;
+        append_file(&mut builder, "foo-1.0.0/src/lib.rs", b"first", 0o644)?;
+        append_file(&mut builder, "foo-1.0.0/./src/lib.rs", b"second", 0o644)?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner()?)?;
+        let data = encoder.finish()?;
+
+        for (policy, expected) in [
+            (OverwritePolicy::Allow, "second"),
+            (OverwritePolicy::Skip, "first"),
+        ] {
+            let temp = tempfile::tempdir()?;
+            let corpus = Corpus::new(temp.path().to_path_buf())?.with_overwrite_policy(policy);
+            let path =
+                corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data.clone()))?;
+            assert_that!(
+                std::fs::read_to_string(path.join("src/lib.rs"))?,
+                eq(expected)
+            );
+        }
+
+        let temp = tempfile::tempdir()?;
+        let corpus =
+            Corpus::new(temp.path().to_path_buf())?.with_overwrite_policy(OverwritePolicy::Error);
+        assert_that!(
+            corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data.clone())),
+            err(matches_pattern!(Error::DuplicateEntry(eq(Path::new(
+                "foo-1.0.0/src/lib.rs"
+            )))))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_components() -> anyhow::Result<()> {
+        let mut builder = Builder::new(Vec::new());
+        append_file(&mut builder, "foo-1.0.0/Cargo.toml", b"[package]\n", 0o644)?;
+        append_file(
+            &mut builder,
+            "foo-1.0.0/foo/Cargo.toml",
+            b"[package]\n",
+            0o644,
+        )?;
+        append_file(&mut builder, "foo-1.0.0/./foo/src/lib.rs", b"", 0o644)?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner()?)?;
+        let data = encoder.finish()?;
+
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?.with_strip_components(1);
+        let mut seen = Vec::new();
+        let path = corpus.populate_with(
+            "foo",
+            "1.0.0",
+            |path, _data| seen.push(path.to_path_buf()),
+            || Ok(data.clone()),
+        )?;
+        assert_that!(
+            seen,
+            elements_are![eq(Path::new("Cargo.toml")), eq(Path::new("src/lib.rs"))]
+        );
+        assert_that!(path.join("src/lib.rs").is_file(), eq(true));
+        assert_that!(path.join("foo").exists(), eq(false));
+
+        assert_that!(
+            strip_components(Path::new("foo-1.0.0/a/b/c"), 2),
+            some(eq(Path::new("foo-1.0.0/c")))
+        );
+        assert_that!(strip_components(Path::new("foo-1.0.0/a/b"), 2), none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_components_links() -> anyhow::Result<()> {
+        fn archive(hard_link_target: &str) -> anyhow::Result<Vec<u8>> {
+            let mut builder = Builder::new(Vec::new());
+            append_file(
+                &mut builder,
+                "foo-1.0.0/foo/Cargo.toml",
+                b"[package]\n",
+                0o644,
+            )?;
+            append_file(&mut builder, "foo-1.0.0/foo/src/lib.rs", b"lib", 0o644)?;
+            for (entry_type, path, target) in [
+                (
+                    tar::EntryType::Link,
+                    "foo-1.0.0/foo/src/copy.rs",
+                    hard_link_target,
+                ),
+                (
+                    tar::EntryType::Symlink,
+                    "foo-1.0.0/foo/src/alias.rs",
+                    "lib.rs",
+                ),
+            ] {
+                let mut header = Header::new_gnu();
+                header.set_entry_type(entry_type);
+                header.set_size(0);
+                header.set_mode(0o644);
+                builder.append_link(&mut header, path, target)?;
+            }
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&builder.into_inner()?)?;
+            Ok(encoder.finish()?)
+        }
+
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?.with_strip_components(1);
+        let data = archive("foo-1.0.0/foo/src/lib.rs")?;
+        let path = corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data))?;
+        assert_that!(
+            std::fs::read_to_string(path.join("src/copy.rs"))?,
+            eq("lib")
+        );
+        assert_that!(
+            std::fs::read_link(path.join("src/alias.rs"))?,
+            eq(Path::new("lib.rs"))
+        );
+
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?.with_strip_components(1);
+        let data = archive("/etc/passwd")?;
+        assert_that!(
+            corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data)),
+            err(matches_pattern!(Error::UnsafePath(eq(Path::new(
+                "foo-1.0.0/src/copy.rs"
+            )))))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resumable_extraction() -> anyhow::Result<()> {
+        let mut builder = Builder::new(Vec::new());
+        append_file(&mut builder, "foo-1.0.0/Cargo.toml", b"[package]\n", 0o644)?;
+        append_file(
+            &mut builder,
+            "foo-1.0.0/src/lib.rs",
+            b"fn foo() {}\n",
+            0o644,
+        )?;
+        append_file(
+            &mut builder,
+            "foo-1.0.0/src/main.rs",
+            b"fn main() {}\n",
+            0o644,
+        )?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner()?)?;
+        let data = encoder.finish()?;
+
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?.with_resumable_extraction();
+
+        // Simulate an earlier attempt that crashed after completing lib.rs and main.rs, after
+        // which main.rs was truncated. lib.rs has the right size, but different contents, so we
+        // can tell that it was skipped. Older versions created the empty version directory up
+        // front, so that's left behind too.
+        std::fs::create_dir_all(corpus.path("foo", "1.0.0")?)?;
+        assert_that!(corpus.populated_path("foo", "1.0.0")?, none());
+        let dest = temp.path().join(EXTRACTING_DIR).join("foo-1.0.0");
+        std::fs::create_dir_all(dest.join("foo-1.0.0/src"))?;
+        std::fs::write(dest.join("foo-1.0.0/src/lib.rs"), b"fn bar() {}\n")?;
+        std::fs::write(dest.join("foo-1.0.0/src/main.rs"), b"fn ma")?;
+        std::fs::write(
+            dest.join(JOURNAL_FILE),
+            "foo-1.0.0/src/lib.rs\nfoo-1.0.0/src/main.rs\n",
+        )?;
+
+        let mut seen = Vec::new();
+        let path = corpus.populate_with(
+            "foo",
+            "1.0.0",
+            |path, _data| seen.push(path.to_path_buf()),
+            || Ok(data.clone()),
+        )?;
+        assert_that!(
+            seen,
+            eq(vec![
+                PathBuf::from("Cargo.toml"),
+                PathBuf::from("src/main.rs")
+            ])
+        );
+        assert_that!(
+            std::fs::read_to_string(path.join("src/lib.rs"))?,
+            eq("fn bar() {}\n")
+        );
+        assert_that!(
+            std::fs::read_to_string(path.join("src/main.rs"))?,
+            eq("fn main() {}\n")
+        );
+        assert_that!(dest.exists(), eq(false));
+        assert_that!(corpus.populated_path("foo", "1.0.0")?, some(anything()));
+
+        // A version that was moved into place before its journal was removed is extracted again.
+        std::fs::create_dir_all(&dest)?;
+        std::fs::write(dest.join(JOURNAL_FILE), "")?;
+        std::fs::write(path.join("src/lib.rs"), b"fn bar() {}\n")?;
+        assert_that!(corpus.populated_path("foo", "1.0.0")?, none());
+        let path = corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data.clone()))?;
+        assert_that!(
+            std::fs::read_to_string(path.join("src/lib.rs"))?,
+            eq("fn foo() {}\n")
+        );
+        assert_that!(dest.exists(), eq(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_tarball() -> anyhow::Result<()> {
+        let data = tarball("foo", "1.0.0")?;
+
+        for layout in [
+            Layout::ByVersion,
+            Layout::ContentAddressed,
+            Layout::ArchiveOnly,
+        ] {
+            let temp = tempfile::tempdir()?;
+            let corpus = Corpus::new(temp.path().to_path_buf())?.with_layout(layout);
+            let mut file = NamedTempFile::new_in(temp.path())?;
+            file.write_all(&data)?;
+
+            let path = corpus.populate_with(
+                "foo",
+                "1.0.0",
+                |_path, _data| {},
+                || Ok(Tarball::File(file)),
+            )?;
+            match layout {
+                Layout::ArchiveOnly => assert_that!(
+                    std::fs::read(corpus.vault().archive_path("foo", "1.0.0")?)?,
+                    eq(data.clone())
+                ),
+                _ => assert_that!(
+                    std::fs::read_to_string(path.join("Cargo.toml"))?,
+                    contains_substring("foo")
+                ),
+            }
+            if layout == Layout::ContentAddressed {
+                assert_that!(
+                    corpus.vault().recorded_checksum("foo", "1.0.0")?,
+                    some(eq(download::sha256(&data)))
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_only() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?
+            .with_layout(Layout::ArchiveOnly)
+            .with_manifest_verification();
+
+        let data = tarball("foo", "1.0.0")?;
+        let path = corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data.clone()))?;
+        let archive = corpus.vault().archive_path("foo", "1.0.0")?;
+        assert_that!(std::fs::read(&archive)?, eq(data));
+        assert_that!(path.join("foo-1.0.0.crate"), eq(archive.clone()));
+        corpus.check_manifest("foo", "1.0.0")?;
+
+        let found: Vec<PathBuf> = corpus
+            .vault()
+            .iter_archives()
+            .map(|result| result.map(|archive| archive.path))
+            .collect::<std::result::Result<_, _>>()?;
+        assert_that!(found, eq(vec![archive]));
+
+        let data = tarball("bar", "1.0.0")?;
+        corpus.populate_with("foo", "2.0.0", |_path, _data| {}, || Ok(data))?;
+        assert_that!(
+            corpus.check_manifest("foo", "2.0.0"),
+            err(matches_pattern!(Error::Vault(matches_pattern!(
+                vault::Error::ManifestMismatch {
+                    expected: eq("foo@2.0.0"),
+                    found: eq("bar@1.0.0"),
+                }
+            ))))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?.with_contents_index();
+
+        let mut builder = Builder::new(Vec::new());
+        append_file(
+            &mut builder,
+            "foo-1.0.0/Cargo.toml",
+            b"[package]\nname = \"foo\"\nversion = \"1.0.0\"\n",
+            0o644,
+        )?;
+        append_file(
+            &mut builder,
+            "foo-1.0.0/src/lib.rs",
+            b"fn foo() {}\n",
+            0o644,
+        )?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner()?)?;
+        let data = encoder.finish()?;
+        let path = corpus.populate_with("foo", "1.0.0", |_path, _data| {}, || Ok(data))?;
+
+        let archive = corpus.compact("foo", "1.0.0")?;
+        assert_that!(archive, eq(corpus.vault().archive_path("foo", "1.0.0")?));
+        assert_that!(
+            std::fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<std::io::Result<Vec<_>>>()?,
+            eq(vec![archive.clone()])
+        );
+        assert_that!(corpus.compact("foo", "1.0.0")?, eq(archive.clone()));
+        assert_that!(
+            corpus.vault().lookup("foo", "1.0.0")?,
+            some(eq(archive.clone()))
+        );
+
+        let data = std::fs::read(&archive)?;
+        let manifest = Corpus::read_manifest("foo", "1.0.0", &data)?;
+        assert_that!(manifest.crate_version(), eq("1.0.0"));
+        let mut lib = Vec::new();
+        assert_that!(
+            Corpus::extract_file(&data, Path::new("src/lib.rs"), &mut lib)?,
+            eq(true)
+        );
+        assert_that!(lib, eq(b"fn foo() {}\n".to_vec()));
+
+        let archived = Corpus::new(temp.path().to_path_buf())?.with_layout(Layout::ArchiveOnly);
+        assert_that!(archived.vault().iter_archives().count(), eq(1));
+
+        Ok(())
+    }
+
+    /// Counts allocations, so that the benchmarks can report them.
+    #[cfg(feature = "bench")]
+    struct CountingAllocator;
+
+    #[cfg(feature = "bench")]
+    static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+    #[cfg(feature = "bench")]
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(
+            &self,
+            ptr: *mut u8,
+            layout: std::alloc::Layout,
+            new_size: usize,
+        ) -> *mut u8 {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            std::alloc::System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[cfg(feature = "bench")]
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Compares decompressing a fixed set of crates with a buffered decoder and reading each file
+    /// into a fresh buffer against decoding straight from the tarball and streaming each file
+    /// through the pooled extraction buffer, then times populating those crates. Run with:
+    ///
+    /// ```sh
+    /// cargo test -p librarian --release --features bench -- --nocapture bench_extraction
+    /// ```
+    #[cfg(feature = "bench")]
+    #[test]
+    fn bench_extraction() -> anyhow::Result<()> {
+        use rayon::iter::IntoParallelRefIterator;
+
+        const CRATES: usize = 200;
+        const ROUNDS: usize = 10;
+
+        let contents = vec![b'x'; 4096];
+        let tarballs = (0..CRATES)
+            .map(|i| {
+                let name = format!("crate{i}");
+                let mut builder = Builder::new(Vec::new());
+                let manifest = format!("[package]\nname = \"{name}\"\nversion = \"1.0.0\"\n");
+                append_file(
+                    &mut builder,
+                    &format!("{name}-1.0.0/Cargo.toml"),
+                    manifest.as_bytes(),
+                    0o644,
+                )?;
+                for file in 0..50 {
+                    append_file(
+                        &mut builder,
+                        &format!("{name}-1.0.0/src/file{file}.rs"),
+                        &contents,
+                        0o644,
+                    )?;
+                }
+
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&builder.into_inner()?)?;
+                Ok((name, encoder.finish()?))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        fn read_entries<R: Read>(archive: &mut Archive<R>, pooled: bool) -> anyhow::Result<()> {
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if pooled {
+                    with_extract_buffer(|buf| -> std::io::Result<()> {
+                        while entry.read(buf)? > 0 {}
+                        Ok(())
+                    })?;
+                } else {
+                    let mut buf = Vec::with_capacity(entry.size() as usize);
+                    entry.read_to_end(&mut buf)?;
+                }
+            }
+            Ok(())
+        }
+
+        let read_all = |pooled: bool| -> anyhow::Result<(usize, Duration)> {
+            let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+            let start = Instant::now();
+            for _round in 0..ROUNDS {
+                for (_name, data) in &tarballs {
+                    if pooled {
+                        read_entries(&mut Archive::new(GzDecoder::new(data.as_slice())), true)?;
+                    } else {
+                        read_entries(
+                            &mut Archive::new(flate2::read::GzDecoder::new(data.as_slice())),
+                            false,
+                        )?;
+                    }
+                }
+            }
+            Ok((
+                ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+                start.elapsed(),
+            ))
+        };
+        // Once to warm up, then once to measure.
+        for pooled in [false, true, false, true] {
+            let (allocations, elapsed) = read_all(pooled)?;
+            println!(
+                "read (pooled: {pooled}): {} crates, {allocations} allocations in {elapsed:?}",
+                CRATES * ROUNDS
+            );
+        }
+
+        let temp = tempfile::tempdir()?;
+        let corpus = Corpus::new(temp.path().to_path_buf())?;
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        tarballs.par_iter().try_for_each(|(name, data)| {
+            corpus
+                .populate_with(name, "1.0.0", |_path, _data| {}, || Ok(data.clone()))
+                .map(|_path| ())
+        })?;
+        println!(
+            "populate: {CRATES} crates, {} allocations in {:?}",
+            ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+            start.elapsed()
+        );
+
+        Ok(())
+    }
+
+    /// Builds a gzipped `.crate` file containing only a manifest.
+    fn tarball(name: &str, num: &str) -> anyhow::Result<Vec<u8>> {
+        let mut builder = Builder::new(Vec::new());
+        let manifest = format!("[package]\nname = \"{name}\"\nversion = \"{num}\"\n");
+        append_file(
+            &mut builder,
+            &format!("{name}-{num}/Cargo.toml"),
+            manifest.as_bytes(),
+            0o644,
+        )?;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&builder.into_inner()?)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn append_file(
+        builder: &mut Builder<Vec<u8>>,
+        path: &str,
+        data: &[u8],
+        mode: u32,
+    ) -> anyhow::Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(mode);
+        header.set_cksum();
+        builder.append_data(&mut header, path, data)?;
+
+        Ok(())
+    }
+}
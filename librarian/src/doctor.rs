@@ -0,0 +1,220 @@
+use std::{fmt::Display, path::Path, time::Duration};
+
+use git2::Repository;
+
+/// The URL requested to check that crates can be downloaded.
+const DOWNLOAD_HOST: &str = "https://static.crates.io/";
+
+/// The result of a single check made by [`diagnose`].
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    /// The check passed, with a description of what was found.
+    Pass(String),
+
+    /// The check failed, with a description of the problem and a hint for fixing it.
+    Fail { problem: String, hint: String },
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, Outcome::Pass(_))
+    }
+
+    fn pass(name: &'static str, detail: impl Display) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Pass(detail.to_string()),
+        }
+    }
+
+    fn fail(name: &'static str, problem: impl Display, hint: impl Display) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Fail {
+                problem: problem.to_string(),
+                hint: hint.to_string(),
+            },
+        }
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            Outcome::Pass(detail) => write!(f, "[ok]   {}: {detail}", self.name),
+            Outcome::Fail { problem, hint } => {
+                write!(f, "[FAIL] {}: {problem}\n       hint: {hint}", self.name)
+            }
+        }
+    }
+}
+
+/// Checks that librarian is set up to use the given index and, if given, corpus.
+///
+/// Nothing is created or modified, other than a temporary file within the corpus.
+pub fn diagnose(
+    index: &Path,
+    corpus: Option<&Path>,
+    user_agent: &str,
+    min_free_bytes: u64,
+) -> Vec<Check> {
+    let mut checks = vec![check_index(index), check_network(user_agent)];
+    if let Some(corpus) = corpus {
+        checks.push(check_writable(corpus));
+        checks.push(check_disk_space(corpus, min_free_bytes));
+    }
+    checks
+}
+
+/// Checks that the index is a git repository with a checked out commit.
+pub fn check_index(path: &Path) -> Check {
+    const NAME: &str = "index";
+    const HINT: &str = "run `librarian -i <path> index-update` to fetch the index";
+
+    if !path.is_dir() {
+        return Check::fail(NAME, format!("{path:?} is not a directory"), HINT);
+    }
+
+    let repo = match Repository::open(path) {
+        Ok(repo) => repo,
+        Err(e) => {
+            return Check::fail(
+                NAME,
+                format!("{path:?} is not a git repository: {}", e.message()),
+                HINT,
+            );
+        }
+    };
+
+    let head = repo.head().and_then(|head| head.peel_to_commit());
+    match head {
+        Ok(commit) => Check::pass(NAME, format!("{path:?} is at {}", commit.id())),
+        Err(_e) => Check::fail(NAME, format!("{path:?} has nothing checked out"), HINT),
+    }
+}
+
+/// Checks that static.crates.io can be reached.
+///
+/// Any HTTP response counts, since all that matters is that the network path works.
+pub fn check_network(user_agent: &str) -> Check {
+    const NAME: &str = "network";
+    const HINT: &str =
+        "check your network connection, and any proxy set in HTTPS_PROXY or ALL_PROXY";
+
+    let result = reqwest::blocking::Client::builder()
+        .user_agent(user_agent)
+        .timeout(Duration::from_secs(10))
+        .build()
+        .and_then(|client| client.head(DOWNLOAD_HOST).send());
+    match result {
+        Ok(response) => Check::pass(
+            NAME,
+            format!("{DOWNLOAD_HOST} responded with {}", response.status()),
+        ),
+        Err(e) => Check::fail(NAME, format!("can't reach {DOWNLOAD_HOST}: {e}"), HINT),
+    }
+}
+
+/// Checks that a file can be created in the corpus, or the nearest existing ancestor if it hasn't
+/// been created yet.
+pub fn check_writable(corpus: &Path) -> Check {
+    const NAME: &str = "corpus";
+
+    let Some(dir) = existing_ancestor(corpus) else {
+        return Check::fail(
+            NAME,
+            format!("no part of {corpus:?} exists"),
+            "check the corpus path",
+        );
+    };
+
+    match tempfile::tempfile_in(dir) {
+        Ok(_file) => Check::pass(NAME, format!("{dir:?} is writable")),
+        Err(e) => Check::fail(
+            NAME,
+            format!("can't write to {dir:?}: {e}"),
+            "check the ownership and permissions of the corpus directory",
+        ),
+    }
+}
+
+/// Checks that the filesystem the corpus is on has at least `min_free_bytes` available.
+pub fn check_disk_space(corpus: &Path, min_free_bytes: u64) -> Check {
+    const NAME: &str = "disk space";
+
+    let Some(dir) = existing_ancestor(corpus) else {
+        return Check::fail(
+            NAME,
+            format!("no part of {corpus:?} exists"),
+            "check the corpus path",
+        );
+    };
+
+    match fs2::available_space(dir) {
+        Ok(available) if available >= min_free_bytes => {
+            Check::pass(NAME, format!("{available} bytes available"))
+        }
+        Ok(available) => Check::fail(
+            NAME,
+            format!("only {available} bytes available, expected at least {min_free_bytes}"),
+            "free up space, or put the corpus on a larger filesystem",
+        ),
+        Err(e) => Check::fail(
+            NAME,
+            format!("can't get the available space for {dir:?}: {e}"),
+            "check the corpus path",
+        ),
+    }
+}
+
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.is_dir())
+        .or_else(|| path.is_relative().then_some(Path::new(".")))
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_check_index() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        assert_that!(
+            check_index(&temp.path().join("missing")).passed(),
+            eq(false)
+        );
+        assert_that!(check_index(temp.path()).passed(), eq(false));
+
+        let repo = Repository::init(temp.path())?;
+        assert_that!(check_index(temp.path()).passed(), eq(false));
+
+        let signature = git2::Signature::now("librarian", "librarian@example.com")?;
+        let tree = repo.find_tree(repo.index()?.write_tree()?)?;
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])?;
+        assert_that!(check_index(temp.path()).passed(), eq(true));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_corpus() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let corpus = temp.path().join("not/yet/created");
+
+        assert_that!(check_writable(&corpus).passed(), eq(true));
+        assert_that!(check_disk_space(&corpus, 0).passed(), eq(true));
+        assert_that!(check_disk_space(&corpus, u64::MAX).passed(), eq(false));
+        assert_that!(corpus.exists(), eq(false));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,961 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    header::{
+        HeaderMap, HeaderValue, InvalidHeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING,
+        CONTENT_RANGE, FROM, RANGE,
+    },
+    StatusCode,
+};
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+use thiserror::Error;
+
+use crate::{
+    cache::TarballCache,
+    throttle::{BandwidthLimiter, Throttled},
+};
+
+/// The `User-Agent` sent with downloads unless overridden with [`Downloader::with_identity`].
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "librarian/",
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("CARGO_PKG_REPOSITORY"),
+    ")"
+);
+
+/// The size of the buffer used to hash a `.crate` file as it is streamed by
+/// [`Downloader::verify`], unless overridden with [`Downloader::with_verify_buffer_size`].
+pub const DEFAULT_VERIFY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The base URL that `.crate` files are downloaded from unless overridden with
+/// [`Downloader::with_mirrors`].
+pub const DEFAULT_MIRROR: &str = "https://static.crates.io/crates";
+
+/// Downloads `.crate` files from static.crates.io, or from a list of mirrors.
+#[derive(Debug)]
+pub struct Downloader {
+    bandwidth_limit: Option<BandwidthLimiter>,
+    client: Client,
+    mirrors: Vec<String>,
+    partial_dir: Option<PathBuf>,
+    tarball_cache: Option<TarballCache>,
+    verify_buffer_size: usize,
+}
+
+impl Downloader {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            bandwidth_limit: None,
+            client: Self::client(DEFAULT_USER_AGENT, None)?,
+            mirrors: vec![DEFAULT_MIRROR.to_string()],
+            partial_dir: None,
+            tarball_cache: None,
+            verify_buffer_size: DEFAULT_VERIFY_BUFFER_SIZE,
+        })
+    }
+
+    /// Sets the `User-Agent` header sent with each download, along with a `From` header if a
+    /// contact is given.
+    ///
+    /// crates.io asks that bulk users identify themselves and provide a way to be contacted.
+    pub fn with_identity(mut self, user_agent: &str, contact: Option<&str>) -> Result<Self, Error> {
+        self.client = Self::client(user_agent, contact)?;
+        Ok(self)
+    }
+
+    /// Caps the throughput of response bodies read by this downloader.
+    ///
+    /// The limiter can be shared with other downloaders, in which case the cap applies to all of
+    /// them together.
+    pub fn with_bandwidth_limit(mut self, limiter: BandwidthLimiter) -> Self {
+        self.bandwidth_limit = Some(limiter);
+        self
+    }
+
+    /// Sets the base URLs that `.crate` files are downloaded from, in order of preference.
+    ///
+    /// Each mirror must lay files out like static.crates.io: `{base}/{name}/{name}-{version}.crate`.
+    /// If a mirror can't be reached, or responds with a 404 or a server error, the next mirror is
+    /// tried before giving up.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mirrors` is empty.
+    pub fn with_mirrors(mut self, mirrors: Vec<String>) -> Self {
+        assert!(!mirrors.is_empty(), "at least one mirror must be given");
+        self.mirrors = mirrors;
+        self
+    }
+
+    /// Streams downloads into files in the given directory, so that an interrupted download can
+    /// be resumed with a `Range` request the next time the same crate version is downloaded.
+    ///
+    /// Like the tarball cache, this is only used when a checksum is provided to
+    /// [`Downloader::download`], since there's otherwise no way to know that the resumed download
+    /// was stitched together correctly.
+    pub fn with_partial_dir(mut self, path: PathBuf) -> Self {
+        self.partial_dir = Some(path);
+        self
+    }
+
+    /// Uses the given cache for downloaded tarballs.
+    ///
+    /// The cache is only used when a checksum is provided to [`Downloader::download`], and by
+    /// [`Downloader::download_to_file`]: tarballs are only cached once they have been verified
+    /// against the index checksum, and are verified again whenever they are read back.
+    pub fn with_tarball_cache(mut self, cache: TarballCache) -> Self {
+        self.tarball_cache = Some(cache);
+        self
+    }
+
+    pub fn tarball_cache(&self) -> Option<&TarballCache> {
+        self.tarball_cache.as_ref()
+    }
+
+    /// Sets the size of the buffer used to hash `.crate` files in [`Downloader::verify`], which
+    /// bounds how much of each file is held in memory at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn with_verify_buffer_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "verify buffer size must be non-zero");
+        self.verify_buffer_size = size;
+        self
+    }
+
+    fn client(user_agent: &str, contact: Option<&str>) -> Result<Client, Error> {
+        Ok(Client::builder()
+            .user_agent(user_agent)
+            .default_headers(default_headers(contact)?)
+            .build()?)
+    }
+
+    /// Downloads a `.crate` file, verifying it against the SHA-256 checksum from the index if one
+    /// is given.
+    #[tracing::instrument(skip(self), err)]
+    pub fn download(&self, name: &str, num: &str, cksum: Option<&str>) -> Result<Vec<u8>, Error> {
+        let cache = self.tarball_cache.as_ref().filter(|_| cksum.is_some());
+        if let (Some(cache), Some(cksum)) = (cache, cksum) {
+            if let Some(data) = cache.get(name, num)? {
+                if sha256(&data) == cksum {
+                    return Ok(data);
+                }
+
+                tracing::warn!(?name, ?num, "cached tarball failed verification; removing");
+                cache.remove(name, num)?;
+            }
+        }
+
+        let partial = self
+            .partial_dir
+            .as_ref()
+            .filter(|_| cksum.is_some())
+            .map(|dir| dir.join(format!("{name}-{num}.crate.part")));
+        let data = match &partial {
+            Some(partial) => self.fetch_resumable(name, num, partial)?,
+            None => self.fetch(name, num)?,
+        };
+
+        if let Some(expected) = cksum {
+            let actual = sha256(&data);
+
+            // Either the partial download is now complete, or it was corrupt: whichever it is, we
+            // don't want to resume from it again.
+            if let Some(partial) = &partial {
+                remove_if_exists(partial)?;
+            }
+
+            if actual != expected {
+                return Err(Error::ChecksumMismatch {
+                    name: name.to_string(),
+                    num: num.to_string(),
+                    expected: expected.to_string(),
+                    actual,
+                });
+            }
+
+            if let Some(cache) = cache {
+                cache.insert(name, num, &data)?;
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Like [`Downloader::download`], but streams the `.crate` file into a temporary file in
+    /// `dir` rather than buffering it in memory, hashing it on the way to verify it against the
+    /// SHA-256 checksum from the index.
+    ///
+    /// The tarball cache is used as in [`Downloader::download`], with cached tarballs copied
+    /// into `dir` and new downloads copied into the cache. Resumable downloads aren't used.
+    #[tracing::instrument(skip(self), err)]
+    pub fn download_to_file(
+        &self,
+        name: &str,
+        num: &str,
+        cksum: &str,
+        dir: &Path,
+    ) -> Result<NamedTempFile, Error> {
+        if let Some(cache) = &self.tarball_cache {
+            if let Some(mut cached) = cache.open(name, num)? {
+                let mut file = HashingWriter::new(NamedTempFile::new_in(dir)?);
+                std::io::copy(&mut cached, &mut file)?;
+                let (file, actual) = file.finish();
+                if actual == cksum {
+                    return Ok(file);
+                }
+
+                tracing::warn!(?name, ?num, "cached tarball failed verification; removing");
+                cache.remove(name, num)?;
+            }
+        }
+
+        let resp = self
+            .send(name, num, |url| self.client.get(url))?
+            .error_for_status()?;
+        let encoding = content_encoding(&resp);
+        let mut resp = self.throttle(resp);
+
+        let mut file = HashingWriter::new(NamedTempFile::new_in(dir)?);
+        match encoding.as_deref().map(str::trim) {
+            None | Some("") | Some("identity") => std::io::copy(&mut resp, &mut file)?,
+            Some("gzip") | Some("x-gzip") => std::io::copy(&mut GzDecoder::new(resp), &mut file)?,
+            Some("deflate") => std::io::copy(&mut ZlibDecoder::new(resp), &mut file)?,
+            Some(encoding) => {
+                return Err(Error::UnsupportedContentEncoding(encoding.to_string()));
+            }
+        };
+
+        let (file, actual) = file.finish();
+        if actual != cksum {
+            return Err(Error::ChecksumMismatch {
+                name: name.to_string(),
+                num: num.to_string(),
+                expected: cksum.to_string(),
+                actual,
+            });
+        }
+
+        if let Some(cache) = &self.tarball_cache {
+            cache.insert_file(name, num, file.path())?;
+        }
+
+        Ok(file)
+    }
+
+    /// Downloads a `.crate` file and verifies it against the SHA-256 checksum from the index,
+    /// without buffering it in memory or writing it to disk, returning the number of bytes
+    /// downloaded.
+    ///
+    /// The tarball cache is not consulted: the point is to check what is being served now.
+    #[tracing::instrument(skip(self), err)]
+    pub fn verify(&self, name: &str, num: &str, cksum: &str) -> Result<u64, Error> {
+        let resp = self
+            .send(name, num, |url| self.client.get(url))?
+            .error_for_status()?;
+
+        let size = self.verify_buffer_size;
+        let encoding = content_encoding(&resp);
+        let mut resp = CountingReader::new(self.throttle(resp));
+        let actual = match encoding.as_deref().map(str::trim) {
+            None | Some("") | Some("identity") => sha256_reader(&mut resp, size)?,
+            Some("gzip") | Some("x-gzip") => sha256_reader(GzDecoder::new(&mut resp), size)?,
+            Some("deflate") => sha256_reader(ZlibDecoder::new(&mut resp), size)?,
+            Some(encoding) => {
+                return Err(Error::UnsupportedContentEncoding(encoding.to_string()));
+            }
+        };
+
+        if actual != cksum {
+            return Err(Error::ChecksumMismatch {
+                name: name.to_string(),
+                num: num.to_string(),
+                expected: cksum.to_string(),
+                actual,
+            });
+        }
+
+        Ok(resp.count)
+    }
+
+    /// Sends the request built by `request` to each mirror in turn, returning the first response
+    /// that isn't worth retrying elsewhere.
+    ///
+    /// Connection failures, timeouts, 404s, 429s and server errors fall through to the next
+    /// mirror; anything else, including other error statuses, is returned to the caller. If every
+    /// mirror fails, the last mirror's response or error is returned.
+    fn send<F>(&self, name: &str, num: &str, request: F) -> Result<Response, Error>
+    where
+        F: Fn(&str) -> RequestBuilder,
+    {
+        let (last, rest) = self.mirrors.split_last().expect("mirrors is non-empty");
+        for mirror in rest {
+            match request(&mirror_url(mirror, name, num)).send() {
+                Ok(resp) if !is_retryable_status(resp.status()) => {
+                    tracing::debug!(?name, ?num, ?mirror, "downloading from mirror");
+                    return Ok(resp);
+                }
+                Ok(resp) => {
+                    tracing::warn!(?name, ?num, ?mirror, status = ?resp.status(), "mirror failed; trying next mirror");
+                }
+                Err(e) => {
+                    tracing::warn!(?name, ?num, ?mirror, %e, "mirror failed; trying next mirror");
+                }
+            }
+        }
+
+        let resp = request(&mirror_url(last, name, num)).send()?;
+        tracing::debug!(?name, ?num, mirror = ?last, "downloading from mirror");
+        Ok(resp)
+    }
+
+    fn fetch(&self, name: &str, num: &str) -> Result<Vec<u8>, Error> {
+        let resp = self
+            .send(name, num, |url| self.client.get(url))?
+            .error_for_status()?;
+        let encoding = content_encoding(&resp);
+        let mut data = Vec::new();
+        self.throttle(resp).read_to_end(&mut data)?;
+        decode_content(encoding.as_deref(), data)
+    }
+
+    /// Wraps a response body so that reading it counts against the bandwidth limit, if any.
+    fn throttle(&self, resp: Response) -> Throttled<Response> {
+        Throttled::new(resp, self.bandwidth_limit.as_ref())
+    }
+
+    /// Downloads to the given partial file, resuming from wherever a previous attempt left off,
+    /// and returns the complete file.
+    ///
+    /// The caller is responsible for verifying the result and removing the partial file.
+    #[tracing::instrument(skip(self), err)]
+    fn fetch_resumable(&self, name: &str, num: &str, partial: &Path) -> Result<Vec<u8>, Error> {
+        if let Some(parent) = partial.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let offset = match std::fs::metadata(partial) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+
+        if offset > 0 {
+            tracing::info!(?offset, "resuming partial download");
+        }
+        // Every mirror serves the same bytes, so a download can be resumed from a different
+        // mirror than it was started from.
+        let resp = self.send(name, num, |url| {
+            let req = self.client.get(url);
+            if offset > 0 {
+                req.header(RANGE, format!("bytes={offset}-"))
+            } else {
+                req
+            }
+        })?;
+
+        // If the range can't be satisfied, then the previous attempt most likely got the whole
+        // file but was interrupted before it could be verified. If not, verification will fail
+        // and the partial file will be removed.
+        if resp.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+            let resp = resp.error_for_status()?;
+            // Only a 206 for the range we asked for can be appended; a 200 is the whole file, and
+            // is written over the partial file below.
+            let resumed = resp.status() == StatusCode::PARTIAL_CONTENT;
+            if resumed && (offset == 0 || content_range_start(&resp) != Some(offset)) {
+                let range = resp
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .map(|value| value.to_str().unwrap_or_default().to_string());
+                remove_if_exists(partial)?;
+                if offset == 0 {
+                    return Err(Error::UnexpectedContentRange(range));
+                }
+
+                tracing::warn!(
+                    ?offset,
+                    ?range,
+                    "unexpected range in response; restarting download"
+                );
+                return self.fetch_resumable(name, num, partial);
+            }
+
+            let encoding = content_encoding(&resp);
+            let mut resp = self.throttle(resp);
+
+            match encoding {
+                Some(encoding) if !matches!(encoding.trim(), "" | "identity") => {
+                    // Byte ranges of an encoded body can't be appended to a decoded file, so
+                    // start again from scratch next time.
+                    if resumed {
+                        remove_if_exists(partial)?;
+                        return Err(Error::UnsupportedContentEncoding(encoding));
+                    }
+
+                    let mut data = Vec::new();
+                    resp.read_to_end(&mut data)?;
+                    let data = decode_content(Some(&encoding), data)?;
+                    std::fs::write(partial, data)?;
+                }
+                _ => {
+                    let mut file = if resumed {
+                        OpenOptions::new().append(true).open(partial)?
+                    } else {
+                        File::create(partial)?
+                    };
+                    std::io::copy(&mut resp, &mut file)?;
+                }
+            }
+        }
+
+        Ok(std::fs::read(partial)?)
+    }
+}
+
+/// A writer that hashes everything written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Returns the inner writer, along with the hex encoded SHA-256 checksum of what was written.
+    fn finish(self) -> (W, String) {
+        (self.inner, format!("{:x}", self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader that counts the bytes read through it.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+/// Returns the first byte position of a `Content-Range: bytes <start>-<end>/<size>` header.
+fn content_range_start(resp: &Response) -> Option<u64> {
+    let range = resp.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (start, _) = range.trim().strip_prefix("bytes ")?.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+fn content_encoding(resp: &Response) -> Option<String> {
+    resp.headers()
+        .get(CONTENT_ENCODING)
+        .map(|value| value.to_str().unwrap_or_default().to_string())
+}
+
+/// Returns the headers sent with every download.
+pub(crate) fn default_headers(contact: Option<&str>) -> Result<HeaderMap, Error> {
+    // .crate files are already gzipped, so there's no point in another layer of compression, and
+    // it would make byte ranges useless for resuming downloads. reqwest is built without its
+    // decompression features, so if a proxy adds one anyway we'll handle it in download().
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+    if let Some(contact) = contact {
+        headers.insert(FROM, HeaderValue::from_str(contact)?);
+    }
+
+    Ok(headers)
+}
+
+pub(crate) fn mirror_url(mirror: &str, name: &str, num: &str) -> String {
+    format!("{}/{name}/{name}-{num}.crate", mirror.trim_end_matches('/'))
+}
+
+/// Returns true if a response with the given status is worth retrying on another mirror.
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::NOT_FOUND
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+/// Removes the given file, ignoring it if it doesn't exist.
+fn remove_if_exists(path: &Path) -> Result<(), Error> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Removes any HTTP content encoding from a response body, leaving just the `.crate` file.
+pub(crate) fn decode_content(encoding: Option<&str>, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::new();
+    match encoding.map(str::trim) {
+        None | Some("") | Some("identity") => {
+            return Ok(data);
+        }
+        Some("gzip") | Some("x-gzip") => {
+            GzDecoder::new(data.as_slice()).read_to_end(&mut decoded)?
+        }
+        Some("deflate") => ZlibDecoder::new(data.as_slice()).read_to_end(&mut decoded)?,
+        Some(encoding) => {
+            return Err(Error::UnsupportedContentEncoding(encoding.to_string()));
+        }
+    };
+
+    Ok(decoded)
+}
+
+/// Returns the hex encoded SHA-256 digest of the given data, as used in the index `cksum` field.
+pub fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Like [`sha256`], but hashes the data as it is read, through a fixed-size buffer of
+/// `buffer_size` bytes.
+pub(crate) fn sha256_reader<R: Read>(mut reader: R, buffer_size: usize) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; buffer_size];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("checksum mismatch for {name} {num}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        name: String,
+        num: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("invalid header value: {0:?}")]
+    InvalidHeaderValue(#[from] InvalidHeaderValue),
+
+    #[error("io error: {0:?}")]
+    Io(#[from] std::io::Error),
+
+    #[error("reqwest error: {0:?}")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("unexpected partial content with range {0:?}")]
+    UnexpectedContentRange(Option<String>),
+
+    #[error("unsupported content encoding: {0:?}")]
+    UnsupportedContentEncoding(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_mirror_fallback() -> anyhow::Result<()> {
+        let krate = gzip(b"not really a tarball")?;
+        let missing = serve_once(b"HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n".to_vec())?;
+        let mut found =
+            format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", krate.len()).into_bytes();
+        found.extend_from_slice(&krate);
+        let found = serve_once(found)?;
+
+        // Nothing should be listening on the discard port, so the first mirror can't be reached.
+        let downloader = Downloader::new()?.with_mirrors(vec![
+            "http://127.0.0.1:9".to_string(),
+            missing,
+            format!("{found}/"),
+        ]);
+        assert_that!(
+            downloader.download("foo", "1.0.0", Some(&sha256(&krate)))?,
+            eq(krate.as_slice())
+        );
+
+        assert_that!(
+            mirror_url("https://example.com/crates/", "foo", "1.0.0"),
+            eq("https://example.com/crates/foo/foo-1.0.0.crate")
+        );
+        assert_that!(is_retryable_status(StatusCode::NOT_FOUND), eq(true));
+        assert_that!(is_retryable_status(StatusCode::BAD_GATEWAY), eq(true));
+        assert_that!(is_retryable_status(StatusCode::FORBIDDEN), eq(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify() -> anyhow::Result<()> {
+        let krate = gzip(b"not really a tarball")?;
+        let mut response =
+            format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", krate.len()).into_bytes();
+        response.extend_from_slice(&krate);
+
+        let downloader = Downloader::new()?.with_mirrors(vec![serve_once(response)?]);
+        assert_that!(
+            downloader.verify("foo", "1.0.0", &sha256(&krate))?,
+            eq(krate.len() as u64)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_buffer_size() -> anyhow::Result<()> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let source: Vec<u8> = (0..256 * 1024).map(|i: u32| (i * 7 % 251) as u8).collect();
+        for (path, data) in [
+            (
+                "foo-1.0.0/Cargo.toml",
+                &b"[package]\nname = \"foo\"\nversion = \"1.0.0\"\n"[..],
+            ),
+            ("foo-1.0.0/src/lib.rs", &source),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data)?;
+        }
+        let krate = gzip(&builder.into_inner()?)?;
+        let mut response =
+            format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", krate.len()).into_bytes();
+        response.extend_from_slice(&krate);
+
+        // The buffer is much smaller than the tarball, so it's hashed over many reads.
+        let downloader = Downloader::new()?
+            .with_mirrors(vec![serve(vec![response.clone(), response])?])
+            .with_verify_buffer_size(512);
+        assert_that!(
+            downloader.verify("foo", "1.0.0", &sha256(&krate))?,
+            eq(krate.len() as u64)
+        );
+        assert_that!(
+            downloader.verify("foo", "1.0.0", &sha256(b"")),
+            err(matches_pattern!(Error::ChecksumMismatch { .. }))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_to_file_cache() -> anyhow::Result<()> {
+        let krate = gzip(b"not really a tarball")?;
+        let cksum = sha256(&krate);
+        let mut response =
+            format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", krate.len()).into_bytes();
+        response.extend_from_slice(&krate);
+        let temp = tempfile::tempdir()?;
+        let cache_dir = temp.path().join("cache");
+        let cache = TarballCache::new(cache_dir.clone())?;
+
+        let downloader = Downloader::new()?
+            .with_mirrors(vec![serve_once(response.clone())?])
+            .with_tarball_cache(TarballCache::new(cache_dir.clone())?);
+        let file = downloader.download_to_file("foo", "1.0.0", &cksum, temp.path())?;
+        assert_that!(std::fs::read(file.path())?, eq(&krate));
+        assert_that!(cache.get("foo", "1.0.0")?, some(eq(&krate)));
+
+        // Once cached, the mirror isn't needed.
+        let downloader = Downloader::new()?
+            .with_mirrors(vec!["http://127.0.0.1:9".to_string()])
+            .with_tarball_cache(TarballCache::new(cache_dir.clone())?);
+        let file = downloader.download_to_file("foo", "1.0.0", &cksum, temp.path())?;
+        assert_that!(std::fs::read(file.path())?, eq(&krate));
+
+        // A corrupt cache entry is thrown away and downloaded again.
+        cache.insert("foo", "1.0.0", b"corrupt")?;
+        let downloader = Downloader::new()?
+            .with_mirrors(vec![serve_once(response)?])
+            .with_tarball_cache(TarballCache::new(cache_dir.clone())?);
+        let file = downloader.download_to_file("foo", "1.0.0", &cksum, temp.path())?;
+        assert_that!(std::fs::read(file.path())?, eq(&krate));
+        assert_that!(cache.get("foo", "1.0.0")?, some(eq(&krate)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume() -> anyhow::Result<()> {
+        let krate = gzip(&b"not really a tarball".repeat(100))?;
+        let (head, tail) = krate.split_at(krate.len() / 2);
+        let temp = tempfile::tempdir()?;
+        let partial = temp.path().join("foo-1.0.0.crate.part");
+
+        // A 206 for the requested range is appended to what's already there.
+        let mut response = format!(
+            "HTTP/1.1 206 Partial Content\r\ncontent-range: bytes {}-{}/{}\r\ncontent-length: {}\r\n\r\n",
+            head.len(),
+            krate.len() - 1,
+            krate.len(),
+            tail.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(tail);
+        std::fs::write(&partial, head)?;
+        let downloader = Downloader::new()?
+            .with_mirrors(vec![serve_once(response)?])
+            .with_partial_dir(temp.path().to_path_buf());
+        assert_that!(
+            downloader.download("foo", "1.0.0", Some(&sha256(&krate)))?,
+            eq(krate.as_slice())
+        );
+        assert_that!(partial.exists(), eq(false));
+
+        // A 200 is the whole file, even though a range was requested.
+        let mut found =
+            format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", krate.len()).into_bytes();
+        found.extend_from_slice(&krate);
+        std::fs::write(&partial, head)?;
+        let downloader = Downloader::new()?
+            .with_mirrors(vec![serve_once(found.clone())?])
+            .with_partial_dir(temp.path().to_path_buf());
+        assert_that!(
+            downloader.download("foo", "1.0.0", Some(&sha256(&krate)))?,
+            eq(krate.as_slice())
+        );
+
+        // A 206 for some other range is thrown away, and the download started again.
+        let mut response = format!(
+            "HTTP/1.1 206 Partial Content\r\ncontent-range: bytes 0-{}/{}\r\ncontent-length: {}\r\n\r\n",
+            tail.len() - 1,
+            krate.len(),
+            tail.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&krate[..tail.len()]);
+        std::fs::write(&partial, head)?;
+        let downloader = Downloader::new()?
+            .with_mirrors(vec![serve(vec![response, found])?])
+            .with_partial_dir(temp.path().to_path_buf());
+        assert_that!(
+            downloader.download("foo", "1.0.0", Some(&sha256(&krate)))?,
+            eq(krate.as_slice())
+        );
+        assert_that!(partial.exists(), eq(false));
+
+        Ok(())
+    }
+
+    /// Serves a single canned HTTP response on a local port, returning its base URL.
+    fn serve_once(response: Vec<u8>) -> anyhow::Result<String> {
+        serve(vec![response])
+    }
+
+    /// Serves each canned HTTP response in turn, one per connection, on a local port, returning
+    /// its base URL.
+    fn serve(responses: Vec<Vec<u8>>) -> anyhow::Result<String> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}", listener.local_addr()?);
+        std::thread::spawn(move || -> std::io::Result<()> {
+            for response in responses {
+                let (mut stream, _) = listener.accept()?;
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                stream.write_all(&response)?;
+            }
+            Ok(())
+        });
+        Ok(url)
+    }
+
+    #[test]
+    fn test_decode_content() -> anyhow::Result<()> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(10);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "foo-1.0.0/Cargo.toml", &b"[package]\n"[..])?;
+        let krate = gzip(&builder.into_inner()?)?;
+
+        // A proxy that gzips the response body on the fly.
+        let proxied = gzip(&krate)?;
+
+        assert_that!(decode_content(Some("gzip"), proxied)?, eq(krate.as_slice()));
+        assert_that!(decode_content(None, krate.clone())?, eq(krate.as_slice()));
+        assert_that!(
+            decode_content(Some("identity"), krate.clone())?,
+            eq(krate.as_slice())
+        );
+        assert_that!(
+            decode_content(Some("br"), krate.clone()),
+            err(matches_pattern!(Error::UnsupportedContentEncoding(eq(
+                "br"
+            ))))
+        );
+
+        // The decoded body is the .crate file itself, not the tarball inside it.
+        let decoded = decode_content(Some("gzip"), gzip(&krate)?)?;
+        let mut archive = tar::Archive::new(GzDecoder::new(decoded.as_slice()));
+        let paths = archive
+            .entries()?
+            .map(|entry| Ok(entry?.path()?.into_owned()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_that!(
+            paths,
+            elements_are![eq(std::path::Path::new("foo-1.0.0/Cargo.toml"))]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_encoding() -> anyhow::Result<()> {
+        let krate = gzip(&b"not really a tarball".repeat(100))?;
+        let cksum = sha256(&krate);
+
+        // A proxy that gzips the response body on the fly.
+        let proxied = gzip(&krate)?;
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-encoding: gzip\r\ncontent-length: {}\r\n\r\n",
+            proxied.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&proxied);
+
+        let temp = tempfile::tempdir()?;
+        let downloader = Downloader::new()?.with_mirrors(vec![serve(vec![
+            response.clone(),
+            response.clone(),
+            response,
+        ])?]);
+        assert_that!(
+            downloader.download("foo", "1.0.0", Some(&cksum))?,
+            eq(krate.as_slice())
+        );
+        let file = downloader.download_to_file("foo", "1.0.0", &cksum, temp.path())?;
+        assert_that!(std::fs::read(file.path())?, eq(&krate));
+        assert_that!(
+            downloader.verify("foo", "1.0.0", &cksum)?,
+            eq(proxied.len() as u64)
+        );
+
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-encoding: br\r\ncontent-length: {}\r\n\r\n",
+            krate.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&krate);
+        let downloader = Downloader::new()?.with_mirrors(vec![serve_once(response)?]);
+        assert_that!(
+            downloader.download("foo", "1.0.0", Some(&cksum)),
+            err(matches_pattern!(Error::UnsupportedContentEncoding(eq(
+                "br"
+            ))))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sha256_reader() -> anyhow::Result<()> {
+        let data = b"not really a tarball".repeat(1024);
+        assert_that!(
+            sha256_reader(data.as_slice(), DEFAULT_VERIFY_BUFFER_SIZE)?,
+            eq(sha256(&data))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sha256_reader_bounded() -> anyhow::Result<()> {
+        // Input that is generated as it is read, so it is never in memory as a whole.
+        const LEN: u64 = 1024 * 1024;
+        let mut reader = MaxReadRecorder {
+            inner: std::io::repeat(b'x').take(LEN),
+            max_read: 0,
+            total: 0,
+        };
+
+        let mut expected = Sha256::new();
+        let chunk = [b'x'; 4096];
+        for _ in 0..(LEN / chunk.len() as u64) {
+            expected.update(chunk);
+        }
+
+        assert_that!(
+            sha256_reader(&mut reader, 1024)?,
+            eq(format!("{:x}", expected.finalize()))
+        );
+        assert_that!(reader.total, eq(LEN));
+        assert_that!(reader.max_read, le(1024));
+
+        Ok(())
+    }
+
+    /// Records the largest read requested from the inner reader.
+    struct MaxReadRecorder<R> {
+        inner: R,
+        max_read: usize,
+        total: u64,
+    }
+
+    impl<R: Read> Read for MaxReadRecorder<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.max_read = self.max_read.max(buf.len());
+            let n = self.inner.read(buf)?;
+            self.total += n as u64;
+            Ok(n)
+        }
+    }
+
+    fn gzip(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+}
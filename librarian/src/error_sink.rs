@@ -0,0 +1,109 @@
+use std::{fmt, io::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::jsonl::JsonlWriter;
+
+/// Somewhere to report per-crate errors from corpus-wide operations as JSON lines, independently
+/// of any tracing subscriber.
+///
+/// Each error is written as a single object with `crate`, `version`, `operation`, and `error`
+/// fields; `crate` and `version` are `null` when the error couldn't be attributed to a crate
+/// version.
+#[derive(Default)]
+pub struct ErrorSink(Option<JsonlWriter>);
+
+impl ErrorSink {
+    /// Returns a sink that discards everything.
+    pub fn none() -> Self {
+        Self(None)
+    }
+
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Self(Some(JsonlWriter::new("error sink", writer)))
+    }
+
+    /// Opens a sink that writes to the given file, or to stderr if the path is `-`.
+    #[tracing::instrument(err)]
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(Self(Some(JsonlWriter::open("error sink", path)?)))
+    }
+
+    /// Returns true if errors are being written anywhere.
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Writes an error to the sink.
+    ///
+    /// Failing to write to the sink isn't fatal to the operation being reported on, so write
+    /// errors are only logged.
+    pub fn record(
+        &self,
+        krate: Option<&str>,
+        version: Option<&str>,
+        operation: &str,
+        error: &dyn fmt::Display,
+    ) {
+        let Some(writer) = &self.0 else {
+            return;
+        };
+
+        writer.write(&Record {
+            krate,
+            version,
+            operation,
+            error: error.to_string(),
+        });
+    }
+}
+
+impl fmt::Debug for ErrorSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ErrorSink")
+            .field(&self.is_enabled())
+            .finish()
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(rename = "crate")]
+    krate: Option<&'a str>,
+    version: Option<&'a str>,
+    operation: &'a str,
+    error: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::jsonl::tests::Shared;
+
+    #[test]
+    fn test_record() -> anyhow::Result<()> {
+        let buf = Shared::default();
+        let sink = ErrorSink::new(buf.clone());
+        sink.record(Some("foo"), Some("1.0.0"), "populate", &"bad \"tarball\"");
+        sink.record(None, None, "list", &"unreadable");
+
+        assert_that!(
+            buf.contents()?,
+            eq(concat!(
+                r#"{"crate":"foo","version":"1.0.0","operation":"populate","error":"bad \"tarball\""}"#,
+                "\n",
+                r#"{"crate":null,"version":null,"operation":"list","error":"unreadable"}"#,
+                "\n",
+            ))
+        );
+
+        ErrorSink::none().record(None, None, "list", &"ignored");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use semver::{Version, VersionReq};
+
+/// Filters crate versions by their semantic version.
+///
+/// All criteria are intersected: a version must satisfy every filter that is set to be included.
+#[derive(Debug, Default, Clone)]
+pub struct VersionFilter {
+    /// Exclude versions lower than this.
+    pub min_version: Option<Version>,
+
+    /// Exclude versions with a pre-release segment.
+    pub stable_only: bool,
+
+    /// Per-crate version requirements, keyed by the lowercased crate name, since crate names are
+    /// matched case-insensitively.
+    pub requirements: HashMap<String, VersionReq>,
+}
+
+impl VersionFilter {
+    /// Returns true if the given version of the given crate should be included.
+    ///
+    /// If any filter applies to the crate, versions that aren't valid semver are excluded with a
+    /// warning.
+    pub fn matches(&self, name: &str, num: &str) -> bool {
+        let req = self.requirements.get(&name.to_ascii_lowercase());
+        if req.is_none() && self.min_version.is_none() && !self.stable_only {
+            return true;
+        }
+
+        let version = match Version::parse(num) {
+            Ok(version) => version,
+            Err(e) => {
+                tracing::warn!(?name, ?num, ?e, "excluding version that isn't valid semver");
+                return false;
+            }
+        };
+
+        if self.stable_only && !version.pre.is_empty() {
+            return false;
+        }
+
+        if matches!(&self.min_version, Some(min) if &version < min) {
+            return false;
+        }
+
+        req.is_none_or(|req| req.matches(&version))
+    }
+}
+
+/// Filters crate versions by their declared minimum supported Rust version.
+#[derive(Debug, Default, Clone)]
+pub struct MsrvFilter {
+    /// Exclude versions with an MSRV lower than this.
+    pub min: Option<Version>,
+
+    /// Exclude versions with an MSRV higher than this.
+    pub max: Option<Version>,
+
+    /// Exclude versions that don't declare an MSRV.
+    pub require: bool,
+}
+
+impl MsrvFilter {
+    /// Returns true if a version with the given `rust_version` should be included.
+    ///
+    /// If a bound is set, versions with an MSRV that can't be parsed are excluded with a warning.
+    pub fn matches(&self, rust_version: Option<&str>) -> bool {
+        let Some(rust_version) = rust_version else {
+            return !self.require;
+        };
+        if self.min.is_none() && self.max.is_none() {
+            return true;
+        }
+
+        let Some(msrv) = parse_rust_version(rust_version) else {
+            tracing::warn!(
+                ?rust_version,
+                "excluding version with an invalid rust-version"
+            );
+            return false;
+        };
+
+        !matches!(&self.min, Some(min) if &msrv < min)
+            && !matches!(&self.max, Some(max) if &msrv > max)
+    }
+}
+
+/// Parses a `rust-version`, which (unlike semver) may omit the minor and patch components.
+pub fn parse_rust_version(s: &str) -> Option<Version> {
+    let mut parts = s.trim().split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().map_or(Ok(0), str::parse).ok()?;
+    let patch = parts.next().map_or(Ok(0), str::parse).ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(Version::new(major, minor, patch))
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_matches() -> anyhow::Result<()> {
+        let filter = VersionFilter::default();
+        assert_that!(filter.matches("foo", "0.1.0-alpha.1"), eq(true));
+        assert_that!(filter.matches("foo", "not semver"), eq(true));
+
+        let filter = VersionFilter {
+            min_version: Some(Version::parse("1.0.0")?),
+            stable_only: true,
+            requirements: HashMap::from([("foo".to_string(), VersionReq::parse("<2")?)]),
+        };
+        assert_that!(filter.matches("foo", "1.2.3"), eq(true));
+        assert_that!(filter.matches("foo", "2.0.0"), eq(false));
+        assert_that!(filter.matches("Foo", "2.0.0"), eq(false));
+        assert_that!(filter.matches("foo", "0.9.0"), eq(false));
+        assert_that!(filter.matches("bar", "2.0.0"), eq(true));
+        assert_that!(filter.matches("bar", "2.0.0-rc.1"), eq(false));
+        assert_that!(filter.matches("bar", "not semver"), eq(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_msrv_matches() {
+        let filter = MsrvFilter::default();
+        assert_that!(filter.matches(None), eq(true));
+        assert_that!(filter.matches(Some("nonsense")), eq(true));
+
+        let filter = MsrvFilter {
+            min: parse_rust_version("1.56"),
+            max: parse_rust_version("1.70"),
+            require: false,
+        };
+        assert_that!(filter.matches(None), eq(true));
+        assert_that!(filter.matches(Some("1.56")), eq(true));
+        assert_that!(filter.matches(Some("1.70.1")), eq(false));
+        assert_that!(filter.matches(Some("1.31")), eq(false));
+        assert_that!(filter.matches(Some("nonsense")), eq(false));
+
+        let filter = MsrvFilter {
+            require: true,
+            ..MsrvFilter::default()
+        };
+        assert_that!(filter.matches(None), eq(false));
+        assert_that!(filter.matches(Some("1.0")), eq(true));
+    }
+
+    #[test]
+    fn test_parse_rust_version() {
+        assert_that!(parse_rust_version("1"), some(eq(Version::new(1, 0, 0))));
+        assert_that!(parse_rust_version("1.70"), some(eq(Version::new(1, 70, 0))));
+        assert_that!(
+            parse_rust_version("1.70.1"),
+            some(eq(Version::new(1, 70, 1)))
+        );
+        assert_that!(parse_rust_version("1.70.1.2"), none());
+        assert_that!(parse_rust_version("1.x"), none());
+    }
+}
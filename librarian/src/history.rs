@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use git2::{FileMode, Oid, Repository, Signature};
+use thiserror::Error;
+
+use crate::index::krate::cmp_version_nums;
+
+/// The repository, relative to a crate's directory in the corpus, that its history is kept in.
+///
+/// The vault doesn't mistake it for a crate version, since it doesn't contain any manifests.
+pub const HISTORY_DIR: &str = ".git";
+
+/// The prefix of the tag recording the commit for each version.
+const TAG_PREFIX: &str = "refs/tags/";
+
+/// Commits each crate version into a bare git repository in its crate's directory, so that
+/// versions can be compared with `git diff` and friends.
+///
+/// Each version is a single commit, tagged with the version number, whose parent is the commit of
+/// the previous version by semver. Since versions can be populated in any order, recording a
+/// version older than the newest already recorded rewrites the commits that follow it; their trees
+/// and tags are kept, so only the commit IDs change.
+#[derive(Debug, Default)]
+pub struct VersionHistory {
+    // Rewriting history isn't atomic, so versions of the same crate are recorded one at a time.
+    // Each crate has its own repository, so different crates don't need to wait for each other.
+    locks: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>,
+}
+
+impl VersionHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the path to the history repository for the crate in `crate_dir`.
+    pub fn repository_path(crate_dir: &Path) -> PathBuf {
+        crate_dir.join(HISTORY_DIR)
+    }
+
+    /// Commits the files in `version_dir` as version `num` of the crate in `crate_dir`, returning
+    /// the new commit.
+    ///
+    /// If the version has already been recorded, its existing commit is returned.
+    #[tracing::instrument(err)]
+    pub fn record(&self, crate_dir: &Path, num: &str, version_dir: &Path) -> Result<Oid, Error> {
+        let lock = self
+            .locks
+            .lock()
+            .expect("history locks")
+            .entry(crate_dir.to_path_buf())
+            .or_default()
+            .clone();
+        let _guard = lock.lock().expect("history lock");
+
+        let path = Self::repository_path(crate_dir);
+        let repo = match Repository::open_bare(&path) {
+            Ok(repo) => repo,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => Repository::init_bare(&path)?,
+            Err(e) => return Err(e.into()),
+        };
+        if let Ok(tag) = repo.find_reference(&format!("{TAG_PREFIX}{num}")) {
+            return Ok(tag.peel_to_commit()?.id());
+        }
+
+        let mut versions = vec![(num.to_string(), write_tree(&repo, version_dir)?)];
+        for name in repo.references_glob(&format!("{TAG_PREFIX}*"))?.names() {
+            let name = name?;
+            let tree = repo.find_reference(name)?.peel_to_tree()?.id();
+            versions.push((name[TAG_PREFIX.len()..].to_string(), tree));
+        }
+        versions.sort_by(|(a, _), (b, _)| cmp_version_nums(a, b));
+
+        // Everything before the new version is unchanged, so history only needs to be written
+        // from there on.
+        let start = versions
+            .iter()
+            .position(|(version, _)| version == num)
+            .expect("new version is in the list");
+        let mut parent = match start {
+            0 => None,
+            _ => Some(
+                repo.find_reference(&format!("{TAG_PREFIX}{}", versions[start - 1].0))?
+                    .peel_to_commit()?,
+            ),
+        };
+        let signature = Signature::now("librarian", "librarian@localhost")?;
+        let mut recorded = None;
+        for (version, tree) in &versions[start..] {
+            let tree = repo.find_tree(*tree)?;
+            let oid = repo.commit(
+                None,
+                &signature,
+                &signature,
+                version,
+                &tree,
+                &parent.iter().collect::<Vec<_>>(),
+            )?;
+            repo.reference(&format!("{TAG_PREFIX}{version}"), oid, true, version)?;
+            if version == num {
+                recorded = Some(oid);
+            }
+            parent = Some(repo.find_commit(oid)?);
+        }
+
+        let head = parent.expect("at least one version was committed").id();
+        repo.reference("refs/heads/main", head, true, num)?;
+        repo.set_head("refs/heads/main")?;
+
+        Ok(recorded.expect("new version was committed"))
+    }
+}
+
+/// Writes the contents of `dir` to the repository as a tree, returning its ID.
+///
+/// Empty directories are omitted, since git can't represent them.
+fn write_tree(repo: &Repository, dir: &Path) -> Result<Oid, Error> {
+    let mut builder = repo.treebuilder(None)?;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let name = entry.file_name();
+
+        if file_type.is_symlink() {
+            let target = std::fs::read_link(entry.path())?;
+            let blob = repo.blob(target.as_os_str().as_encoded_bytes())?;
+            builder.insert(&name, blob, FileMode::Link.into())?;
+        } else if file_type.is_dir() {
+            let tree = write_tree(repo, &entry.path())?;
+            if !repo.find_tree(tree)?.is_empty() {
+                builder.insert(&name, tree, FileMode::Tree.into())?;
+            }
+        } else {
+            let blob = repo.blob_path(&entry.path())?;
+            let mode = if entry.metadata()?.permissions().mode() & 0o111 != 0 {
+                FileMode::BlobExecutable
+            } else {
+                FileMode::Blob
+            };
+            builder.insert(&name, blob, mode.into())?;
+        }
+    }
+
+    Ok(builder.write()?)
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("git error: {0:?}")]
+    Git(#[from] git2::Error),
+
+    #[error("io error: {0:?}")]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_record() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let crate_dir = temp.path().join("f/fo/foo");
+        let version = |num: &str| -> anyhow::Result<PathBuf> {
+            let dir = crate_dir.join(num);
+            std::fs::create_dir_all(dir.join("src"))?;
+            std::fs::write(dir.join("Cargo.toml"), format!("version = \"{num}\"\n"))?;
+            std::fs::write(dir.join("src/lib.rs"), "")?;
+            Ok(dir)
+        };
+
+        let history = VersionHistory::new();
+        history.record(&crate_dir, "1.0.0", &version("1.0.0")?)?;
+        history.record(&crate_dir, "2.0.0", &version("2.0.0")?)?;
+        // Out of order, so 2.0.0 has to be rewritten on top of it.
+        history.record(&crate_dir, "1.1.0", &version("1.1.0")?)?;
+        let again = history.record(&crate_dir, "1.1.0", &crate_dir.join("1.1.0"))?;
+
+        let repo = Repository::open_bare(VersionHistory::repository_path(&crate_dir))?;
+        let mut messages = Vec::new();
+        let mut commit = repo.head()?.peel_to_commit()?;
+        loop {
+            messages.push(commit.message().unwrap_or_default().to_string());
+            match commit.parents().next() {
+                Some(parent) => commit = parent,
+                None => break,
+            }
+        }
+        assert_that!(messages, eq(vec!["2.0.0", "1.1.0", "1.0.0"]));
+        assert_that!(
+            repo.find_reference("refs/tags/1.1.0")?
+                .peel_to_commit()?
+                .id(),
+            eq(again)
+        );
+
+        let tree = repo.find_reference("refs/tags/2.0.0")?.peel_to_tree()?;
+        let manifest = tree.get_path(Path::new("Cargo.toml"))?.to_object(&repo)?;
+        assert_that!(
+            manifest.as_blob().map(|blob| blob.content().to_vec()),
+            some(eq(b"version = \"2.0.0\"\n".to_vec()))
+        );
+
+        Ok(())
+    }
+}
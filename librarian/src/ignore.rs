@@ -0,0 +1,159 @@
+use std::{io::ErrorKind, path::Path};
+
+/// The name of the ignore file read from the root of a corpus.
+pub const IGNORE_FILE: &str = ".librarianignore";
+
+/// A set of patterns for crates and versions that must not be mirrored, read from a
+/// `.librarianignore` file.
+///
+/// Each line is a pattern matched against both `name` and `name@version`, so `foo` ignores every
+/// version of `foo`, while `foo@1.*` only ignores the 1.x versions. `*` matches any number of
+/// characters and `?` matches exactly one. As with `.gitignore`, blank lines and lines starting
+/// with `#` are ignored, a leading `!` re-includes anything matched by an earlier pattern, and
+/// the last matching pattern wins.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreList {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    glob: String,
+    negated: bool,
+}
+
+impl IgnoreList {
+    /// Loads the ignore file from the root of the given corpus, if it has one.
+    pub fn for_corpus(corpus: &Path) -> std::io::Result<Self> {
+        Self::load(&corpus.join(IGNORE_FILE))
+    }
+
+    /// Loads an ignore file, returning an empty list if it doesn't exist.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        Self {
+            patterns: content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| match line.strip_prefix('!') {
+                    Some(glob) => Pattern {
+                        glob: lowercase_name(glob),
+                        negated: true,
+                    },
+                    None => Pattern {
+                        glob: lowercase_name(line),
+                        negated: false,
+                    },
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns true if the given crate version must not be mirrored.
+    ///
+    /// Crate names are matched case insensitively, as they are on crates.io.
+    pub fn is_ignored(&self, name: &str, num: &str) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+
+        let name = name.to_ascii_lowercase();
+        let versioned = format!("{name}@{num}");
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| {
+                glob_matches(&pattern.glob, &name) || glob_matches(&pattern.glob, &versioned)
+            })
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// Lowercases the crate name part of a pattern, leaving any version as it is, since only names
+/// are case insensitive.
+fn lowercase_name(glob: &str) -> String {
+    match glob.split_once('@') {
+        Some((name, version)) => format!("{}@{version}", name.to_ascii_lowercase()),
+        None => glob.to_ascii_lowercase(),
+    }
+}
+
+/// Matches a string against a glob supporting `*` and `?`.
+pub fn glob_matches(glob: &str, s: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+
+    // The usual greedy matcher with backtracking to the most recent `*`.
+    let (mut g, mut i) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while i < s.len() {
+        if g < glob.len() && (glob[g] == '?' || glob[g] == s[i]) {
+            g += 1;
+            i += 1;
+        } else if g < glob.len() && glob[g] == '*' {
+            star = Some((g, i));
+            g += 1;
+        } else if let Some((star_g, star_i)) = star {
+            g = star_g + 1;
+            i = star_i + 1;
+            star = Some((star_g, star_i + 1));
+        } else {
+            return false;
+        }
+    }
+
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_is_ignored() {
+        let ignore = IgnoreList::parse(
+            r#"
+            # Never mirror these.
+            evil
+            bad-*
+            foo@1.*
+            Pre@1.0.0-RC1
+            !bad-but-ok
+            "#,
+        );
+
+        assert_that!(ignore.is_ignored("evil", "0.1.0"), eq(true));
+        assert_that!(ignore.is_ignored("Evil", "0.1.0"), eq(true));
+        assert_that!(ignore.is_ignored("evil-twin", "0.1.0"), eq(false));
+        assert_that!(ignore.is_ignored("bad-crate", "2.0.0"), eq(true));
+        assert_that!(ignore.is_ignored("bad-but-ok", "2.0.0"), eq(false));
+        assert_that!(ignore.is_ignored("foo", "1.2.3"), eq(true));
+        assert_that!(ignore.is_ignored("foo", "0.9.0"), eq(false));
+        assert_that!(ignore.is_ignored("pre", "1.0.0-RC1"), eq(true));
+        assert_that!(ignore.is_ignored("pre", "1.0.0-rc1"), eq(false));
+        assert_that!(IgnoreList::default().is_ignored("evil", "0.1.0"), eq(false));
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert_that!(glob_matches("a*c", "abbbc"), eq(true));
+        assert_that!(glob_matches("a*c", "abbbd"), eq(false));
+        assert_that!(glob_matches("a?c", "abc"), eq(true));
+        assert_that!(glob_matches("*", ""), eq(true));
+        assert_that!(glob_matches("a*b*c", "aXbYbZc"), eq(true));
+    }
+}
@@ -1,27 +1,68 @@
 use std::{
     ffi::OsString,
-    io::ErrorKind,
+    io::{BufRead, ErrorKind, Read},
     os::unix::prelude::OsStrExt,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
 };
 
 use git2::{
-    build::CheckoutBuilder, BranchType, FetchOptions, RemoteCallbacks, Repository, ResetType,
-};
-use indicatif::{
-    MultiProgress, ParallelProgressIterator, ProgressBar, ProgressIterator, ProgressStyle,
+    build::CheckoutBuilder, BranchType, ErrorCode, FetchOptions, RemoteCallbacks, Repository,
+    ResetType,
 };
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use thiserror::Error;
+use vault::CrateName;
 use walkdir::WalkDir;
 
-use self::krate::Krate;
+pub use self::{batch::IndexBatch, pack::CacheStats};
+use self::{krate::Krate, pack::Pack, remote::Remote};
+use crate::{progress::Progress, telemetry};
 
+pub mod api;
+mod batch;
+pub mod cargo_config;
+mod changes;
 pub mod krate;
+mod pack;
+pub mod remote;
+mod resolve;
+mod snapshot;
+
+/// The crates returned by [`Index::all_until`].
+#[derive(Debug)]
+pub struct PartialCrates {
+    pub crates: Vec<Result<Krate, Error>>,
+
+    /// True if the walk was stopped early, in which case `crates` only covers part of the index.
+    pub interrupted: bool,
+}
+
+/// What [`Index::all`] and friends do with index files whose names aren't valid UTF-8.
+///
+/// These can't exist in the crates.io index, but might in an arbitrary mirror.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidNamePolicy {
+    /// Skip the file, logging a warning.
+    #[default]
+    Skip,
+
+    /// Yield [`Error::InvalidCrateName`] for the file.
+    Error,
+}
 
 #[derive(Clone, Debug)]
-pub struct Index(Arc<PathBuf>);
+pub struct Index {
+    path: Arc<PathBuf>,
+    invalid_names: InvalidNamePolicy,
+    /// The sorted names of the crates in the index, once [`Index::crate_names`] has walked for
+    /// them. Shared between clones, and cleared when the index is updated.
+    names: Arc<RwLock<Option<Vec<String>>>>,
+    pack: Arc<Pack>,
+}
 
 impl Index {
     #[tracing::instrument(err)]
@@ -34,93 +75,468 @@ impl Index {
                 {
                     Repository::init(path)?;
                 }
-                Ok(Self(Arc::new(std::fs::canonicalize(path)?)))
+                Ok(Self::from_path(std::fs::canonicalize(path)?))
             }
             Ok(_) => Err(Error::NotADirectory(path.into())),
             Err(e) if e.kind() == ErrorKind::NotFound => {
                 Repository::init(path)?;
-                Ok(Self(Arc::new(std::fs::canonicalize(path)?)))
+                Ok(Self::from_path(std::fs::canonicalize(path)?))
             }
             Err(e) => Err(e.into()),
         }
     }
 
-    #[tracing::instrument]
-    pub fn all(&self) -> impl Iterator<Item = Result<Krate, Error>> + '_ {
-        let progress = ProgressBar::new(0).with_style(
-            ProgressStyle::with_template("Discovering crates: {pos}").expect("bar template"),
-        );
-        let names: Vec<Result<String, Error>> = WalkDir::new(self.0.as_path())
+    fn from_path(path: PathBuf) -> Self {
+        Self {
+            pack: Arc::new(Pack::new(path.join(pack::PACK_FILE))),
+            path: Arc::new(path),
+            invalid_names: InvalidNamePolicy::default(),
+            names: Arc::default(),
+        }
+    }
+
+    /// Sets what happens to index files whose names aren't valid UTF-8 when walking the index.
+    ///
+    /// By default, they're skipped with a warning.
+    pub fn with_invalid_name_policy(mut self, policy: InvalidNamePolicy) -> Self {
+        self.invalid_names = policy;
+        self
+    }
+
+    /// Returns the sorted names of the crates in the index, without parsing any index files.
+    ///
+    /// The index is only walked the first time this is called: the names are then cached until
+    /// the index is next updated.
+    #[tracing::instrument(err)]
+    pub fn crate_names(&self) -> Result<Vec<String>, Error> {
+        if let Some(names) = self.names.read().expect("crate name cache lock").as_ref() {
+            return Ok(names.clone());
+        }
+
+        let mut names = self.walk_crate_names().collect::<Result<Vec<_>, _>>()?;
+        names.sort();
+        *self.names.write().expect("crate name cache lock") = Some(names.clone());
+
+        Ok(names)
+    }
+
+    #[tracing::instrument(skip(progress))]
+    pub fn all<'a>(
+        &'a self,
+        progress: &dyn Progress,
+    ) -> impl Iterator<Item = Result<Krate, Error>> + 'a {
+        self.all_until(&AtomicBool::new(false), progress)
+            .crates
+            .into_iter()
+    }
+
+    /// Like [`Index::all`], but stops early once `stop` is set, returning whatever had been
+    /// parsed by then.
+    ///
+    /// If the flag is set while the index is being walked, the crates discovered so far are still
+    /// parsed; if it's set while they're being parsed, no more are started. Either way, the
+    /// crates that were parsed are returned in the order they were discovered.
+    pub fn all_until(&self, stop: &AtomicBool, progress: &dyn Progress) -> PartialCrates {
+        self.all_matching(stop, &|_name| true, progress)
+    }
+
+    /// Like [`Index::all_until`], but only parses the crates whose names `keep` returns true for,
+    /// so that crates that would be thrown away anyway are never read.
+    #[tracing::instrument(skip(stop, keep, progress))]
+    pub fn all_matching(
+        &self,
+        stop: &AtomicBool,
+        keep: &(dyn Fn(&str) -> bool + Sync),
+        progress: &dyn Progress,
+    ) -> PartialCrates {
+        progress.start("Discovering crates", None);
+        let names: Vec<Result<String, Error>> = self
+            .walk_crate_names()
+            .take_while(|_| !stop.load(Ordering::Relaxed))
+            .inspect(|_| progress.advance(1))
+            .filter(|result| result.as_ref().map_or(true, |name| keep(name)))
+            .collect();
+        progress.finish();
+
+        // If the walk was interrupted, then parsing what was found is the point, so the flag is
+        // only checked while parsing if it wasn't set beforehand.
+        let walk_interrupted = stop.load(Ordering::Relaxed);
+        progress.start("Parsing indices", Some(names.len() as u64));
+        let crates: Vec<_> = names
+            .into_par_iter()
+            .filter_map(|result| {
+                if !walk_interrupted && stop.load(Ordering::Relaxed) {
+                    return None;
+                }
+
+                let result = result.and_then(|name| self.read_krate(&name));
+                progress.advance(1);
+                Some(result)
+            })
+            .collect();
+        progress.finish();
+
+        PartialCrates {
+            crates,
+            interrupted: stop.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Like [`Index::all`], but walks and parses the index in chunks of `chunk_size` crates as
+    /// the iterator is consumed, rather than all at once.
+    ///
+    /// Only one chunk is held in memory at a time, so peak memory use depends on the chunk size
+    /// rather than the size of the index. Since the total isn't known up front, `progress` is
+    /// given a single phase without a total.
+    #[tracing::instrument(skip(progress))]
+    pub fn all_chunked<'a>(
+        &'a self,
+        chunk_size: usize,
+        progress: &'a dyn Progress,
+    ) -> impl Iterator<Item = Result<Krate, Error>> + 'a {
+        progress.start("Parsing indices", None);
+        let mut names = self.walk_crate_names();
+        std::iter::from_fn(move || {
+            let chunk: Vec<_> = names.by_ref().take(chunk_size.max(1)).collect();
+            if chunk.is_empty() {
+                progress.finish();
+                return None;
+            }
+
+            Some(
+                chunk
+                    .into_par_iter()
+                    .map(|result| {
+                        let result = result.and_then(|name| self.read_krate(&name));
+                        progress.advance(1);
+                        result
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .fuse()
+        .flatten()
+    }
+
+    /// Walks the index for the names of the crates in it, followed by the names of any crates that
+    /// are only in the pack.
+    fn walk_crate_names(&self) -> impl Iterator<Item = Result<String, Error>> + '_ {
+        // The pack is only read once the walk reaches it.
+        let packed = std::iter::once(()).flat_map(move |()| match self.pack.names() {
+            Ok(names) => names
+                .into_iter()
+                .filter(|name| !self.has_loose_file(name))
+                .map(Ok)
+                .collect(),
+            Err(e) => vec![Err(e)],
+        });
+        self.walk_loose_crate_names().chain(packed)
+    }
+
+    /// Walks the index checkout for the names of the crates with their own files.
+    ///
+    /// Files that can't be crate names, such as `config.json`, are ignored, except that files
+    /// with names that aren't valid UTF-8 are handled according to the [`InvalidNamePolicy`].
+    fn walk_loose_crate_names(&self) -> impl Iterator<Item = Result<String, Error>> {
+        let policy = self.invalid_names;
+        WalkDir::new(self.path.as_path())
             .min_depth(1)
             .into_iter()
             .filter_entry(|entry| {
-                entry
-                    .file_name()
-                    .as_bytes()
-                    .iter()
+                let name = entry.file_name();
+                if entry.file_type().is_file() && name.to_str().is_none() {
+                    return true;
+                }
+
+                let name = name.as_bytes();
+                let name = if entry.file_type().is_file() {
+                    name.strip_suffix(b".gz").unwrap_or(name)
+                } else {
+                    name
+                };
+
+                name.iter()
                     .all(|c| c.is_ascii_alphanumeric() || *c == b'-' || *c == b'_')
             })
-            .progress_with(progress)
-            .filter_map(|result| match result {
+            .filter_map(move |result| match result {
                 Ok(entry) if entry.file_type().is_dir() => None,
                 Ok(entry) => {
                     let file_name = entry.file_name();
-                    match file_name.to_str() {
-                        Some(name) => Some(Ok(name.to_string())),
-                        None => Some(Err(Error::InvalidCrateName(file_name.to_os_string()))),
+                    match (file_name.to_str(), policy) {
+                        (Some(name), _) => Some(Ok(name.trim_end_matches(".gz").to_string())),
+                        (None, InvalidNamePolicy::Skip) => {
+                            tracing::warn!(path = ?entry.path(), "skipping index file with a non-UTF-8 name");
+                            None
+                        }
+                        (None, InvalidNamePolicy::Error) => {
+                            Some(Err(Error::InvalidCrateName(file_name.to_os_string())))
+                        }
                     }
                 }
                 Err(e) => Some(Err(Error::from(e))),
             })
-            .collect();
+    }
 
-        let crates: Vec<_> = names
-            .into_par_iter()
-            .progress_with_style(
-                ProgressStyle::with_template("Parsing indices {wide_bar} {pos}/{len} ETA: {eta}")
-                    .expect("bar template"),
-            )
-            .map(|result| result.and_then(|name| self.get(&name)))
-            .collect();
+    /// Reads and parses the index entry for the given crate, from its own file if it has one, or
+    /// else from the pack.
+    #[tracing::instrument(err)]
+    pub fn get(&self, name: &CrateName) -> Result<Krate, Error> {
+        self.read_krate(name)
+    }
+
+    /// Like [`Index::get`], for names that came from the index itself rather than from a caller.
+    fn read_krate(&self, name: &str) -> Result<Krate, Error> {
+        Krate::from_reader(name, self.open_entry(name)?)
+    }
 
-        crates.into_iter()
+    /// Returns true if the index contains the given crate.
+    ///
+    /// This only checks that the crate's index file exists, or that the pack has an entry for it,
+    /// without opening or parsing it, so it's cheap enough to validate a long list of names up
+    /// front. Invalid names don't exist.
+    pub fn exists(&self, name: &str) -> bool {
+        self.has_loose_file(name)
+            || (self.crate_path(name).is_ok() && self.pack.contains(name).unwrap_or(false))
     }
 
+    fn has_loose_file(&self, name: &str) -> bool {
+        self.existing_crate_path(name)
+            .is_ok_and(|path| path.is_file())
+    }
+
+    /// Opens the uncompressed index entry for the given crate from whichever tier holds it: its
+    /// own file in the checkout, gzipped or not, or else the pack.
+    fn open_entry(&self, name: &str) -> Result<Box<dyn BufRead>, Error> {
+        match krate::open_index_file(&self.existing_crate_path(name)?) {
+            Err(e) if e.kind() == ErrorKind::NotFound => match self.pack.read(name)? {
+                Some(reader) => Ok(reader),
+                None => Err(Error::from_io(name, e)),
+            },
+            result => result.map_err(|e| Error::from_io(name, e)),
+        }
+    }
+
+    /// Moves the given crate's index file into the pack, returning false if it doesn't have its own
+    /// file.
+    ///
+    /// The file is removed from the checkout, so this is for indices that aren't updated, such as
+    /// ones extracted from a snapshot: an update restores the file, which then takes precedence
+    /// over the packed entry.
     #[tracing::instrument(err)]
-    pub fn get(&self, name: &str) -> Result<Krate, Error> {
-        let path = match name.len() {
-            0 => {
-                return Err(Error::EmptyCrateName);
-            }
-            1 => self.0.join("1"),
-            2 => self.0.join("2"),
-            3 => self.0.join("3").join(&name[0..1]),
-            _ => self.0.join(&name[0..2]).join(&name[2..4]),
+    pub fn pack_crate(&self, name: &str) -> Result<bool, Error> {
+        let path = self.existing_crate_path(name)?;
+        let mut data = Vec::new();
+        match krate::open_index_file(&path).and_then(|mut reader| reader.read_to_end(&mut data)) {
+            Ok(_len) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
         }
-        .join(name);
 
-        Krate::open(name, &path).map_err(|e| {
-            if let Error::Io(e) = &e {
-                if e.kind() == ErrorKind::NotFound {
-                    return Error::NotFound(name.to_string());
-                }
+        self.pack.append(name, &data)?;
+        std::fs::remove_file(path)?;
+        Ok(true)
+    }
+
+    /// Moves every crate's index file into the pack, returning the number of crates packed: see
+    /// [`Index::pack_crate`].
+    #[tracing::instrument(skip(progress), err)]
+    pub fn pack_all(&self, progress: &dyn Progress) -> Result<usize, Error> {
+        let names = self
+            .walk_loose_crate_names()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        progress.start("Packing indices", Some(names.len() as u64));
+        let mut packed = 0;
+        for name in names {
+            if self.pack_crate(&name)? {
+                packed += 1;
             }
-            e
-        })
+            progress.advance(1);
+        }
+        progress.finish();
+
+        Ok(packed)
     }
 
+    /// Returns the number of crates in each tier of the index, and how much space they take up.
     #[tracing::instrument(err)]
-    pub fn update(&mut self, remote: &str, branch: &str) -> Result<(), Error> {
-        let repo = Repository::open(self.0.as_path())?;
+    pub fn cache_stats(&self) -> Result<CacheStats, Error> {
+        let mut stats = CacheStats::default();
+        for name in self.walk_loose_crate_names() {
+            stats.loose += 1;
+            stats.loose_bytes += std::fs::metadata(self.existing_crate_path(&name?)?)?.len();
+        }
+        (stats.packed, stats.pack_bytes) = self.pack.stats()?;
 
-        Self::fetch(&repo, remote, branch)?;
-        Self::checkout(&repo, branch)
+        Ok(stats)
     }
 
-    #[tracing::instrument(skip(repo), err)]
-    fn fetch(repo: &Repository, remote_url: &str, branch: &str) -> Result<(), Error> {
-        let progress = FetchProgress::new();
+    /// Returns the version numbers of the given crate and whether each is yanked, newest first.
+    ///
+    /// Only the `vers` and `yanked` fields of each entry are parsed, so this is much cheaper than
+    /// [`Index::get`] for crates with many versions.
+    #[tracing::instrument(err)]
+    pub fn version_summaries(&self, name: &str) -> Result<Vec<krate::VersionSummary>, Error> {
+        krate::read_summaries(self.open_entry(name)?)
+    }
+
+    /// Returns the contents of the index file for the given crate, decompressed if necessary, but
+    /// otherwise exactly as they appear in the index.
+    ///
+    /// Unlike [`Index::get`], this doesn't impose librarian's schema on the entries, so fields
+    /// that [`Version`](krate::Version) doesn't model are preserved.
+    #[tracing::instrument(err)]
+    pub fn get_raw(&self, name: &str) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        self.open_entry(name)?.read_to_end(&mut data)?;
+
+        Ok(data)
+    }
+
+    /// Returns up to `max` names of crates that are close to the given name, closest first, for
+    /// suggesting alternatives when [`Index::get`] returns [`Error::NotFound`].
+    ///
+    /// Only the index directory that the name would be in is searched, so this catches typos
+    /// after the first few characters, and differences in case or between `-` and `_`.
+    pub fn suggest(&self, name: &str, max: usize) -> Vec<String> {
+        let Ok(bucket) = self.crate_path(name) else {
+            return Vec::new();
+        };
+        let Some(Ok(entries)) = bucket.parent().map(std::fs::read_dir) else {
+            return Vec::new();
+        };
+
+        // Index files are always lowercase, and crates.io treats `-` and `_` as equivalent.
+        let name = name.to_ascii_lowercase().replace('_', "-");
+        let threshold = (name.len() / 3).max(1);
+        let mut candidates: Vec<(usize, String)> = entries
+            .filter_map(|entry| {
+                let file_name = entry.ok()?.file_name().into_string().ok()?;
+                let candidate = file_name.trim_end_matches(".gz").to_string();
+                let distance = edit_distance(&name, &candidate.replace('_', "-"));
+                (distance <= threshold).then_some((distance, candidate))
+            })
+            .collect();
+        candidates.sort();
+        candidates.dedup_by(|a, b| a.1 == b.1);
+
+        candidates
+            .into_iter()
+            .take(max)
+            .map(|(_distance, candidate)| candidate)
+            .collect()
+    }
+
+    /// Returns the path to the index file for the given crate, falling back to a locally
+    /// compressed copy if there's no uncompressed file.
+    fn existing_crate_path(&self, name: &str) -> Result<PathBuf, Error> {
+        let mut path = self.crate_path(name)?;
+        if !path.exists() {
+            path.as_mut_os_string().push(".gz");
+        }
+        Ok(path)
+    }
+
+    /// Returns the path to the index file for the given crate, whether or not it exists.
+    pub fn crate_path(&self, name: &str) -> Result<PathBuf, Error> {
+        Ok(self.path.join(relative_crate_path(name)?))
+    }
+
+    /// Updates the index from the first of the given remotes that can be fetched successfully,
+    /// returning the remote that was used.
+    ///
+    /// Remotes may be given as cargo source URLs: see [`Remote`](remote::Remote). Sparse remotes
+    /// can't be fetched, so they're skipped with [`Error::SparseUnsupported`].
+    #[tracing::instrument(skip(progress), err)]
+    pub fn update<'a>(
+        &mut self,
+        remotes: &[&'a str],
+        branch: &str,
+        progress: &dyn Progress,
+    ) -> Result<&'a str, Error> {
+        self.update_inner(remotes, branch, false, progress)
+    }
+
+    /// Like [`Index::update`], but refuses to update if the fetched branch isn't a descendant of
+    /// the current `HEAD`, returning [`Error::NonFastForward`] instead.
+    ///
+    /// The index is normally only ever appended to, so this detects upstream history rewrites.
+    #[tracing::instrument(skip(progress), err)]
+    pub fn update_ff_only<'a>(
+        &mut self,
+        remotes: &[&'a str],
+        branch: &str,
+        progress: &dyn Progress,
+    ) -> Result<&'a str, Error> {
+        self.update_inner(remotes, branch, true, progress)
+    }
+
+    fn update_inner<'a>(
+        &mut self,
+        remotes: &[&'a str],
+        branch: &str,
+        ff_only: bool,
+        progress: &dyn Progress,
+    ) -> Result<&'a str, Error> {
+        let result = self.update_from_remotes(remotes, branch, ff_only, progress);
+        telemetry::index_updated(result.is_ok());
+        result
+    }
+
+    fn update_from_remotes<'a>(
+        &mut self,
+        remotes: &[&'a str],
+        branch: &str,
+        ff_only: bool,
+        progress: &dyn Progress,
+    ) -> Result<&'a str, Error> {
+        let repo = Repository::open(self.path.as_path())?;
+        // Whatever happens below, the checkout may have changed.
+        *self.names.write().expect("crate name cache lock") = None;
 
+        let mut last_err = Error::NoRemotes;
+        for remote in remotes {
+            let url = match remote.parse()? {
+                Remote::Git(url) => url,
+                Remote::Sparse(url) => {
+                    tracing::warn!(
+                        ?remote,
+                        "sparse remotes can't be fetched; trying next remote"
+                    );
+                    last_err = Error::SparseUnsupported(url);
+                    continue;
+                }
+            };
+
+            match Self::fetch(&repo, &url, branch, progress) {
+                Ok(()) => {
+                    if ff_only {
+                        Self::check_fast_forward(&repo, branch)?;
+                    }
+                    Self::checkout(&repo, branch, progress)?;
+                    return Ok(remote);
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        ?remote,
+                        ?e,
+                        "error fetching from remote; trying next remote"
+                    );
+                    last_err = e;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+
+    #[tracing::instrument(skip(repo, progress), err)]
+    fn fetch(
+        repo: &Repository,
+        remote_url: &str,
+        branch: &str,
+        progress: &dyn Progress,
+    ) -> Result<(), Error> {
         let mut remote = match repo.find_remote("origin") {
             Ok(remote) => {
                 repo.remote_set_url("origin", remote_url)?;
@@ -129,41 +545,142 @@ impl Index {
             Err(_e) => repo.remote("origin", remote_url)?,
         };
 
+        let mut phase = FetchPhase::Connecting;
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.sideband_progress(|msg| {
+            match std::str::from_utf8(msg) {
+                Ok(s) => progress.message(s.trim_matches('\r')),
+                Err(e) => tracing::warn!(?e, ?msg, "sideband got non UTF-8 data"),
+            }
+
+            true
+        });
+        callbacks.transfer_progress(|stats| {
+            progress.bytes(stats.received_bytes() as u64);
+
+            // libgit2 only tells us the totals once the transfer is underway, so each phase is
+            // started on the first callback that has its total.
+            if phase == FetchPhase::Connecting && stats.total_objects() > 0 {
+                progress.start("Receiving objects", Some(stats.total_objects() as u64));
+                phase = FetchPhase::Objects;
+            }
+            if phase == FetchPhase::Objects {
+                progress.set_position(stats.indexed_objects() as u64);
+                if stats.indexed_objects() == stats.total_objects() && stats.total_deltas() > 0 {
+                    progress.finish();
+                    progress.start("Resolving deltas", Some(stats.total_deltas() as u64));
+                    phase = FetchPhase::Deltas;
+                }
+            }
+            if phase == FetchPhase::Deltas {
+                progress.set_position(stats.indexed_deltas() as u64);
+            }
+
+            true
+        });
+
         remote.fetch(
             &[&branch],
-            Some(FetchOptions::new().remote_callbacks(progress.create_callbacks())),
+            Some(FetchOptions::new().remote_callbacks(callbacks)),
             None,
         )?;
+        progress.finish();
 
         Ok(())
     }
 
     #[tracing::instrument(skip(repo), err)]
-    fn checkout(repo: &Repository, branch: &str) -> Result<(), Error> {
+    fn check_fast_forward(repo: &Repository, branch: &str) -> Result<(), Error> {
+        let head = match repo.head() {
+            Ok(head) => head.peel_to_commit()?.id(),
+            // A fresh index can always be fast forwarded.
+            Err(e) if matches!(e.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => {
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+        let target = repo
+            .find_branch(&format!("origin/{branch}"), BranchType::Remote)?
+            .get()
+            .peel_to_commit()?
+            .id();
+
+        if head == target || repo.graph_descendant_of(target, head)? {
+            Ok(())
+        } else {
+            Err(Error::NonFastForward {
+                head: head.to_string(),
+                target: target.to_string(),
+            })
+        }
+    }
+
+    #[tracing::instrument(skip(repo, progress), err)]
+    fn checkout(repo: &Repository, branch: &str, progress: &dyn Progress) -> Result<(), Error> {
         let branch = repo.find_branch(&format!("origin/{branch}"), BranchType::Remote)?;
         let tree = branch.get().peel_to_commit()?;
 
-        let progress = ProgressBar::new(0).with_style(
-            ProgressStyle::with_template(
-                "Checking out files {wide_bar} {pos}/{len} ETA: {eta:>10}",
-            )
-            .expect("checkout progress"),
-        );
-
+        let mut started = false;
         let mut options = CheckoutBuilder::new();
         options.progress(|_path, completed, total| {
-            progress.set_length(total as u64);
+            if !started {
+                progress.start("Checking out files", Some(total as u64));
+                started = true;
+            }
             progress.set_position(completed as u64);
         });
 
         repo.reset(&tree.into_object(), ResetType::Hard, Some(&mut options))?;
+        progress.finish();
 
         Ok(())
     }
 }
 
+/// Returns the path of the index file for the given crate, relative to the root of the index.
+pub fn relative_crate_path(name: &str) -> Result<PathBuf, Error> {
+    if !name.is_ascii() {
+        return Err(Error::InvalidCrateName(name.into()));
+    }
+
+    // Index files are always named in lowercase, regardless of the case the crate was published
+    // with.
+    let name = name.to_ascii_lowercase();
+    Ok(match name.len() {
+        0 => {
+            return Err(Error::EmptyCrateName);
+        }
+        1 => PathBuf::from("1"),
+        2 => PathBuf::from("2"),
+        3 => Path::new("3").join(&name[0..1]),
+        _ => Path::new(&name[0..2]).join(&name[2..4]),
+    }
+    .join(name))
+}
+
+/// Returns the Levenshtein distance between two ASCII strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.as_bytes();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.bytes().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
+    #[error("parsing cargo config at {0:?}: {1:?}")]
+    CargoConfig(PathBuf, #[source] toml::de::Error),
+
     #[error("invalid crate name: cannot be empty")]
     EmptyCrateName,
 
@@ -173,90 +690,496 @@ pub enum Error {
     #[error("invalid crate name: {0:?}")]
     InvalidCrateName(OsString),
 
+    #[error("snapshot does not contain an index: {0:?}")]
+    InvalidSnapshot(PathBuf),
+
     #[error("io error: {0:?}")]
     Io(#[from] std::io::Error),
 
     #[error("JSON error: {0:?}")]
     Json(#[from] serde_json::Error),
 
+    #[error("the index config does not specify an API endpoint")]
+    NoApi,
+
+    #[error("no version of {name} matches {req}")]
+    NoMatchingVersion { name: String, req: String },
+
+    #[error("no remotes were provided")]
+    NoRemotes,
+
+    #[error("fetched index {target} is not a descendant of the current index {head}")]
+    NonFastForward { head: String, target: String },
+
     #[error("path exists, but is not a directory: {0:?}")]
     NotADirectory(PathBuf),
 
+    #[error("directory is not empty: {0:?}")]
+    NotEmpty(PathBuf),
+
     #[error("crate not found: {0}")]
     NotFound(String),
 
+    #[error("reqwest error: {0:?}")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("semver error: {0:?}")]
+    Semver(#[from] semver::Error),
+
+    #[error("source replacement cycle at {0:?}")]
+    SourceReplacementCycle(String),
+
+    #[error("sparse index remotes are not supported for updates: {0}")]
+    SparseUnsupported(String),
+
+    #[error("snapshot entry has an unsafe path: {0:?}")]
+    UnsafeSnapshotPath(PathBuf),
+
+    #[error("unsupported remote scheme: {0:?} (expected registry+ or sparse+)")]
+    UnsupportedRemoteScheme(String),
+
     #[error("walkdir error: {0:?}")]
     WalkDir(#[from] walkdir::Error),
 }
 
-struct FetchProgress {
-    multi: MultiProgress,
-    objects: ProgressBar,
-    deltas: ProgressBar,
-    bytes: ProgressBar,
+impl Error {
+    /// Converts an error from opening the index file for the given crate, mapping a missing file
+    /// to [`Error::NotFound`].
+    fn from_io(name: &str, e: std::io::Error) -> Self {
+        if e.kind() == ErrorKind::NotFound {
+            Self::NotFound(name.to_string())
+        } else {
+            Self::Io(e)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FetchPhase {
+    Connecting,
+    Objects,
+    Deltas,
 }
 
-impl FetchProgress {
-    fn new() -> Self {
-        let multi = MultiProgress::new();
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write};
 
-        let objects = ProgressBar::new(1).with_style(
-            ProgressStyle::with_template("Objects {wide_bar} {pos}/{len} ETA: {eta:>10}")
-                .expect("object template"),
-        );
+    use flate2::{write::GzEncoder, Compression};
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::progress::NoProgress;
+
+    #[test]
+    fn test_get() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
 
-        let deltas = ProgressBar::new(1).with_style(
-            ProgressStyle::with_template("Deltas  {wide_bar} {pos}/{len} ETA: {eta:>10}")
-                .expect("deltas template"),
+        create_index_file(&index, "1/a", "a", &["0.1.0"])?;
+        create_index_file(&index, "2/ab", "ab", &["0.1.0", "0.2.0"])?;
+        create_index_file(&index, "3/a/abc", "abc", &["1.0.0"])?;
+        create_index_file(&index, "ab/cd/abcd", "abcd", &["1.0.0"])?;
+        create_index_file(&index, "se/rd/serde", "Serde", &["1.0.0"])?;
+        create_gzipped_index_file(&index, "gz/ip/gzipped.gz", "gzipped", &["1.0.0"])?;
+
+        for name in ["a", "ab", "abc", "abcd", "Serde", "SERDE", "gzipped"] {
+            let krate = index.get(&name.parse()?)?;
+            assert_that!(krate.name(), eq(name));
+        }
+
+        assert_that!(
+            index
+                .get(&"ab".parse()?)?
+                .version_nums()
+                .collect::<Vec<_>>(),
+            elements_are![eq("0.1.0"), eq("0.2.0")]
+        );
+        assert_that!(
+            index.get(&"abd".parse()?),
+            err(matches_pattern!(Error::NotFound(eq("abd"))))
+        );
+        assert_that!(
+            index.read_krate(""),
+            err(matches_pattern!(Error::EmptyCrateName))
+        );
+        assert_that!(
+            index.get_raw("ab")?,
+            eq(std::fs::read(index.crate_path("ab")?)?)
+        );
+        assert_that!(
+            String::from_utf8(index.get_raw("gzipped")?)?,
+            contains_substring(r#""name":"gzipped","vers":"1.0.0""#)
+        );
+        assert_that!(
+            index.get_raw("abd"),
+            err(matches_pattern!(Error::NotFound(eq("abd"))))
+        );
+        assert_that!(
+            index.get(&"ü".parse()?),
+            err(matches_pattern!(Error::InvalidCrateName(anything())))
         );
 
-        let bytes = ProgressBar::new(0).with_style(
-            ProgressStyle::with_template("Bytes transferred: {bytes}").expect("object template"),
+        assert_that!(index.exists("SERDE"), eq(true));
+        assert_that!(index.exists("gzipped"), eq(true));
+        assert_that!(index.exists("abd"), eq(false));
+        assert_that!(index.exists("ü"), eq(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_suggest() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
+
+        create_index_file(&index, "se/rd/serde", "serde", &["1.0.0"])?;
+        create_index_file(&index, "se/rd/serde_json", "serde_json", &["1.0.0"])?;
+        create_index_file(&index, "se/rd/serde-jsonc", "serde-jsonc", &["1.0.0"])?;
+        create_gzipped_index_file(&index, "se/rd/serdes.gz", "serdes", &["1.0.0"])?;
+
+        assert_that!(
+            index.suggest("serdex", 5),
+            eq(vec!["serde".to_string(), "serdes".to_string()])
+        );
+        assert_that!(
+            index.suggest("Serde-Json", 5),
+            eq(vec!["serde_json".to_string(), "serde-jsonc".to_string()])
+        );
+        assert_that!(
+            index.suggest("serde-json", 1),
+            eq(vec!["serde_json".to_string()])
         );
+        assert_that!(index.suggest("tokio", 5), empty());
+        assert_that!(index.suggest("", 5), empty());
 
-        multi.add(objects.clone());
-        multi.add(deltas.clone());
-        multi.add(bytes.clone());
+        Ok(())
+    }
 
-        Self {
-            multi,
-            objects,
-            deltas,
-            bytes,
+    #[test]
+    fn test_all() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
+
+        create_index_file(&index, "3/a/abc", "abc", &["1.0.0"])?;
+        create_gzipped_index_file(&index, "gz/ip/gzipped.gz", "gzipped", &["1.0.0"])?;
+
+        let names: Vec<String> = index
+            .all(&NoProgress)
+            .map(|krate| krate.map(|krate| krate.name().to_string()))
+            .collect::<std::result::Result<_, Error>>()?;
+        assert_that!(names, unordered_elements_are![eq("abc"), eq("gzipped")]);
+
+        let names: Vec<String> = index
+            .all_chunked(1, &NoProgress)
+            .map(|krate| krate.map(|krate| krate.name().to_string()))
+            .collect::<std::result::Result<_, Error>>()?;
+        assert_that!(names, unordered_elements_are![eq("abc"), eq("gzipped")]);
+
+        // Interrupting the walk after the first crate still parses that crate.
+        struct StopOnAdvance<'a>(&'a AtomicBool);
+        impl Progress for StopOnAdvance<'_> {
+            fn start(&self, _label: &str, _total: Option<u64>) {}
+            fn advance(&self, _delta: u64) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+            fn set_position(&self, _position: u64) {}
+            fn finish(&self) {}
         }
+        let stop = AtomicBool::new(false);
+        let partial = index.all_until(&stop, &StopOnAdvance(&stop));
+        assert_that!(partial.interrupted, eq(true));
+        assert_that!(partial.crates, len(eq(1)));
+        assert_that!(partial.crates[0], ok(anything()));
+
+        let partial = index.all_until(&AtomicBool::new(false), &NoProgress);
+        assert_that!(partial.interrupted, eq(false));
+        assert_that!(partial.crates, len(eq(2)));
+
+        let partial =
+            index.all_matching(&AtomicBool::new(false), &|name| name != "abc", &NoProgress);
+        assert_that!(
+            partial
+                .crates
+                .into_iter()
+                .map(|krate| krate.map(|krate| krate.name().to_string()))
+                .collect::<std::result::Result<Vec<_>, Error>>()?,
+            elements_are![eq("gzipped")]
+        );
+
+        Ok(())
     }
 
-    fn create_callbacks(&self) -> RemoteCallbacks<'_> {
-        let mut cb = RemoteCallbacks::new();
+    #[test]
+    fn test_pack() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
 
-        cb.sideband_progress(|msg| {
-            match std::str::from_utf8(msg) {
-                Ok(s) => self
-                    .multi
-                    .println(s.trim_matches('\r'))
-                    .expect("sideband progress"),
-                Err(e) => tracing::warn!(?e, ?msg, "sideband got non UTF-8 data"),
-            }
+        create_index_file(&index, "se/rd/serde", "serde", &["1.0.0", "1.0.1"])?;
+        create_gzipped_index_file(&index, "gz/ip/gzipped.gz", "gzipped", &["1.0.0"])?;
+        create_index_file(&index, "3/a/abc", "abc", &["1.0.0"])?;
+        let raw = index.get_raw("serde")?;
 
-            true
-        });
+        assert_that!(index.pack_crate("serde")?, eq(true));
+        assert_that!(index.pack_crate("gzipped")?, eq(true));
+        assert_that!(index.pack_crate("missing")?, eq(false));
+        assert_that!(index.crate_path("serde")?.exists(), eq(false));
 
-        cb.transfer_progress(|progress| {
-            self.objects.set_length(progress.total_objects() as u64);
-            self.objects.set_position(progress.indexed_objects() as u64);
-            self.deltas.set_length(progress.total_deltas() as u64);
-            self.deltas.set_position(progress.indexed_deltas() as u64);
-            self.bytes.set_position(progress.received_bytes() as u64);
+        // A fresh index has to find the entries by scanning the pack.
+        let index = Index::new(temp.path())?;
+        assert_that!(index.get_raw("serde")?, eq(raw.as_slice()));
+        assert_that!(
+            index.get(&"gzipped".parse()?)?.version_nums().count(),
+            eq(1)
+        );
+        assert_that!(
+            index.version_summaries("serde")?,
+            elements_are![
+                matches_pattern!(krate::VersionSummary { num: eq("1.0.1") }),
+                matches_pattern!(krate::VersionSummary { num: eq("1.0.0") })
+            ]
+        );
+        assert_that!(index.exists("serde"), eq(true));
 
-            true
-        });
+        // Packed crates are found regardless of case, as loose ones are.
+        assert_that!(index.exists("SERDE"), eq(true));
+        assert_that!(index.get_raw("Serde")?, eq(raw.as_slice()));
+        assert_that!(index.get(&"Serde".parse()?)?.version_nums().count(), eq(2));
+        assert_that!(index.version_summaries("SeRdE")?, len(eq(2)));
+        assert_that!(
+            index.get(&"missing".parse()?),
+            err(matches_pattern!(Error::NotFound(eq("missing"))))
+        );
+        assert_that!(
+            index.crate_names()?,
+            eq(vec![
+                "abc".to_string(),
+                "gzipped".to_string(),
+                "serde".to_string()
+            ])
+        );
+        assert_that!(
+            index.cache_stats()?,
+            matches_pattern!(CacheStats {
+                loose: eq(1),
+                packed: eq(2),
+                pack_bytes: eq(std::fs::metadata(temp.path().join(pack::PACK_FILE))?.len())
+            })
+        );
+
+        // An incomplete record from an interrupted append is ignored, then overwritten.
+        let mut pack = File::options()
+            .append(true)
+            .open(temp.path().join(pack::PACK_FILE))?;
+        pack.write_all(&[3, 0, b'a', b'b'])?;
+        let index = Index::new(temp.path())?;
+        assert_that!(index.pack_all(&NoProgress)?, eq(1));
+        let index = Index::new(temp.path())?;
+        assert_that!(
+            index.cache_stats()?,
+            matches_pattern!(CacheStats {
+                loose: eq(0),
+                packed: eq(3)
+            })
+        );
+        assert_that!(index.get(&"abc".parse()?)?.version_nums().count(), eq(1));
 
-        cb
+        Ok(())
     }
-}
 
-impl Drop for FetchProgress {
-    fn drop(&mut self) {
-        self.multi.clear().expect("multi clear");
+    #[test]
+    fn test_pack_shared() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let first = Index::new(temp.path())?;
+        let second = Index::new(temp.path())?;
+        create_index_file(&first, "se/rd/serde", "serde", &["1.0.0"])?;
+        create_index_file(&first, "3/a/abc", "abc", &["1.0.0"])?;
+
+        // Both indices have scanned the (empty) pack before either appends to it.
+        assert_that!(first.cache_stats()?.packed, eq(0));
+        assert_that!(second.cache_stats()?.packed, eq(0));
+        assert_that!(first.pack_crate("serde")?, eq(true));
+        assert_that!(second.pack_crate("abc")?, eq(true));
+
+        let index = Index::new(temp.path())?;
+        assert_that!(index.cache_stats()?.packed, eq(2));
+        assert_that!(index.get(&"serde".parse()?)?.version_nums().count(), eq(1));
+        assert_that!(index.get(&"abc".parse()?)?.version_nums().count(), eq(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_crate_names() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
+
+        create_index_file(&index, "se/rd/serde", "serde", &["1.0.0"])?;
+        create_index_file(&index, "3/a/abc", "abc", &["1.0.0"])?;
+        create_gzipped_index_file(&index, "gz/ip/gzipped.gz", "gzipped", &["1.0.0"])?;
+
+        let names = vec![
+            "abc".to_string(),
+            "gzipped".to_string(),
+            "serde".to_string(),
+        ];
+        assert_that!(index.crate_names()?, eq(names.clone()));
+
+        // Until the index is updated, the cached names are returned, including from clones.
+        create_index_file(&index, "2/ab", "ab", &["1.0.0"])?;
+        assert_that!(index.clone().crate_names()?, eq(names));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_name_policy() -> anyhow::Result<()> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
+        create_index_file(&index, "se/rd/serde", "serde", &["1.0.0"])?;
+        std::fs::create_dir_all(temp.path().join("ab/cd"))?;
+        std::fs::write(
+            temp.path()
+                .join("ab/cd")
+                .join(OsStr::from_bytes(b"abcd\xff")),
+            "",
+        )?;
+
+        assert_that!(index.crate_names()?, eq(vec!["serde".to_string()]));
+
+        let index = Index::new(temp.path())?.with_invalid_name_policy(InvalidNamePolicy::Error);
+        assert_that!(
+            index.all(&NoProgress).collect::<Vec<_>>(),
+            contains(err(matches_pattern!(Error::InvalidCrateName(anything()))))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_ff_only() -> anyhow::Result<()> {
+        let upstream_dir = tempfile::tempdir()?;
+        let upstream = Repository::init(upstream_dir.path())?;
+        let remote = upstream_dir.path().to_str().expect("UTF-8 temp path");
+
+        let temp = tempfile::tempdir()?;
+        let mut index = Index::new(temp.path())?;
+
+        let first = commit(&upstream, "a", None)?;
+        index.update_ff_only(&[remote], "master", &NoProgress)?;
+        assert_that!(index.crate_names()?, eq(vec!["a".to_string()]));
+        commit(&upstream, "ab", Some(first))?;
+        index.update_ff_only(&[remote], "master", &NoProgress)?;
+        // Each commit only contains its own crate, so a stale cache would still have `a`.
+        assert_that!(index.crate_names()?, eq(vec!["ab".to_string()]));
+        assert_that!(
+            index
+                .get(&"ab".parse()?)
+                .map(|krate| krate.name().to_string()),
+            ok(eq("ab"))
+        );
+
+        // Rewrite history so that master no longer descends from what we have.
+        commit(&upstream, "abc", Some(first))?;
+        assert_that!(
+            index.update_ff_only(&[remote], "master", &NoProgress),
+            err(matches_pattern!(Error::NonFastForward {
+                head: anything(),
+                target: anything(),
+            }))
+        );
+        assert_that!(
+            index.get(&"abc".parse()?),
+            err(matches_pattern!(Error::NotFound(eq("abc"))))
+        );
+
+        // A normal update goes ahead regardless.
+        index.update(&[remote], "master", &NoProgress)?;
+        assert_that!(
+            index
+                .get(&"abc".parse()?)
+                .map(|krate| krate.name().to_string()),
+            ok(eq("abc"))
+        );
+
+        Ok(())
+    }
+
+    /// Commits an index file for the given crate on top of `parent`, and forces master to point
+    /// to the new commit.
+    fn commit(
+        repo: &Repository,
+        name: &str,
+        parent: Option<git2::Oid>,
+    ) -> anyhow::Result<git2::Oid> {
+        let path = relative_crate_path(name)?;
+        let content = format!(
+            r#"{{"name":"{name}","vers":"1.0.0","deps":[],"cksum":"","features":{{}},"yanked":false}}"#
+        );
+
+        let mut builder = repo.treebuilder(None)?;
+        let blob = repo.blob(content.as_bytes())?;
+        // Build the nested trees for the bucket directories from the inside out.
+        let mut components: Vec<_> = path.iter().collect();
+        let file = components.pop().expect("file name");
+        builder.insert(file, blob, 0o100644)?;
+        let mut tree = builder.write()?;
+        for dir in components.into_iter().rev() {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert(dir, tree, 0o040000)?;
+            tree = builder.write()?;
+        }
+
+        let signature = git2::Signature::now("test", "test@example.com")?;
+        let parents = parent.map(|parent| repo.find_commit(parent)).transpose()?;
+        let oid = repo.commit(
+            None,
+            &signature,
+            &signature,
+            name,
+            &repo.find_tree(tree)?,
+            &parents.iter().collect::<Vec<_>>(),
+        )?;
+        repo.reference("refs/heads/master", oid, true, "test")?;
+
+        Ok(oid)
+    }
+
+    fn create_gzipped_index_file(
+        index: &Index,
+        path: &str,
+        name: &str,
+        versions: &[&str],
+    ) -> anyhow::Result<()> {
+        create_index_file(index, path, name, versions)?;
+
+        let path = index.path.join(path);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&std::fs::read(&path)?)?;
+        std::fs::write(&path, encoder.finish()?)?;
+
+        Ok(())
+    }
+
+    fn create_index_file(
+        index: &Index,
+        path: &str,
+        name: &str,
+        versions: &[&str],
+    ) -> anyhow::Result<()> {
+        let path = index.path.join(path);
+        std::fs::create_dir_all(path.parent().expect("index file parent"))?;
+
+        let mut file = File::create(path)?;
+        for version in versions {
+            writeln!(
+                &mut file,
+                r#"{{"name":"{name}","vers":"{version}","deps":[],"cksum":"","features":{{}},"yanked":false}}"#
+            )?;
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,102 @@
+use reqwest::blocking::Client;
+use serde::Deserialize;
+
+use super::{Error, Index};
+use crate::download::DEFAULT_USER_AGENT;
+
+/// Crate metadata from the registry API that isn't recorded in the index.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CrateMetadata {
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub downloads: u64,
+    #[serde(skip)]
+    pub owners: Vec<Owner>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Owner {
+    pub login: String,
+    pub name: Option<String>,
+    /// Either `user` or `team`.
+    pub kind: String,
+}
+
+#[derive(Deserialize)]
+struct Config {
+    api: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CrateResponse {
+    #[serde(rename = "crate")]
+    krate: CrateMetadata,
+}
+
+#[derive(Deserialize)]
+struct OwnersResponse {
+    users: Vec<Owner>,
+}
+
+impl Index {
+    /// Fetches metadata for a crate from the registry API given in the index's `config.json`.
+    ///
+    /// Unlike [`Index::get`], this requires network access.
+    #[tracing::instrument(err)]
+    pub fn crate_metadata(&self, name: &str) -> Result<CrateMetadata, Error> {
+        let api = self.api_url()?;
+        let client = Client::builder().user_agent(DEFAULT_USER_AGENT).build()?;
+
+        let get = |url: String| -> Result<_, Error> {
+            Ok(client.get(url).send()?.error_for_status()?.bytes()?)
+        };
+
+        let mut metadata =
+            serde_json::from_slice::<CrateResponse>(&get(format!("{api}/api/v1/crates/{name}"))?)?
+                .krate;
+        metadata.owners = serde_json::from_slice::<OwnersResponse>(&get(format!(
+            "{api}/api/v1/crates/{name}/owners"
+        ))?)?
+        .users;
+
+        Ok(metadata)
+    }
+
+    fn api_url(&self) -> Result<String, Error> {
+        let config: Config =
+            serde_json::from_slice(&std::fs::read(self.path.join("config.json"))?)?;
+
+        config
+            .api
+            .map(|api| api.trim_end_matches('/').to_string())
+            .ok_or(Error::NoApi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_api_url() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
+
+        std::fs::write(
+            temp.path().join("config.json"),
+            r#"{"dl": "https://crates.io/api/v1/crates", "api": "https://crates.io/"}"#,
+        )?;
+        assert_that!(index.api_url()?, eq("https://crates.io"));
+
+        std::fs::write(
+            temp.path().join("config.json"),
+            r#"{"dl": "https://crates.io/api/v1/crates"}"#,
+        )?;
+        assert_that!(index.api_url(), err(matches_pattern!(Error::NoApi)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::BufReader,
+    path::Path,
+};
+
+use git2::{ErrorCode, Repository, Tree, TreeEntry};
+
+use super::{
+    krate::{self, Krate},
+    relative_crate_path, Error, Index,
+};
+
+/// A handle for looking up many crates from the same [`Index`], such as while resolving a
+/// dependency closure.
+///
+/// Crates are read from the tree of the index repository's `HEAD`, which is looked up once when
+/// the batch is created, rather than from the checkout. Each crate is only parsed once, however
+/// many times it's requested. If the repository doesn't have any commits yet, crates are read
+/// from the checkout as [`Index::get`] would.
+pub struct IndexBatch<'repo> {
+    index: &'repo Index,
+    repo: &'repo Repository,
+    tree: Option<Tree<'repo>>,
+    krates: HashMap<String, Krate>,
+}
+
+impl Index {
+    /// Opens the git repository that backs the index, for use with [`Index::batch`].
+    pub fn repository(&self) -> Result<Repository, Error> {
+        Ok(Repository::open(self.path.as_path())?)
+    }
+
+    /// Starts a batch of lookups against the `HEAD` of the given repository, which should be the
+    /// one returned by [`Index::repository`].
+    #[tracing::instrument(skip(repo), err)]
+    pub fn batch<'repo>(&'repo self, repo: &'repo Repository) -> Result<IndexBatch<'repo>, Error> {
+        let tree = match repo.head() {
+            Ok(head) => Some(head.peel_to_tree()?),
+            Err(e) if matches!(e.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(IndexBatch {
+            index: self,
+            repo,
+            tree,
+            krates: HashMap::new(),
+        })
+    }
+}
+
+impl IndexBatch<'_> {
+    /// Returns the given crate, parsing it if it hasn't already been requested from this batch.
+    pub fn get(&mut self, name: &str) -> Result<&Krate, Error> {
+        match self.krates.entry(name.to_ascii_lowercase()) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let krate = match &self.tree {
+                    Some(tree) => Self::read_from_tree(self.repo, tree, name)?,
+                    None => self.index.read_krate(name)?,
+                };
+                Ok(entry.insert(krate))
+            }
+        }
+    }
+
+    fn read_from_tree(repo: &Repository, tree: &Tree<'_>, name: &str) -> Result<Krate, Error> {
+        let path = relative_crate_path(name)?;
+        let mut gzipped = path.clone();
+        gzipped.as_mut_os_string().push(".gz");
+
+        let entry = match Self::tree_entry(tree, &path)? {
+            Some(entry) => entry,
+            None => Self::tree_entry(tree, &gzipped)?
+                .ok_or_else(|| Error::NotFound(name.to_string()))?,
+        };
+        let blob = entry.to_object(repo)?.peel_to_blob()?;
+
+        let reader = krate::decompress_if_gzipped(BufReader::new(blob.content()))?;
+        let krate = Krate::from_reader(name, reader)?;
+        Ok(krate)
+    }
+
+    fn tree_entry(tree: &Tree<'_>, path: &Path) -> Result<Option<TreeEntry<'static>>, Error> {
+        match tree.get_path(path) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_batch() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
+        let line = |name: &str| format!(r#"{{"name":"{name}","vers":"1.0.0","cksum":""}}"#);
+
+        std::fs::create_dir_all(temp.path().join("se/rd"))?;
+        std::fs::write(temp.path().join("se/rd/serde"), line("serde"))?;
+        std::fs::create_dir_all(temp.path().join("3/f"))?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(line("foo").as_bytes())?;
+        std::fs::write(temp.path().join("3/f/foo.gz"), encoder.finish()?)?;
+
+        // Without any commits, the checkout is used.
+        let repo = index.repository()?;
+        assert_that!(
+            index
+                .batch(&repo)?
+                .get("serde")
+                .map(|krate| krate.name().to_string()),
+            ok(eq("serde"))
+        );
+
+        let mut git_index = repo.index()?;
+        git_index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+        let tree = repo.find_tree(git_index.write_tree()?)?;
+        let signature = git2::Signature::now("test", "test@example.com")?;
+        repo.commit(Some("HEAD"), &signature, &signature, "index", &tree, &[])?;
+
+        // Once there's a commit, crates come from its tree rather than the checkout.
+        std::fs::remove_file(temp.path().join("se/rd/serde"))?;
+        let mut batch = index.batch(&repo)?;
+        assert_that!(
+            batch.get("Serde")?.version_nums().collect::<Vec<_>>(),
+            eq(vec!["1.0.0"])
+        );
+        assert_that!(batch.get("foo")?.name(), eq("foo"));
+        assert_that!(
+            batch.get("bar").map(|krate| krate.name().to_string()),
+            err(matches_pattern!(Error::NotFound(eq("bar"))))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use super::{remote::Remote, Error};
+
+/// The name cargo gives the crates.io registry.
+pub const CRATES_IO: &str = "crates-io";
+
+/// The git index of the crates.io registry.
+pub const CRATES_IO_INDEX: &str = "https://github.com/rust-lang/crates.io-index";
+
+/// The parts of a cargo configuration file needed to find the index of a named registry: the
+/// `[registries]` table, and the `[source]` table used for source replacement.
+#[derive(Debug, Default, Deserialize)]
+pub struct CargoConfig {
+    #[serde(default)]
+    registries: HashMap<String, RegistryConfig>,
+
+    #[serde(default)]
+    source: HashMap<String, SourceConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryConfig {
+    index: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct SourceConfig {
+    registry: Option<String>,
+    replace_with: Option<String>,
+}
+
+impl CargoConfig {
+    /// Loads the configuration in cargo's home directory: `$CARGO_HOME`, or `~/.cargo` if that
+    /// isn't set.
+    ///
+    /// Project level `.cargo/config.toml` files aren't consulted. If there's no configuration,
+    /// an empty one is returned.
+    pub fn load() -> Result<Self, Error> {
+        let home = match std::env::var_os("CARGO_HOME") {
+            Some(home) => PathBuf::from(home),
+            None => match std::env::var_os("HOME") {
+                Some(home) => Path::new(&home).join(".cargo"),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        Self::load_from(&home)
+    }
+
+    /// Loads the configuration in the given cargo home directory.
+    ///
+    /// Like cargo, `config.toml` is preferred over the legacy `config` file.
+    pub fn load_from(cargo_home: &Path) -> Result<Self, Error> {
+        for name in ["config.toml", "config"] {
+            let path = cargo_home.join(name);
+            match std::fs::read_to_string(&path) {
+                Ok(content) => {
+                    return toml::from_str(&content).map_err(|e| Error::CargoConfig(path, e));
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(Self::default())
+    }
+
+    /// Returns the index remote of the named registry or source, following `replace-with`, or
+    /// `None` if the name isn't configured.
+    ///
+    /// `crates-io` always resolves, to the crates.io git index if it isn't replaced. Sources that
+    /// aren't registries, such as `local-registry` and `directory` sources, resolve to `None`.
+    pub fn registry_remote(&self, name: &str) -> Result<Option<Remote>, Error> {
+        let mut name = name;
+        let mut seen = Vec::new();
+        loop {
+            if seen.contains(&name) {
+                return Err(Error::SourceReplacementCycle(name.to_string()));
+            }
+            seen.push(name);
+
+            let source = self.source.get(name);
+            if let Some(replacement) = source.and_then(|source| source.replace_with.as_deref()) {
+                name = replacement;
+                continue;
+            }
+
+            let url = source
+                .and_then(|source| source.registry.as_deref())
+                .or_else(|| {
+                    self.registries
+                        .get(name)
+                        .and_then(|registry| registry.index.as_deref())
+                })
+                .or_else(|| (name == CRATES_IO).then_some(CRATES_IO_INDEX));
+            return url.map(str::parse).transpose();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_registry_remote() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        assert_that!(
+            CargoConfig::load_from(temp.path())?.registry_remote("internal")?,
+            none()
+        );
+
+        std::fs::write(
+            temp.path().join("config.toml"),
+            r#"
+[registries.internal]
+index = "sparse+https://cargo.example.com/index/"
+
+[source.crates-io]
+replace-with = "mirror"
+
+[source.mirror]
+registry = "https://git.example.com/crates.io-index"
+
+[source.vendored]
+directory = "vendor"
+
+[source.loop]
+replace-with = "again"
+
+[source.again]
+replace-with = "loop"
+"#,
+        )?;
+        let config = CargoConfig::load_from(temp.path())?;
+
+        assert_that!(
+            config.registry_remote("internal")?,
+            some(eq(Remote::Sparse(
+                "https://cargo.example.com/index/".to_string()
+            )))
+        );
+        assert_that!(
+            config.registry_remote(CRATES_IO)?,
+            some(eq(Remote::Git(
+                "https://git.example.com/crates.io-index".to_string()
+            )))
+        );
+        assert_that!(config.registry_remote("vendored")?, none());
+        assert_that!(config.registry_remote("missing")?, none());
+        assert_that!(
+            config.registry_remote("loop"),
+            err(matches_pattern!(Error::SourceReplacementCycle(eq("loop"))))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader},
+};
+
+use git2::{ErrorCode, Oid, Repository};
+
+use super::{
+    krate::{self, Version},
+    Error, Index,
+};
+
+impl Index {
+    /// Returns the commit that the index repository's `HEAD` points to, or `None` if the index
+    /// has never been updated.
+    pub fn head(&self) -> Result<Option<String>, Error> {
+        match self.repository()?.head() {
+            Ok(head) => Ok(Some(head.peel_to_commit()?.id().to_string())),
+            Err(e) if matches!(e.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the versions that were added or changed between the given commit and the current
+    /// `HEAD`, such as those published by an [`Index::update`].
+    ///
+    /// A version counts as changed if its line in the index file differs at all, which includes
+    /// it being yanked or unyanked. Versions that were removed aren't returned.
+    ///
+    /// Returns `None` if the given commit is no longer in the repository, as happens when the
+    /// crates.io index is squashed, in which case the caller has to fall back to the whole index.
+    #[tracing::instrument(err)]
+    pub fn changed_since(&self, since: &str) -> Result<Option<Vec<Version>>, Error> {
+        let repo = self.repository()?;
+        let old = match repo.find_commit(Oid::from_str(since)?) {
+            Ok(commit) => commit.tree()?,
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                tracing::warn!(since, "commit is no longer in the index");
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let new = repo.head()?.peel_to_tree()?;
+        let diff = repo.diff_tree_to_tree(Some(&old), Some(&new), None)?;
+
+        let mut versions = Vec::new();
+        for delta in diff.deltas() {
+            let new_file = delta.new_file();
+            if new_file.id().is_zero() {
+                continue;
+            }
+            let Some(path) = new_file.path() else {
+                continue;
+            };
+            if path.starts_with(".github") || path.extension().is_some_and(|ext| ext == "json") {
+                continue;
+            }
+
+            let old_lines: HashSet<String> = if delta.old_file().id().is_zero() {
+                HashSet::new()
+            } else {
+                blob_lines(&repo, delta.old_file().id())?
+                    .into_iter()
+                    .collect()
+            };
+            for line in blob_lines(&repo, new_file.id())? {
+                if !line.trim().is_empty() && !old_lines.contains(&line) {
+                    versions.push(serde_json::from_str(&line)?);
+                }
+            }
+        }
+
+        Ok(Some(versions))
+    }
+}
+
+/// Reads the lines of an index file stored in the repository, decompressing it if necessary.
+fn blob_lines(repo: &Repository, id: Oid) -> Result<Vec<String>, Error> {
+    let blob = repo.find_blob(id)?;
+    let reader = krate::decompress_if_gzipped(BufReader::new(blob.content()))?;
+    let lines = reader.lines().collect::<std::io::Result<_>>()?;
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_changed_since() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
+        let repo = index.repository()?;
+        assert_that!(index.head()?, none());
+
+        let line = |name: &str, num: &str, yanked: bool| {
+            format!(r#"{{"name":"{name}","vers":"{num}","cksum":"","yanked":{yanked}}}"#)
+        };
+        let commit = |files: &[(&str, Vec<String>)]| -> anyhow::Result<String> {
+            for (path, lines) in files {
+                let path = temp.path().join(path);
+                std::fs::create_dir_all(path.parent().expect("index file parent"))?;
+                std::fs::write(path, lines.join("\n"))?;
+            }
+            let mut git_index = repo.index()?;
+            git_index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+            let tree = repo.find_tree(git_index.write_tree()?)?;
+            let signature = git2::Signature::now("test", "test@example.com")?;
+            let parents = match repo.head() {
+                Ok(head) => vec![head.peel_to_commit()?],
+                Err(_) => vec![],
+            };
+            let oid = repo.commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "index",
+                &tree,
+                &parents.iter().collect::<Vec<_>>(),
+            )?;
+            Ok(oid.to_string())
+        };
+
+        let first = commit(&[
+            ("se/rd/serde", vec![line("serde", "1.0.0", false)]),
+            ("3/f/foo", vec![line("foo", "0.1.0", false)]),
+        ])?;
+        assert_that!(index.head()?, some(eq(first.as_str())));
+
+        commit(&[
+            (
+                "se/rd/serde",
+                vec![line("serde", "1.0.0", true), line("serde", "1.0.1", false)],
+            ),
+            ("3/b/bar", vec![line("bar", "0.1.0", false)]),
+            ("config.json", vec!["{}".to_string()]),
+        ])?;
+
+        let mut changed: Vec<(String, String)> = index
+            .changed_since(&first)?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|version| (version.name().to_string(), version.num().to_string()))
+            .collect();
+        changed.sort();
+        assert_that!(
+            changed,
+            eq(vec![
+                ("bar".to_string(), "0.1.0".to_string()),
+                ("serde".to_string(), "1.0.0".to_string()),
+                ("serde".to_string(), "1.0.1".to_string()),
+            ])
+        );
+
+        // A commit that has been squashed away can't be diffed against.
+        assert_that!(
+            index.changed_since("0123456789abcdef0123456789abcdef01234567")?,
+            none()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use flate2::bufread::GzDecoder;
+use serde::Deserialize;
+
+use super::Error;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Opens an index file for reading, decompressing it if necessary.
+pub(crate) fn open_index_file(path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+    decompress_if_gzipped(BufReader::new(File::open(path)?))
+}
+
+/// Wraps a reader over an index file in a gzip decoder if the file is compressed.
+///
+/// Index files may have been gzipped to save space, whether or not they were given a `.gz`
+/// extension, so we sniff for the gzip magic number rather than trusting the name.
+pub(crate) fn decompress_if_gzipped<'a, R>(mut reader: R) -> std::io::Result<Box<dyn BufRead + 'a>>
+where
+    R: BufRead + 'a,
+{
+    Ok(if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Box::new(BufReader::new(GzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct Krate {
+    name: String,
+    versions: Vec<Version>,
+}
+
+impl Krate {
+    #[tracing::instrument(err)]
+    pub fn open(name: &str, path: &Path) -> Result<Self, Error> {
+        Self::from_reader(name, open_index_file(path)?)
+    }
+
+    /// Parses an uncompressed index file from the given reader.
+    pub fn from_reader<R>(name: &str, reader: R) -> Result<Self, Error>
+    where
+        R: BufRead,
+    {
+        let mut versions = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            versions.push(serde_json::from_str(&line)?);
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            versions,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Iterates over the versions of the crate in the order they were published.
+    ///
+    /// The index only ever appends to a crate's file, so this is the order the versions appear in
+    /// the file.
+    pub fn iter_versions(&self) -> impl Iterator<Item = (&String, &Version)> {
+        self.versions.iter().map(|version| (&version.vers, version))
+    }
+
+    /// Iterates over the versions of the crate in ascending semver order.
+    ///
+    /// Any versions that aren't valid semver come last, in the order they were published.
+    pub fn iter_versions_sorted(&self) -> impl Iterator<Item = (&String, &Version)> {
+        let mut versions: Vec<_> = self
+            .versions
+            .iter()
+            .map(|version| (semver::Version::parse(&version.vers).ok(), version))
+            .collect();
+        versions.sort_by(|(a, _), (b, _)| cmp_semver(a.as_ref(), b.as_ref()));
+
+        versions
+            .into_iter()
+            .map(|(_semver, version)| (&version.vers, version))
+    }
+
+    pub fn version_nums(&self) -> impl Iterator<Item = &str> {
+        self.versions.iter().map(|version| version.vers.as_str())
+    }
+}
+
+/// Just the version number and yanked flag of an index entry, for when nothing else is needed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct VersionSummary {
+    #[serde(rename = "vers")]
+    pub num: String,
+    #[serde(default)]
+    pub yanked: bool,
+}
+
+/// Reads the version summaries from an uncompressed index file, newest first by semver. Versions
+/// that aren't valid semver come last, in the order they were published.
+pub(crate) fn read_summaries<R: BufRead>(reader: R) -> Result<Vec<VersionSummary>, Error> {
+    let mut summaries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let summary: VersionSummary = serde_json::from_str(&line)?;
+        summaries.push((semver::Version::parse(&summary.num).ok(), summary));
+    }
+
+    summaries.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) => b.cmp(a),
+        _ => cmp_semver(a.as_ref(), b.as_ref()),
+    });
+    Ok(summaries
+        .into_iter()
+        .map(|(_semver, summary)| summary)
+        .collect())
+}
+
+/// Orders versions by semver, with versions that couldn't be parsed after all the others.
+fn cmp_semver(a: Option<&semver::Version>, b: Option<&semver::Version>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(b),
+        // None sorts before Some, so compare the other way round to put invalid versions last.
+        _ => b.is_some().cmp(&a.is_some()),
+    }
+}
+
+/// Orders version numbers by semver, with version numbers that aren't valid semver after the
+/// others, and ties broken by the version numbers themselves.
+pub(crate) fn cmp_version_nums(a: &str, b: &str) -> Ordering {
+    cmp_semver(
+        semver::Version::parse(a).ok().as_ref(),
+        semver::Version::parse(b).ok().as_ref(),
+    )
+    .then_with(|| a.cmp(b))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Version {
+    name: String,
+    vers: String,
+    cksum: String,
+    #[serde(default)]
+    yanked: bool,
+    #[serde(default)]
+    rust_version: Option<String>,
+    #[serde(default)]
+    crate_size: Option<u64>,
+    #[serde(default)]
+    deps: Vec<Dependency>,
+    #[serde(default)]
+    features: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    features2: BTreeMap<String, Vec<String>>,
+}
+
+impl Version {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn num(&self) -> &str {
+        &self.vers
+    }
+
+    pub fn cksum(&self) -> &str {
+        &self.cksum
+    }
+
+    pub fn is_yanked(&self) -> bool {
+        self.yanked
+    }
+
+    /// The minimum supported Rust version declared by the crate, if any.
+    pub fn rust_version(&self) -> Option<&str> {
+        self.rust_version.as_deref()
+    }
+
+    /// The size of the `.crate` file in bytes, if the index records it.
+    pub fn crate_size(&self) -> Option<u64> {
+        self.crate_size
+    }
+
+    pub fn dependencies(&self) -> &[Dependency] {
+        &self.deps
+    }
+
+    /// The features declared by the version, as published to the index.
+    ///
+    /// Features using newer syntax (`dep:` or `?/`) are stored separately by the registry in
+    /// `features2` so that older versions of cargo can ignore them; both tables are merged here.
+    pub fn features(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.features
+            .iter()
+            .chain(self.features2.iter())
+            .map(|(name, enables)| (name.as_str(), enables.as_slice()))
+    }
+
+    /// The features enabled by the `default` feature, if the version declares one.
+    pub fn default_features(&self) -> &[String] {
+        self.features
+            .get("default")
+            .or_else(|| self.features2.get("default"))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+// Versions are identified by their crate name and version number alone, so that the same version
+// read from different sources compares equal even if other fields (such as `yanked`) differ.
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.vers == other.vers
+    }
+}
+
+impl Eq for Version {}
+
+impl Hash for Version {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.vers.hash(state);
+    }
+}
+
+/// Versions are ordered by crate name, then by semver, with version numbers that aren't valid
+/// semver after the others.
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.name
+            .cmp(&other.name)
+            .then_with(|| cmp_version_nums(&self.vers, &other.vers))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Merges versions from one or more sources, such as several indices, into a sorted list with
+/// one entry per crate version.
+///
+/// Where the same version appears more than once, the first is kept.
+pub fn dedup_versions(versions: impl IntoIterator<Item = Version>) -> Vec<Version> {
+    let mut seen = HashSet::new();
+    let mut versions: Vec<Version> = versions
+        .into_iter()
+        .filter(|version| seen.insert((version.name.clone(), version.vers.clone())))
+        .collect();
+    versions.sort();
+    versions
+}
+
+/// A dependency of a crate version, as recorded in the index.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Dependency {
+    name: String,
+    req: String,
+    #[serde(default)]
+    optional: bool,
+    #[serde(default)]
+    target: Option<String>,
+    #[serde(default)]
+    kind: Option<String>,
+    #[serde(default)]
+    package: Option<String>,
+}
+
+impl Dependency {
+    /// The name of the crate depended on, which may differ from the name the dependency is
+    /// referred to by if it was renamed.
+    pub fn crate_name(&self) -> &str {
+        self.package.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn req(&self) -> &str {
+        &self.req
+    }
+
+    pub fn is_optional(&self) -> bool {
+        self.optional
+    }
+
+    /// The `cfg` expression or target triple the dependency is limited to, if any.
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    /// Either `normal`, `build`, or `dev`.
+    pub fn kind(&self) -> &str {
+        self.kind.as_deref().unwrap_or("normal")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_dedup_versions() -> anyhow::Result<()> {
+        let version = |name: &str, num: &str, yanked: bool| {
+            serde_json::from_str::<Version>(&format!(
+                r#"{{"name":"{name}","vers":"{num}","cksum":"","yanked":{yanked}}}"#
+            ))
+        };
+
+        let versions = dedup_versions([
+            version("foo", "1.10.0", false)?,
+            version("foo", "1.2.0", true)?,
+            version("bar", "0.1.0", false)?,
+            version("foo", "not-semver", false)?,
+            version("foo", "1.2.0", false)?,
+        ]);
+        assert_that!(
+            versions
+                .iter()
+                .map(|version| (version.name(), version.num(), version.is_yanked()))
+                .collect::<Vec<_>>(),
+            eq(vec![
+                ("bar", "0.1.0", false),
+                ("foo", "1.2.0", true),
+                ("foo", "1.10.0", false),
+                ("foo", "not-semver", false),
+            ])
+        );
+        assert_that!(
+            version("foo", "1.2.0", true)?,
+            eq(version("foo", "1.2.0", false)?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_versions_sorted() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("foo");
+        let lines = ["0.2.0", "0.10.0", "bogus", "0.2.0-rc.1", "0.9.1"]
+            .map(|num| format!(r#"{{"name":"foo","vers":"{num}","cksum":""}}"#));
+        std::fs::write(&path, lines.join("\n"))?;
+
+        let krate = Krate::open("foo", &path)?;
+        assert_that!(
+            krate.version_nums().collect::<Vec<_>>(),
+            eq(vec!["0.2.0", "0.10.0", "bogus", "0.2.0-rc.1", "0.9.1"])
+        );
+        assert_that!(
+            krate
+                .iter_versions_sorted()
+                .map(|(num, _version)| num.as_str())
+                .collect::<Vec<_>>(),
+            eq(vec!["0.2.0-rc.1", "0.2.0", "0.9.1", "0.10.0", "bogus"])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_summaries() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("foo");
+        std::fs::write(
+            &path,
+            concat!(
+                r#"{"name":"foo","vers":"0.9.0","cksum":"","yanked":false}"#,
+                "\n",
+                r#"{"name":"foo","vers":"bogus","cksum":""}"#,
+                "\n",
+                r#"{"name":"foo","vers":"0.10.0","cksum":"","yanked":true}"#,
+                "\n",
+            ),
+        )?;
+
+        assert_that!(
+            read_summaries(open_index_file(&path)?)?,
+            eq(vec![
+                VersionSummary {
+                    num: "0.10.0".to_string(),
+                    yanked: true,
+                },
+                VersionSummary {
+                    num: "0.9.0".to_string(),
+                    yanked: false,
+                },
+                VersionSummary {
+                    num: "bogus".to_string(),
+                    yanked: false,
+                },
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_features() -> anyhow::Result<()> {
+        let version: Version = serde_json::from_str(
+            r#"{"name":"foo","vers":"1.0.0","deps":[],"cksum":"","features":{"default":["std"],"std":[]},"features2":{"serde":["dep:serde"]},"yanked":false}"#,
+        )?;
+        assert_that!(
+            version
+                .features()
+                .map(|(name, enables)| (name.to_string(), enables.to_vec()))
+                .collect::<Vec<_>>(),
+            eq(vec![
+                ("default".to_string(), vec!["std".to_string()]),
+                ("std".to_string(), vec![]),
+                ("serde".to_string(), vec!["dep:serde".to_string()]),
+            ])
+        );
+        assert_that!(version.default_features(), eq(&["std".to_string()][..]));
+
+        let version: Version = serde_json::from_str(r#"{"name":"foo","vers":"1.0.0","cksum":""}"#)?;
+        assert_that!(version.features().count(), eq(0));
+        assert_that!(version.default_features().is_empty(), eq(true));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::File,
+    io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    os::unix::ffi::OsStringExt,
+    path::PathBuf,
+    sync::{Mutex, RwLock},
+};
+
+use flate2::{bufread::GzDecoder, write::GzEncoder, Compression};
+use fs2::FileExt;
+
+use super::Error;
+
+/// The file, relative to the index root, that packed index entries are appended to.
+///
+/// The leading dot keeps it out of walks for crate names, and git ignores untracked files when
+/// the index is updated.
+pub(crate) const PACK_FILE: &str = ".librarian-pack";
+
+/// A single append-only file holding gzipped index entries, as a cheaper alternative to one file
+/// per crate on filesystems where inodes or small files are expensive.
+///
+/// Each record is the length of the crate name as a little endian `u16`, the name, the length of
+/// the compressed entry as a little endian `u64`, then the entry itself. A later record for the
+/// same crate supersedes any earlier ones. A record truncated by an interrupted append is ignored,
+/// and overwritten by the next append.
+///
+/// Crate names are case insensitive, so they're lowercased in the pack, as they are in the paths
+/// of loose index files. Appends take an exclusive lock on the file, so that other processes with
+/// the same pack open don't lose records.
+#[derive(Debug)]
+pub(crate) struct Pack {
+    path: PathBuf,
+    /// The records in the pack, once it has been scanned.
+    scanned: RwLock<Option<Scanned>>,
+    /// Held while appending, so that records aren't interleaved.
+    append: Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct Scanned {
+    /// The offset and length of the latest compressed entry for each crate, keyed by the
+    /// lowercased name.
+    entries: HashMap<String, (u64, u64)>,
+    /// The length of the pack up to the end of the last complete record.
+    len: u64,
+}
+
+/// The number of index entries held in each tier, as returned by
+/// [`Index::cache_stats`](super::Index::cache_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Crates with their own file in the index checkout, whether gzipped or not.
+    pub loose: usize,
+
+    /// The total size of the loose files, in bytes.
+    pub loose_bytes: u64,
+
+    /// Crates in the pack, including any that are shadowed by a loose file.
+    pub packed: usize,
+
+    /// The size of the pack file, in bytes.
+    pub pack_bytes: u64,
+}
+
+impl Pack {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            scanned: RwLock::default(),
+            append: Mutex::default(),
+        }
+    }
+
+    /// Returns a reader over the decompressed entry for the given crate, if the pack has one.
+    pub(crate) fn read(&self, name: &str) -> Result<Option<Box<dyn BufRead>>, Error> {
+        let name = name.to_ascii_lowercase();
+        let Some((offset, len)) =
+            self.with_scanned(|scanned| scanned.entries.get(&name).copied())?
+        else {
+            return Ok(None);
+        };
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Some(Box::new(BufReader::new(GzDecoder::new(
+            BufReader::new(file.take(len)),
+        )))))
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> Result<bool, Error> {
+        let name = name.to_ascii_lowercase();
+        self.with_scanned(|scanned| scanned.entries.contains_key(&name))
+    }
+
+    /// Returns the sorted names of the crates in the pack.
+    pub(crate) fn names(&self) -> Result<Vec<String>, Error> {
+        let mut names =
+            self.with_scanned(|scanned| scanned.entries.keys().cloned().collect::<Vec<_>>())?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// Returns the number of crates in the pack, and the size of the pack file in bytes.
+    pub(crate) fn stats(&self) -> Result<(usize, u64), Error> {
+        self.with_scanned(|scanned| (scanned.entries.len(), scanned.len))
+    }
+
+    /// Compresses and appends an entry for the given crate, superseding any earlier entry.
+    pub(crate) fn append(&self, name: &str, data: &[u8]) -> Result<(), Error> {
+        let name = name.to_ascii_lowercase();
+        let name_len =
+            u16::try_from(name.len()).map_err(|_e| Error::InvalidCrateName(name.clone().into()))?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+
+        let _guard = self.append.lock().expect("index pack append lock");
+        let mut file = File::options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&self.path)?;
+        file.lock_exclusive()?;
+
+        // If the file isn't the length this pack last saw, then either another process has
+        // appended to it since, or an earlier append was interrupted, and it needs to be scanned
+        // again to find the end of the last complete record.
+        let mut start = self.with_scanned(|scanned| scanned.len)?;
+        let file_len = file.metadata()?.len();
+        if file_len != start {
+            let rescanned = self.scan()?;
+            start = rescanned.len;
+            *self.scanned.write().expect("index pack lock") = Some(rescanned);
+        }
+        // Drop any record left incomplete by an interrupted append. Nothing else can be writing
+        // to the file while the lock is held.
+        if start < file_len {
+            file.set_len(start)?;
+        }
+        file.seek(SeekFrom::Start(start))?;
+
+        let mut record = Vec::with_capacity(2 + name.len() + 8 + compressed.len());
+        record.extend_from_slice(&name_len.to_le_bytes());
+        record.extend_from_slice(name.as_bytes());
+        record.extend_from_slice(&(compressed.len() as u64).to_le_bytes());
+        record.extend_from_slice(&compressed);
+        file.write_all(&record)?;
+        // The loose file is removed once this returns, so the record has to be durable first.
+        file.sync_data()?;
+
+        let mut scanned = self.scanned.write().expect("index pack lock");
+        let scanned = scanned.get_or_insert_with(Scanned::default);
+        let offset = start + 2 + name.len() as u64 + 8;
+        scanned
+            .entries
+            .insert(name, (offset, compressed.len() as u64));
+        scanned.len = start + record.len() as u64;
+
+        Ok(())
+    }
+
+    fn with_scanned<T>(&self, f: impl FnOnce(&Scanned) -> T) -> Result<T, Error> {
+        if let Some(scanned) = self.scanned.read().expect("index pack lock").as_ref() {
+            return Ok(f(scanned));
+        }
+
+        let mut guard = self.scanned.write().expect("index pack lock");
+        if guard.is_none() {
+            *guard = Some(self.scan()?);
+        }
+        Ok(f(guard.as_ref().expect("pack was just scanned")))
+    }
+
+    /// Reads the record headers in the pack, skipping over the entries themselves.
+    fn scan(&self) -> Result<Scanned, Error> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Scanned::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut scanned = Scanned::default();
+        loop {
+            let mut name_len = [0; 2];
+            let mut name = Vec::new();
+            let mut data_len = [0; 8];
+            let header = reader
+                .read_exact(&mut name_len)
+                .and_then(|()| {
+                    name.resize(u16::from_le_bytes(name_len) as usize, 0);
+                    reader.read_exact(&mut name)
+                })
+                .and_then(|()| reader.read_exact(&mut data_len));
+            match header {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+
+            let offset = scanned.len + 2 + name.len() as u64 + 8;
+            let data_len = u64::from_le_bytes(data_len);
+            if offset + data_len > file_len {
+                break;
+            }
+            let name = String::from_utf8(name)
+                .map_err(|e| Error::InvalidCrateName(OsString::from_vec(e.into_bytes())))?;
+            reader.seek_relative(data_len as i64)?;
+            scanned
+                .entries
+                .insert(name.to_ascii_lowercase(), (offset, data_len));
+            scanned.len = offset + data_len;
+        }
+
+        if scanned.len < file_len {
+            tracing::warn!(path = ?self.path, "ignoring incomplete record at the end of the index pack");
+        }
+        Ok(scanned)
+    }
+}
@@ -0,0 +1,93 @@
+use std::{fmt, str::FromStr};
+
+use super::Error;
+
+/// An index remote, as it would be written in a cargo source URL.
+///
+/// Cargo prefixes registry URLs with the protocol used to fetch the index: `registry+` for a git
+/// index and `sparse+` for a sparse index. Both are accepted here, along with plain git URLs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Remote {
+    /// A git index, with any `registry+` prefix removed.
+    Git(String),
+
+    /// A sparse (HTTP) index, with the `sparse+` prefix removed.
+    Sparse(String),
+}
+
+impl Remote {
+    /// The URL without the scheme prefix.
+    pub fn url(&self) -> &str {
+        match self {
+            Self::Git(url) | Self::Sparse(url) => url,
+        }
+    }
+}
+
+impl FromStr for Remote {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Only treat the text before a `+` as a prefix if it's followed by a URL, so that scp-style
+        // remotes and local paths containing a `+` are left alone.
+        let prefixed = s.split_once('+').filter(|(prefix, rest)| {
+            !prefix.is_empty()
+                && prefix.bytes().all(|c| c.is_ascii_alphanumeric())
+                && rest.contains("://")
+        });
+
+        match prefixed {
+            None => Ok(Self::Git(s.to_string())),
+            Some(("registry", url)) => Ok(Self::Git(url.to_string())),
+            Some(("sparse", url)) => Ok(Self::Sparse(url.to_string())),
+            Some((prefix, _url)) => Err(Error::UnsupportedRemoteScheme(prefix.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Git(url) => f.write_str(url),
+            Self::Sparse(url) => write!(f, "sparse+{url}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_from_str() -> anyhow::Result<()> {
+        for (s, expected) in [
+            (
+                "https://github.com/rust-lang/crates.io-index",
+                Remote::Git("https://github.com/rust-lang/crates.io-index".to_string()),
+            ),
+            (
+                "registry+https://github.com/rust-lang/crates.io-index",
+                Remote::Git("https://github.com/rust-lang/crates.io-index".to_string()),
+            ),
+            (
+                "sparse+https://index.crates.io/",
+                Remote::Sparse("https://index.crates.io/".to_string()),
+            ),
+            (
+                "git@github.com:foo/c++-index.git",
+                Remote::Git("git@github.com:foo/c++-index.git".to_string()),
+            ),
+        ] {
+            assert_that!(s.parse::<Remote>()?, eq(expected));
+        }
+
+        assert_that!(
+            "ftp+https://example.com/".parse::<Remote>(),
+            err(matches_pattern!(Error::UnsupportedRemoteScheme(eq("ftp"))))
+        );
+
+        Ok(())
+    }
+}
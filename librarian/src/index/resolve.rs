@@ -0,0 +1,158 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+use semver::VersionReq;
+
+use super::{
+    krate::{Krate, Version},
+    Error, Index,
+};
+
+impl Index {
+    /// Returns the given crate and everything it transitively depends on, picking the highest
+    /// non-yanked version that matches each requirement.
+    ///
+    /// This is much looser than cargo's resolver: requirements aren't unified across the graph,
+    /// so several versions of a crate may be returned. Dev-dependencies and optional dependencies
+    /// are skipped, since features aren't considered, while platform-specific dependencies are
+    /// always included. The result is sorted by crate name, then version.
+    #[tracing::instrument(err)]
+    pub fn resolve_closure(
+        &self,
+        name: &str,
+        req: &VersionReq,
+    ) -> Result<Vec<(String, Version)>, Error> {
+        let repo = self.repository()?;
+        let mut batch = self.batch(&repo)?;
+        let mut seen_reqs = HashSet::new();
+        let mut resolved = BTreeMap::new();
+        let mut queue = VecDeque::from([(name.to_string(), req.clone())]);
+
+        while let Some((name, req)) = queue.pop_front() {
+            // Requirements are compared by their string form, which is enough to stop cycles.
+            if !seen_reqs.insert((name.to_ascii_lowercase(), req.to_string())) {
+                continue;
+            }
+
+            let krate = batch.get(&name)?;
+            let version =
+                highest_matching(krate, &req).ok_or_else(|| Error::NoMatchingVersion {
+                    name: name.clone(),
+                    req: req.to_string(),
+                })?;
+
+            let key = (
+                version.name().to_string(),
+                semver::Version::parse(version.num())?,
+            );
+            if resolved.contains_key(&key) {
+                continue;
+            }
+
+            for dep in version.dependencies() {
+                if dep.kind() == "dev" || dep.is_optional() {
+                    continue;
+                }
+                queue.push_back((dep.crate_name().to_string(), VersionReq::parse(dep.req())?));
+            }
+            resolved.insert(key, version.clone());
+        }
+
+        Ok(resolved
+            .into_iter()
+            .map(|((name, _num), version)| (name, version))
+            .collect())
+    }
+}
+
+fn highest_matching<'a>(krate: &'a Krate, req: &VersionReq) -> Option<&'a Version> {
+    krate
+        .iter_versions()
+        .filter(|(_num, version)| !version.is_yanked())
+        .filter_map(|(num, version)| Some((semver::Version::parse(num).ok()?, version)))
+        .filter(|(num, _version)| req.matches(num))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_num, version)| version)
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_closure() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
+
+        write_index_file(
+            &index,
+            "app",
+            &[(
+                "1.0.0",
+                r#"[
+                    {"name":"lib","req":"^1","kind":"normal"},
+                    {"name":"test-only","req":"^1","kind":"dev"},
+                    {"name":"opt","req":"^1","optional":true},
+                    {"name":"renamed","package":"old","req":"^0.1","target":"cfg(windows)"}
+                ]"#,
+            )],
+        )?;
+        write_index_file(
+            &index,
+            "lib",
+            &[
+                ("1.0.0", "[]"),
+                ("1.1.0", r#"[{"name":"app","req":"^1"}]"#),
+                ("2.0.0", "[]"),
+            ],
+        )?;
+        write_index_file(&index, "old", &[("0.1.0", "[]"), ("0.1.1", "[]")])?;
+
+        let resolved: Vec<(String, String)> = index
+            .resolve_closure("app", &VersionReq::STAR)?
+            .into_iter()
+            .map(|(name, version)| (name, version.num().to_string()))
+            .collect();
+        assert_that!(
+            resolved,
+            eq(vec![
+                ("app".to_string(), "1.0.0".to_string()),
+                ("lib".to_string(), "1.1.0".to_string()),
+                ("old".to_string(), "0.1.1".to_string()),
+            ])
+        );
+
+        assert_that!(
+            index.resolve_closure("lib", &VersionReq::parse("^3")?),
+            err(matches_pattern!(Error::NoMatchingVersion {
+                name: eq("lib"),
+                req: eq("^3"),
+            }))
+        );
+
+        Ok(())
+    }
+
+    fn write_index_file(
+        index: &Index,
+        name: &str,
+        versions: &[(&str, &str)],
+    ) -> anyhow::Result<()> {
+        let path = index.crate_path(name)?;
+        std::fs::create_dir_all(path.parent().expect("index file parent"))?;
+
+        let lines: Vec<String> = versions
+            .iter()
+            .map(|(num, deps)| {
+                let deps = deps.split_whitespace().collect::<String>();
+                format!(
+                    r#"{{"name":"{name}","vers":"{num}","deps":{deps},"cksum":"","features":{{}},"yanked":false}}"#
+                )
+            })
+            .collect();
+        std::fs::write(path, lines.join("\n"))?;
+
+        Ok(())
+    }
+}
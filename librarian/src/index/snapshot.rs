@@ -0,0 +1,188 @@
+use std::{
+    fs::File,
+    io::{BufReader, ErrorKind},
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+use tar::Archive;
+use tempfile::tempdir_in;
+
+use super::{krate, Error, Index};
+
+/// The file at the root of every index, used to find the root within a snapshot.
+const CONFIG_FILE: &str = "config.json";
+
+impl Index {
+    /// Creates an index at `path` from a snapshot of the index tree, rather than cloning it.
+    ///
+    /// The snapshot is a tarball, optionally gzipped, of an index checkout. Its root is wherever
+    /// `config.json` is, so snapshots that wrap the index in a top-level directory (as GitHub's
+    /// archives do) work as is. Anything outside the root, and any `.git` directory, is ignored.
+    ///
+    /// `path` must not exist, or be an empty directory, failing with [`Error::NotEmpty`]
+    /// otherwise. The snapshot is extracted alongside it, and only moved into place once it has
+    /// been extracted in full. The index has no commits afterwards, so crates are always read
+    /// from the extracted files.
+    #[tracing::instrument(err)]
+    pub fn from_snapshot(path: &Path, snapshot: &Path) -> Result<Self, Error> {
+        match std::fs::read_dir(path) {
+            Ok(mut entries) => {
+                if entries.next().is_some() {
+                    return Err(Error::NotEmpty(path.to_path_buf()));
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) if e.kind() == ErrorKind::NotADirectory => {
+                return Err(Error::NotADirectory(path.to_path_buf()))
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let root = snapshot_root(snapshot)?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        std::fs::create_dir_all(parent)?;
+        let temp = tempdir_in(parent)?;
+
+        let mut archive = Archive::new(open_snapshot(snapshot)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_type = entry.header().entry_type();
+            if !(entry_type.is_file() || entry_type.is_dir()) {
+                continue;
+            }
+
+            let entry_path = entry.path()?.into_owned();
+            let Ok(relative) = entry_path.strip_prefix(&root) else {
+                continue;
+            };
+            if relative
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(Error::UnsafeSnapshotPath(entry_path));
+            }
+            if relative.starts_with(".git") || relative.as_os_str().is_empty() {
+                continue;
+            }
+
+            let dest = temp.path().join(relative);
+            if entry_type.is_dir() {
+                std::fs::create_dir_all(&dest)?;
+            } else {
+                if let Some(parent) = dest.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::io::copy(&mut entry, &mut File::create(&dest)?)?;
+            }
+        }
+
+        // An empty directory can be replaced by a rename, but can't be replaced by one that
+        // isn't.
+        match std::fs::remove_dir(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        // Temporary directories are only accessible to their owner.
+        std::fs::set_permissions(temp.path(), std::fs::Permissions::from_mode(0o755))?;
+        std::fs::rename(temp.path(), path)?;
+
+        Self::new(path)
+    }
+}
+
+/// Finds the directory within the snapshot that contains `config.json`.
+fn snapshot_root(snapshot: &Path) -> Result<PathBuf, Error> {
+    let mut archive = Archive::new(open_snapshot(snapshot)?);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?;
+        if path.file_name().is_some_and(|name| name == CONFIG_FILE) {
+            // Only the shallowest config.json counts, but tarballs list parents before children,
+            // so the first one will do.
+            return Ok(path.parent().map(Path::to_path_buf).unwrap_or_default());
+        }
+    }
+
+    Err(Error::InvalidSnapshot(snapshot.to_path_buf()))
+}
+
+fn open_snapshot(snapshot: &Path) -> Result<impl std::io::Read, Error> {
+    Ok(krate::decompress_if_gzipped(BufReader::new(File::open(
+        snapshot,
+    )?))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{write::GzEncoder, Compression};
+    use googletest::prelude::*;
+    use tar::{Builder, Header};
+
+    use super::*;
+
+    #[test]
+    fn test_from_snapshot() -> anyhow::Result<()> {
+        let mut builder = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, data) in [
+            ("crates.io-index-master/config.json", "{}"),
+            (
+                "crates.io-index-master/se/rd/serde",
+                r#"{"name":"serde","vers":"1.0.0","cksum":"","yanked":false}"#,
+            ),
+            ("crates.io-index-master/.git/HEAD", "ref: refs/heads/master"),
+            ("README", "outside the index"),
+        ] {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data.as_bytes())?;
+        }
+        let temp = tempfile::tempdir()?;
+        let snapshot = temp.path().join("snapshot.tar.gz");
+        std::fs::write(&snapshot, builder.into_inner()?.finish()?)?;
+
+        let index = Index::from_snapshot(&temp.path().join("index"), &snapshot)?;
+        assert_that!(
+            index
+                .get(&"serde".parse()?)?
+                .version_nums()
+                .collect::<Vec<_>>(),
+            elements_are![eq("1.0.0")]
+        );
+        assert_that!(index.crate_names()?, eq(vec!["serde".to_string()]));
+        assert_that!(temp.path().join("index/README").exists(), eq(false));
+
+        // An index that's already there isn't extracted over.
+        assert_that!(
+            Index::from_snapshot(&temp.path().join("index"), &snapshot),
+            err(matches_pattern!(Error::NotEmpty(eq(temp
+                .path()
+                .join("index")))))
+        );
+        let empty = temp.path().join("empty");
+        std::fs::create_dir(&empty)?;
+        assert_that!(
+            Index::from_snapshot(&empty, &snapshot)?.crate_names()?,
+            eq(vec!["serde".to_string()])
+        );
+
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        header.set_cksum();
+        builder.append_data(&mut header, "README", &b""[..])?;
+        std::fs::write(&snapshot, builder.into_inner()?)?;
+        assert_that!(
+            Index::from_snapshot(&temp.path().join("other"), &snapshot),
+            err(matches_pattern!(Error::InvalidSnapshot(eq(
+                snapshot.as_path()
+            ))))
+        );
+
+        Ok(())
+    }
+}
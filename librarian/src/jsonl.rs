@@ -0,0 +1,103 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::Serialize;
+
+/// A writer of JSON lines, shared between threads, that each record is flushed to as soon as it's
+/// written.
+pub(crate) struct JsonlWriter {
+    /// What's being written, for the warnings logged when writing fails.
+    name: &'static str,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonlWriter {
+    pub(crate) fn new<W>(name: &'static str, writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Self {
+            name,
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Opens a writer to the given file, or to stderr if the path is `-`.
+    pub(crate) fn open(name: &'static str, path: &Path) -> std::io::Result<Self> {
+        Ok(if path == Path::new("-") {
+            Self::new(name, std::io::stderr())
+        } else {
+            Self::new(name, BufWriter::new(File::create(path)?))
+        })
+    }
+
+    /// Writes a record as a single line.
+    ///
+    /// Failing to write a record isn't fatal to whatever it's reporting on, so write errors are
+    /// only logged.
+    pub(crate) fn write<T: Serialize>(&self, record: &T) {
+        let mut writer = self.writer.lock().expect("JSON lines writer lock");
+        let result = serde_json::to_writer(&mut *writer, record)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writeln!(writer))
+            .and_then(|()| writer.flush());
+        if let Err(e) = result {
+            tracing::warn!(?e, "error writing to {}", self.name);
+        }
+    }
+}
+
+impl fmt::Debug for JsonlWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonlWriter")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+
+    use googletest::prelude::*;
+
+    use super::*;
+
+    /// A writer that can be inspected after being handed to a [`JsonlWriter`].
+    #[derive(Clone, Default)]
+    pub(crate) struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Shared {
+        /// Returns everything written so far.
+        pub(crate) fn contents(&self) -> anyhow::Result<String> {
+            Ok(String::from_utf8(self.0.lock().expect("lock").clone())?)
+        }
+    }
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().expect("lock").write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write() -> anyhow::Result<()> {
+        let buf = Shared::default();
+        let writer = JsonlWriter::new("test", buf.clone());
+        writer.write(&("foo", 1));
+        writer.write(&["bar\nbaz"]);
+
+        assert_that!(buf.contents()?, eq("[\"foo\",1]\n[\"bar\\nbaz\"]\n"));
+
+        Ok(())
+    }
+}
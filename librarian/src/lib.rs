@@ -0,0 +1,23 @@
+#[cfg(feature = "async")]
+pub mod async_corpus;
+pub mod audit;
+pub mod breaker;
+pub mod cache;
+pub mod corpus;
+pub mod doctor;
+pub mod download;
+pub mod error_sink;
+pub mod filter;
+pub mod history;
+pub mod ignore;
+pub mod index;
+mod jsonl;
+pub mod licenses;
+pub mod order;
+pub mod output;
+pub mod progress;
+pub mod registry;
+pub mod telemetry;
+pub mod throttle;
+pub mod timing;
+pub mod verified;
@@ -0,0 +1,198 @@
+use std::{collections::BTreeMap, fmt, path::PathBuf};
+
+use serde::Serialize;
+use thiserror::Error;
+use vault::{Vault, WalkedVersion};
+
+/// The licenses declared by the crate versions in a vault.
+#[derive(Debug, Default, Serialize)]
+pub struct LicenseReport {
+    /// The number of versions declaring each SPDX expression in `license`, by expression.
+    pub licenses: BTreeMap<String, usize>,
+
+    /// The number of versions that only declare a `license-file`, without an SPDX expression.
+    pub license_file_only: usize,
+
+    /// The versions that declare neither a `license` nor a `license-file`, sorted by name and
+    /// version.
+    pub unlicensed: Vec<UnlicensedVersion>,
+
+    /// The manifests that couldn't be read or parsed, sorted by path. These aren't counted as
+    /// versions anywhere else in the report.
+    pub unreadable: Vec<UnreadableManifest>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct UnlicensedVersion {
+    pub name: String,
+    pub num: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct UnreadableManifest {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl LicenseReport {
+    /// Returns the total number of versions in the report.
+    pub fn total(&self) -> usize {
+        self.licenses.values().sum::<usize>() + self.license_file_only + self.unlicensed.len()
+    }
+}
+
+impl fmt::Display for LicenseReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut licenses: Vec<_> = self.licenses.iter().collect();
+        licenses.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        for (license, count) in licenses {
+            writeln!(f, "{count:>8}  {license}")?;
+        }
+        if self.license_file_only > 0 {
+            writeln!(f, "{:>8}  (license file only)", self.license_file_only)?;
+        }
+        if !self.unlicensed.is_empty() {
+            writeln!(f, "{:>8}  (none)", self.unlicensed.len())?;
+            writeln!(f)?;
+            writeln!(f, "Versions with no license declared:")?;
+            for version in &self.unlicensed {
+                writeln!(f, "{} {}", version.name, version.num)?;
+            }
+        }
+        if !self.unreadable.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Manifests that couldn't be read:")?;
+            for manifest in &self.unreadable {
+                writeln!(f, "{}: {}", manifest.path.display(), manifest.error)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the manifest of every crate version in the vault, and tallies the licenses they declare.
+///
+/// SPDX expressions are counted as written, apart from surrounding whitespace, so `MIT/Apache-2.0`
+/// and `MIT OR Apache-2.0` are counted separately. Manifests that can't be read, or are larger
+/// than the vault's limit, are listed in the report rather than failing it.
+#[tracing::instrument(err)]
+pub fn report(vault: &Vault) -> Result<LicenseReport, Error> {
+    let mut report = LicenseReport::default();
+    for version in vault.iter_crate_versions_lenient() {
+        let version = match version? {
+            WalkedVersion::Version(version) => version,
+            WalkedVersion::ManifestError { path, error } => {
+                report.unreadable.push(UnreadableManifest {
+                    path,
+                    error: error.to_string(),
+                });
+                continue;
+            }
+        };
+        let manifest = match vault.parse_manifest(&version.path) {
+            Ok(manifest) => manifest,
+            Err(error) => {
+                report.unreadable.push(UnreadableManifest {
+                    path: version.path,
+                    error: error.to_string(),
+                });
+                continue;
+            }
+        };
+
+        match (manifest.license().map(str::trim), manifest.license_file()) {
+            (Some(license), _) if !license.is_empty() => {
+                *report.licenses.entry(license.to_string()).or_default() += 1;
+            }
+            (_, Some(_license_file)) => report.license_file_only += 1,
+            _ => report.unlicensed.push(UnlicensedVersion {
+                name: version.crate_name,
+                num: version.version,
+            }),
+        }
+    }
+    report.unlicensed.sort();
+    report.unreadable.sort();
+
+    Ok(report)
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("vault error: {0:?}")]
+    Vault(#[from] vault::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_report() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf());
+        for (name, num, extra) in [
+            ("foo", "1.0.0", "license = \"MIT OR Apache-2.0\"\n"),
+            ("foo", "1.0.1", "license = \" MIT OR Apache-2.0 \"\n"),
+            ("bar", "0.1.0", "license = \"MIT\"\n"),
+            ("baz", "0.1.0", "license-file = \"LICENSE\"\n"),
+            ("qux", "0.2.0", ""),
+            ("qux", "0.1.0", "license = \"\"\n"),
+            ("quux", "0.1.0", "license = [\n"),
+        ] {
+            let path = vault.crate_version_path(&name.parse()?, &num.parse()?)?;
+            std::fs::create_dir_all(&path)?;
+            std::fs::write(
+                path.join("Cargo.toml"),
+                format!("[package]\nname = \"{name}\"\nversion = \"{num}\"\n{extra}"),
+            )?;
+        }
+
+        let report = report(&vault)?;
+        assert_that!(report.total(), eq(6));
+        assert_that!(
+            report.licenses,
+            eq(BTreeMap::from([
+                ("MIT".to_string(), 1),
+                ("MIT OR Apache-2.0".to_string(), 2)
+            ]))
+        );
+        assert_that!(report.license_file_only, eq(1));
+        assert_that!(
+            report.unlicensed,
+            elements_are![
+                eq(UnlicensedVersion {
+                    name: "qux".to_string(),
+                    num: "0.1.0".to_string()
+                }),
+                eq(UnlicensedVersion {
+                    name: "qux".to_string(),
+                    num: "0.2.0".to_string()
+                })
+            ]
+        );
+        assert_that!(
+            report.to_string(),
+            starts_with("       2  MIT OR Apache-2.0\n       1  MIT\n       1  (license file only)\n       2  (none)\n\nVersions with no license declared:\nqux 0.1.0\nqux 0.2.0\n\nManifests that couldn't be read:\n")
+        );
+        assert_that!(
+            report.unreadable,
+            elements_are![matches_pattern!(UnreadableManifest {
+                path: eq(vault
+                    .crate_version_path(&"quux".parse()?, &"0.1.0".parse()?)?
+                    .join("Cargo.toml")),
+                error: anything(),
+            })]
+        );
+
+        // Manifests over the size limit are reported the same way.
+        let report = super::report(&vault.with_max_manifest_size(16))?;
+        assert_that!(report.total(), eq(0));
+        assert_that!(report.unreadable, len(eq(7)));
+
+        Ok(())
+    }
+}
@@ -1,27 +1,175 @@
-use std::{collections::HashSet, path::PathBuf, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Write,
+    net::SocketAddr,
+    num::NonZeroUsize,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
 
 use clap::{Parser, Subcommand};
-use corpus::Corpus;
-use index::{krate::Krate, Index};
-use indicatif::{ParallelProgressIterator, ProgressStyle};
+use indicatif::{HumanBytes, ParallelProgressIterator, ProgressBar, ProgressStyle};
+use librarian::{
+    audit::{self, Baseline},
+    breaker::CircuitBreaker,
+    cache::TarballCache,
+    corpus::{self, Corpus, OnError},
+    doctor,
+    download::{self, Downloader},
+    error_sink::ErrorSink,
+    filter::{self, MsrvFilter, VersionFilter},
+    ignore::{self, IgnoreList},
+    index::{
+        self,
+        cargo_config::{CargoConfig, CRATES_IO_INDEX},
+        krate::Krate,
+        Index,
+    },
+    licenses,
+    order::DownloadOrder,
+    output::OutputFormat,
+    progress::{IndicatifProgress, Progress},
+    registry::LocalRegistry,
+    throttle::BandwidthLimiter,
+    timing::{ResultStream, TimingLog},
+    verified::VerifiedMarkers,
+};
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use semver::VersionReq;
 use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
-
-mod corpus;
-mod index;
+use vault::{CrateName, CrateVersion, CrateVersionNum, ManifestIssue, Vault, WalkedVersion};
 
 #[derive(Parser)]
 struct Opt {
     /// Path to the crates.io index repo.
+    ///
+    /// If this is a bare name that matches a registry or source in cargo's configuration (eg
+    /// `crates-io`, or a registry in `~/.cargo/config.toml`), the index is still kept in a
+    /// directory of that name, but `index-update` and `sync` fetch it from that registry's index
+    /// unless `--remote` is given.
     #[arg(short, long)]
     index: PathBuf,
 
+    /// If given, Prometheus metrics are served over HTTP on this address (eg `127.0.0.1:9100`)
+    /// while librarian runs.
+    ///
+    /// Requires librarian to be built with the `metrics` feature.
+    #[arg(long)]
+    metrics_listen: Option<SocketAddr>,
+
     #[command(subcommand)]
     command: Command,
 }
 
+// Only one of these is ever constructed, so there's no point boxing the larger variants.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Command {
+    /// Compare the crate versions in a corpus against a baseline of known-good `.crate`
+    /// checksums.
+    ///
+    /// Checksums are only available for corpora populated with the content addressed layout, or
+    /// for versions whose tarballs are in the given tarball cache.
+    Audit {
+        /// Path to the corpus to audit.
+        #[arg(short, long)]
+        corpus: PathBuf,
+
+        /// Path to the baseline file, with one `name version sha256` line per crate version.
+        #[arg(short, long)]
+        baseline: PathBuf,
+
+        /// If given, checksums are recomputed from tarballs cached in this directory.
+        #[arg(long)]
+        tarball_cache: Option<PathBuf>,
+    },
+    /// Download a crate version and write a single file from it to stdout, without populating a
+    /// corpus.
+    ///
+    /// The download is verified against the checksum in the index, if the version is there.
+    Cat {
+        /// The crate to download.
+        name: String,
+
+        /// The version of the crate to download.
+        version: String,
+
+        /// The path of the file to write, relative to the crate root, eg `src/lib.rs`.
+        path: PathBuf,
+
+        /// User-Agent to send when downloading the crate.
+        #[arg(long, default_value = download::DEFAULT_USER_AGENT)]
+        user_agent: String,
+
+        /// Contact details (usually an e-mail address) to send in the From header when
+        /// downloading the crate.
+        #[arg(long)]
+        contact: Option<String>,
+    },
+    /// Remove every crate version from a corpus.
+    Clear {
+        /// Path to the corpus to clear.
+        #[arg(short, long)]
+        corpus: PathBuf,
+
+        /// If set, don't ask for confirmation first.
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Repack each extracted crate version in a corpus into a `.crate` file, removing the
+    /// extracted files.
+    ///
+    /// The repacked files aren't byte for byte identical to the originals, so their checksums
+    /// won't match the index.
+    Compact {
+        /// Path to the corpus to compact.
+        #[arg(short, long)]
+        corpus: PathBuf,
+    },
+    /// Check that librarian is set up correctly, printing a checklist of what passed and what
+    /// didn't, with hints for fixing any problems.
+    ///
+    /// The index is checked but, unlike every other command, not created if it doesn't exist.
+    Doctor {
+        /// If given, also check that this corpus path is writable and has enough free space.
+        #[arg(short, long)]
+        corpus: Option<PathBuf>,
+
+        /// The free space, in bytes, below which the corpus's filesystem is considered too full.
+        #[arg(long, default_value_t = 1 << 30)]
+        min_free_bytes: u64,
+
+        /// User-Agent to send when checking that static.crates.io is reachable.
+        #[arg(long, default_value = download::DEFAULT_USER_AGENT)]
+        user_agent: String,
+    },
+    /// Check a corpus for problems.
+    Fsck {
+        /// Path to the corpus to check.
+        #[arg(short, long)]
+        corpus: PathBuf,
+
+        /// If set, crate directories whose names differ only by case will be merged into the
+        /// directory matching the crate's canonical name.
+        #[arg(long)]
+        merge_case_duplicates: bool,
+    },
+    /// Move each crate's file in the index into a single compressed pack file, to save space and
+    /// inodes, then print how many crates are in each.
+    ///
+    /// Packed crates are read transparently, but an index update restores their files, which then
+    /// take precedence, so this is best suited to indices that aren't updated.
+    IndexPack,
+    /// Extract a tarball snapshot of the index into the index directory, instead of fetching it
+    /// with git.
+    ///
+    /// The index directory must not exist yet, or be empty.
+    IndexSnapshot {
+        /// Path to the snapshot, which may be gzipped.
+        snapshot: PathBuf,
+    },
     /// Update the index repo.
     IndexUpdate {
         /// Index repo branch to check out.
@@ -29,21 +177,379 @@ enum Command {
         branch: String,
 
         /// Index repo remote.
-        #[arg(long, default_value = "https://github.com/rust-lang/crates.io-index")]
-        remote: String,
+        ///
+        /// May be given more than once, in which case each remote is tried in order until one
+        /// can be fetched. Cargo-style `registry+` URLs are accepted; `sparse+` URLs are
+        /// recognised, but skipped, since only git indices can be fetched.
+        ///
+        /// Defaults to the registry named by `--index`, if any, or else crates.io.
+        #[arg(long)]
+        remote: Vec<String>,
+
+        /// If set, the update fails if the fetched branch isn't a descendant of the current
+        /// index, rather than discarding the local history.
+        #[arg(long)]
+        ff_only: bool,
+    },
+    /// Report the licenses declared by every crate version in a corpus, with the number of
+    /// versions declaring each SPDX expression, and list the versions that declare no license.
+    Licenses {
+        /// Path to the corpus to report on.
+        #[arg(short, long)]
+        corpus: PathBuf,
+
+        /// If set, the report is written as a single JSON object.
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the crate versions in a corpus.
+    List {
+        /// Path to the corpus to list.
+        #[arg(short, long)]
+        corpus: PathBuf,
+
+        /// Output format.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Jsonl)]
+        format: OutputFormat,
+
+        /// Skip versions that can't be read, writing each error to this file, or `-` for stderr,
+        /// as JSON lines.
+        #[arg(long)]
+        errors_jsonl: Option<PathBuf>,
+
+        /// If set, versions whose manifests can't be read or parsed are reported on stderr (or to
+        /// `--errors-jsonl`, if given) and skipped, but any other error still fails the listing.
+        #[arg(long)]
+        manifest_only_errors: bool,
+    },
+    /// Write crates from the index into a directory usable as a cargo `local-registry` source
+    /// replacement.
+    ///
+    /// Unless `--crates` is provided, all crates in the index will be downloaded.
+    LocalRegistry {
+        /// Path to write the registry to.
+        #[arg(short, long)]
+        out: PathBuf,
+
+        /// If given, only these (comma separated) crates will be downloaded.
+        ///
+        /// Each crate may be given as `name@req` to only download versions matching the semver
+        /// requirement, eg `serde@^1.0`.
+        ///
+        /// Crate names may contain `*` and `?` wildcards, eg `aws-*` or `*-sys`.
+        #[arg(long)]
+        crates: Option<CrateSet>,
+
+        /// If given, only versions greater than or equal to this version will be downloaded.
+        #[arg(long)]
+        min_version: Option<semver::Version>,
+
+        /// If set, pre-release versions will not be downloaded.
+        #[arg(long)]
+        stable_only: bool,
+
+        /// User-Agent to send when downloading crates.
+        #[arg(long, default_value = download::DEFAULT_USER_AGENT)]
+        user_agent: String,
+
+        /// Contact details (usually an e-mail address) to send in the From header when
+        /// downloading crates.
+        #[arg(long)]
+        contact: Option<String>,
+
+        /// If given, downloaded tarballs will be cached in this directory and reused.
+        #[arg(long)]
+        tarball_cache: Option<PathBuf>,
+    },
+    /// List versions that are in the index, but not in a corpus.
+    ///
+    /// Versions matching a `.librarianignore` file in the corpus root are not listed.
+    Missing {
+        /// Path to the corpus to check.
+        #[arg(short, long)]
+        corpus: PathBuf,
+
+        /// If given, only these (comma separated) crates will be checked.
+        ///
+        /// Each crate may be given as `name@req` to only check versions matching the semver
+        /// requirement, eg `serde@^1.0`.
+        ///
+        /// Crate names may contain `*` and `?` wildcards, eg `aws-*` or `*-sys`.
+        #[arg(long)]
+        crates: Option<CrateSet>,
     },
     /// Populate crates from the index by downloading them from static.crates.io and extracting
     /// them locally.
     ///
-    /// Unless `--crates` is provided, all crates in the index will be downloaded.
+    /// Unless `--crates` is provided, all crates in the index will be downloaded. Versions
+    /// matching a `.librarianignore` file in the corpus root are never downloaded.
     Populate {
         /// Path to place the extracted crates in.
         #[arg(short, long)]
         corpus: PathBuf,
 
         /// If given, only these (comma separated) crates will be downloaded.
+        ///
+        /// Each crate may be given as `name@req` to only download versions matching the semver
+        /// requirement, eg `serde@^1.0`.
+        ///
+        /// Crate names may contain `*` and `?` wildcards, eg `aws-*` or `*-sys`.
         #[arg(long)]
         crates: Option<CrateSet>,
+
+        /// If given, these (comma separated) crates will not be downloaded.
+        ///
+        /// If `--crates` is also given, the crates it lists are downloaded, minus any listed here.
+        /// Version requirements aren't supported: each crate is excluded entirely. As with
+        /// `--crates`, names may contain wildcards.
+        #[arg(long)]
+        exclude: Option<CrateSet>,
+
+        /// If given, only versions greater than or equal to this version will be downloaded.
+        #[arg(long)]
+        min_version: Option<semver::Version>,
+
+        /// If set, pre-release versions will not be downloaded.
+        #[arg(long)]
+        stable_only: bool,
+
+        /// User-Agent to send when downloading crates.
+        #[arg(long, default_value = download::DEFAULT_USER_AGENT)]
+        user_agent: String,
+
+        /// Contact details (usually an e-mail address) to send in the From header when
+        /// downloading crates.
+        #[arg(long)]
+        contact: Option<String>,
+
+        /// If given, downloaded tarballs will be cached in this directory and reused when the
+        /// same crate version is populated again.
+        #[arg(long)]
+        tarball_cache: Option<PathBuf>,
+
+        /// If given, the least recently used tarballs will be evicted from the tarball cache
+        /// after populating until it is no larger than this many bytes.
+        #[arg(long, requires = "tarball_cache")]
+        tarball_cache_max_bytes: Option<u64>,
+
+        /// The order in which crate versions are downloaded.
+        ///
+        /// If not given, the order is unspecified.
+        #[arg(long, value_enum)]
+        order: Option<DownloadOrder>,
+
+        /// If given, only versions declaring a `rust-version` of at least this will be
+        /// downloaded.
+        #[arg(long, value_parser = parse_rust_version)]
+        min_msrv: Option<semver::Version>,
+
+        /// If given, only versions declaring a `rust-version` of at most this will be downloaded.
+        #[arg(long, value_parser = parse_rust_version)]
+        max_msrv: Option<semver::Version>,
+
+        /// If set, versions that don't declare a `rust-version` will not be downloaded.
+        ///
+        /// Otherwise, they are downloaded regardless of `--min-msrv` and `--max-msrv`.
+        #[arg(long)]
+        require_msrv: bool,
+
+        /// If set, crates will be downloaded with an async client, which allows many more
+        /// concurrent downloads. Requires librarian to be built with the `async` feature.
+        ///
+        /// The tarball cache is not used in this mode.
+        #[arg(long = "async", conflicts_with = "tarball_cache")]
+        use_async: bool,
+
+        /// The maximum number of concurrent downloads when `--async` is set.
+        #[arg(long, default_value_t = 64, requires = "use_async")]
+        concurrency: usize,
+
+        /// If set, the manifest of each newly populated version is checked against the crate
+        /// name and version it was requested as, and the version is removed if they differ.
+        #[arg(long)]
+        verify_manifests: bool,
+
+        /// Also write each version that fails to populate to this file, or `-` for stderr, as
+        /// JSON lines.
+        #[arg(long)]
+        errors_jsonl: Option<PathBuf>,
+
+        /// If given, the size, download time, extraction time, and result of each newly populated
+        /// version are written to this file (or stderr, if `-`) as lines of JSON.
+        #[arg(long, conflicts_with = "use_async")]
+        timing_log: Option<PathBuf>,
+
+        /// If set, the result of each version is written to stdout as a line of JSON as soon as
+        /// it completes, with `crate`, `version`, `status`, `bytes`, and `duration_ms` fields,
+        /// plus `error` if it failed.
+        #[arg(long, conflicts_with = "use_async")]
+        stream_results: bool,
+
+        /// If given, only the newest (by semver) of each crate's versions that pass the other
+        /// filters will be downloaded, up to this many. Versions that aren't valid semver are
+        /// excluded.
+        #[arg(long)]
+        max_versions_per_crate: Option<usize>,
+
+        /// If given, downloads are paused when at least this fraction (greater than 0, and at most
+        /// 1) of the most recent downloads failed, resuming once a probe download succeeds.
+        #[arg(long, conflicts_with = "use_async", value_parser = parse_failure_fraction)]
+        circuit_breaker_threshold: Option<f64>,
+
+        /// The number of recent downloads considered by `--circuit-breaker-threshold`.
+        #[arg(long, default_value_t = 50, requires = "circuit_breaker_threshold")]
+        circuit_breaker_window: usize,
+
+        /// How long to pause for, in seconds, when `--circuit-breaker-threshold` is reached. This
+        /// doubles each time a probe download fails, up to ten times the initial value.
+        #[arg(long, default_value_t = 30, requires = "circuit_breaker_threshold")]
+        circuit_breaker_backoff: u64,
+
+        /// If given, the combined throughput of all downloads is capped at this many bytes per
+        /// second.
+        #[arg(long, conflicts_with = "use_async", value_parser = clap::value_parser!(u64).range(1..))]
+        max_bandwidth: Option<u64>,
+
+        /// Base URL to download `.crate` files from, laid out like static.crates.io.
+        ///
+        /// May be given more than once, in which case each mirror is tried in order until one
+        /// serves the crate. Defaults to static.crates.io.
+        #[arg(long = "mirror", conflicts_with = "use_async")]
+        mirrors: Vec<String>,
+
+        /// If set, each newly populated version is also committed into a git repository in its
+        /// crate's directory, with one commit per version in semver order and each commit tagged
+        /// with its version, so versions can be compared with `git diff`.
+        #[arg(long, conflicts_with = "use_async")]
+        history: bool,
+
+        /// Carry on populating the remaining versions after a failure, reporting every failure
+        /// at the end. This is the default.
+        #[arg(long, conflicts_with = "fail_fast")]
+        keep_going: bool,
+
+        /// Stop populating after the first failure.
+        #[arg(long)]
+        fail_fast: bool,
+    },
+    /// Move every version of a crate in a corpus to a new crate name, for crates that have been
+    /// renamed upstream.
+    RenameCrate {
+        /// Path to the corpus containing the crate.
+        #[arg(short, long)]
+        corpus: PathBuf,
+
+        /// The crate's current name.
+        old_name: String,
+
+        /// The name to move the crate's versions to.
+        new_name: String,
+    },
+    /// Update the index repo, then populate a corpus with the crate versions added or changed
+    /// since the corpus was last synced.
+    ///
+    /// The index commit is recorded in the corpus once every version has been populated, so a
+    /// sync that fails partway is retried from the same point next time. If the corpus has never
+    /// been synced, or the commit it was synced to has since been squashed out of the index,
+    /// every version in the index is populated.
+    Sync {
+        /// Path to the corpus to sync.
+        #[arg(short, long)]
+        corpus: PathBuf,
+
+        /// Index repo branch to check out.
+        #[arg(long, default_value = "master")]
+        branch: String,
+
+        /// Index repo remote.
+        ///
+        /// May be given more than once, in which case each remote is tried in order until one
+        /// can be fetched.
+        ///
+        /// Defaults to the registry named by `--index`, if any, or else crates.io.
+        #[arg(long)]
+        remote: Vec<String>,
+
+        /// User-Agent to send when downloading crates.
+        #[arg(long, default_value = download::DEFAULT_USER_AGENT)]
+        user_agent: String,
+
+        /// Contact details (usually an e-mail address) to send in the From header when
+        /// downloading crates.
+        #[arg(long)]
+        contact: Option<String>,
+
+        /// Also write each version that fails to sync to this file, or `-` for stderr, as JSON
+        /// lines.
+        #[arg(long)]
+        errors_jsonl: Option<PathBuf>,
+    },
+    /// Download crates from static.crates.io and check them against the index checksums, without
+    /// keeping them.
+    ///
+    /// Unless `--crates` is provided, all crates in the index will be verified.
+    Verify {
+        /// If given, only these (comma separated) crates will be verified.
+        ///
+        /// Each crate may be given as `name@req` to only verify versions matching the semver
+        /// requirement, eg `serde@^1.0`.
+        ///
+        /// Crate names may contain `*` and `?` wildcards, eg `aws-*` or `*-sys`.
+        #[arg(long)]
+        crates: Option<CrateSet>,
+
+        /// If given, only versions greater than or equal to this version will be verified.
+        #[arg(long)]
+        min_version: Option<semver::Version>,
+
+        /// If set, pre-release versions will not be verified.
+        #[arg(long)]
+        stable_only: bool,
+
+        /// User-Agent to send when downloading crates.
+        #[arg(long, default_value = download::DEFAULT_USER_AGENT)]
+        user_agent: String,
+
+        /// Contact details (usually an e-mail address) to send in the From header when
+        /// downloading crates.
+        #[arg(long)]
+        contact: Option<String>,
+
+        /// If given, versions matching the patterns in this file (in `.librarianignore` format)
+        /// will not be verified.
+        #[arg(long)]
+        ignore_file: Option<PathBuf>,
+
+        /// If given, the manifest of each version already populated in this corpus is also
+        /// checked against the crate name and version it is stored under.
+        ///
+        /// Each version that passes verification is also recorded in the corpus, for use with
+        /// `--max-age`.
+        #[arg(short, long)]
+        corpus: Option<PathBuf>,
+
+        /// If given, versions that passed verification within this many seconds are skipped,
+        /// unless they have been repopulated since.
+        #[arg(long, requires = "corpus")]
+        max_age: Option<u64>,
+
+        /// Verify every version, even if it passed verification within `--max-age`.
+        #[arg(long)]
+        force: bool,
+
+        /// The number of versions to verify at once. Defaults to the number of CPUs.
+        #[arg(short, long)]
+        jobs: Option<NonZeroUsize>,
+
+        /// Also write each version that fails verification to this file, or `-` for stderr, as
+        /// JSON lines.
+        #[arg(long)]
+        errors_jsonl: Option<PathBuf>,
+    },
+    /// Print the versions of a crate in the index, newest first.
+    Versions {
+        /// The crate to list the versions of.
+        name: String,
     },
 }
 
@@ -54,64 +560,1211 @@ fn main() -> anyhow::Result<()> {
         .init();
 
     let opt = Opt::parse();
+
+    // Opening the index initialises it if necessary, which would hide the problem doctor is
+    // looking for.
+    if let Command::Doctor {
+        corpus,
+        min_free_bytes,
+        user_agent,
+    } = &opt.command
+    {
+        let checks = doctor::diagnose(&opt.index, corpus.as_deref(), user_agent, *min_free_bytes);
+        for check in &checks {
+            println!("{check}");
+        }
+
+        let failed = checks.iter().filter(|check| !check.passed()).count();
+        if failed > 0 {
+            anyhow::bail!("{failed} check(s) failed");
+        }
+        return Ok(());
+    }
+
+    if let Some(addr) = opt.metrics_listen {
+        install_metrics_exporter(addr)?;
+    }
+    // Opening the index would initialise a repository in the directory the snapshot has to be
+    // extracted into.
+    if let Command::IndexSnapshot { snapshot } = &opt.command {
+        let index = Index::from_snapshot(&opt.index, snapshot)?;
+        println!(
+            "Extracted {} crate(s) from {snapshot:?}",
+            index.crate_names()?.len()
+        );
+        return Ok(());
+    }
     let mut index = Index::new(&opt.index)?;
 
     match opt.command {
-        Command::IndexUpdate { branch, remote } => index.update(&remote, &branch)?,
-        Command::Populate { corpus, crates } => {
-            let corpus = Corpus::new(corpus)?;
+        Command::Audit {
+            corpus,
+            baseline,
+            tarball_cache,
+        } => {
+            let vault = Vault::open(corpus)?;
+            let baseline = Baseline::load(&baseline)?;
+            let cache = tarball_cache.map(TarballCache::new).transpose()?;
 
-            let crates: Vec<Krate> = match crates {
-                Some(crates) => crates
-                    .0
-                    .into_par_iter()
-                    .map(|name| index.get(&name))
-                    .collect::<Result<_, _>>()?,
-                None => index.all().collect::<Result<_, index::Error>>()?,
+            let findings = audit::audit(&vault, &baseline, cache.as_ref())?;
+            for finding in &findings {
+                println!("{finding}");
+            }
+            if !findings.is_empty() {
+                anyhow::bail!("{} audit finding(s)", findings.len());
+            }
+        }
+        Command::Cat {
+            name,
+            version,
+            path,
+            user_agent,
+            contact,
+        } => {
+            // Versions that aren't in the index can still be downloaded, but not verified.
+            let cksum = match index.get(&name.parse()?) {
+                Ok(krate) => krate
+                    .iter_versions()
+                    .find(|(num, _version)| **num == version)
+                    .map(|(_num, version)| version.cksum().to_string()),
+                Err(index::Error::NotFound(_)) => None,
+                Err(e) => return Err(e.into()),
             };
+            if cksum.is_none() {
+                tracing::warn!(?name, ?version, "not in the index; downloading unverified");
+            }
+
+            let downloader = Downloader::new()?.with_identity(&user_agent, contact.as_deref())?;
+            let data = downloader.download(&name, &version, cksum.as_deref())?;
+            if !Corpus::extract_file(&data, &path, std::io::stdout().lock())? {
+                anyhow::bail!("{name} {version} does not contain a file at {path:?}");
+            }
+        }
+        Command::Clear { corpus, yes } => {
+            if !corpus.is_dir() {
+                anyhow::bail!("corpus does not exist: {corpus:?}");
+            }
+
+            if !yes {
+                eprint!("Remove every crate version from {corpus:?}? [y/N] ");
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                    anyhow::bail!("not clearing corpus");
+                }
+            }
 
-            let versions = crates
+            let corpus = Corpus::new(corpus)?;
+            let _lock = corpus.lock()?;
+            corpus.clear()?;
+        }
+        Command::Compact { corpus } => {
+            let corpus = Corpus::new(corpus)?;
+            let _lock = corpus.lock()?;
+            let versions = corpus
+                .vault()
+                .iter_crate_versions()
+                .collect::<Result<Vec<_>, vault::Error>>()?;
+
+            let failures: Vec<_> = versions
                 .into_par_iter()
                 .progress_with_style(ProgressStyle::with_template(
-                    "Hydrating crate versions {wide_bar} {pos}/{len} ETA: {eta}",
+                    "Compacting crates {wide_bar} {pos}/{len} ETA: {eta}",
                 )?)
-                .map(|krate| {
-                    krate
-                        .iter_versions()
-                        .map(|(num, version)| (version.name().to_string(), num.clone()))
-                        .collect::<Vec<(String, String)>>()
+                .filter_map(|version| {
+                    let e = corpus
+                        .compact(&version.crate_name, &version.version)
+                        .err()?;
+                    Some((version.crate_name, version.version, e))
                 })
-                .flatten()
-                .collect::<Vec<_>>();
+                .collect();
+            for (name, num, e) in &failures {
+                tracing::error!(?name, ?num, ?e, "error compacting version");
+                eprintln!("{name} {num}: {e}");
+            }
+            if !failures.is_empty() {
+                anyhow::bail!("{} crate version(s) failed to compact", failures.len());
+            }
+        }
+        Command::Doctor { .. } => unreachable!("doctor is run before the index is opened"),
+        Command::Fsck {
+            corpus,
+            merge_case_duplicates,
+        } => {
+            let ignore = IgnoreList::for_corpus(&corpus)?;
+            let vault = Vault::open(corpus)?;
+            // Merging moves crate directories, so it mustn't race with a populate.
+            let _lock = if merge_case_duplicates {
+                Some(Corpus::new(vault.to_path_buf())?.lock()?)
+            } else {
+                None
+            };
+
+            for duplicate in vault.bucket_duplicates()? {
+                println!(
+                    "{}: crate directories found in more than one bucket (expected {:?}): {:?}",
+                    duplicate.name, duplicate.expected, duplicate.paths
+                );
+            }
 
-            versions
+            for duplicate in vault.case_duplicates()? {
+                println!(
+                    "{}: crate directories differ only by case: {:?}",
+                    duplicate.name, duplicate.paths
+                );
+
+                if merge_case_duplicates {
+                    let target = vault.merge_case_duplicates(&duplicate)?;
+                    println!("{}: merged into {target:?}", duplicate.name);
+                }
+            }
+
+            // Manifest parse failures are summarised by category, since a large corpus can have
+            // thousands of them.
+            let mut issues: BTreeMap<ManifestIssue, usize> = BTreeMap::new();
+            for result in vault.iter_crate_versions() {
+                match result {
+                    Ok(version) if ignore.is_ignored(&version.crate_name, &version.version) => {
+                        println!(
+                            "{} {}: version is in the corpus, but matches {}: {:?}",
+                            version.crate_name,
+                            version.version,
+                            ignore::IGNORE_FILE,
+                            version.path
+                        );
+                    }
+                    Ok(_version) => {}
+                    Err(e) => match e.manifest_issue() {
+                        Some(issue) => {
+                            tracing::debug!(?e, "manifest parse failure");
+                            *issues.entry(issue).or_default() += 1;
+                        }
+                        None => println!("{e}"),
+                    },
+                }
+            }
+            for (issue, count) in issues {
+                println!("{count} manifest(s) could not be parsed: {issue}");
+            }
+        }
+        Command::IndexPack => {
+            let packed = index.pack_all(&IndicatifProgress::new())?;
+            let stats = index.cache_stats()?;
+            println!("Packed {packed} crate(s)");
+            println!(
+                "{} loose crate(s) ({}), {} packed crate(s) ({})",
+                stats.loose,
+                HumanBytes(stats.loose_bytes),
+                stats.packed,
+                HumanBytes(stats.pack_bytes)
+            );
+        }
+        Command::IndexSnapshot { .. } => {
+            unreachable!("snapshots are extracted before the index is opened")
+        }
+        Command::IndexUpdate {
+            branch,
+            remote,
+            ff_only,
+        } => {
+            let remote = index_remotes(&opt.index, remote)?;
+            let remotes: Vec<&str> = remote.iter().map(String::as_str).collect();
+            let remote = if ff_only {
+                index.update_ff_only(&remotes, &branch, &IndicatifProgress::new())?
+            } else {
+                index.update(&remotes, &branch, &IndicatifProgress::new())?
+            };
+            println!("Updated index from {remote}");
+        }
+        Command::Licenses { corpus, json } => {
+            let vault = Vault::open(corpus)?;
+            let report = licenses::report(&vault)?;
+            if json {
+                serde_json::to_writer(std::io::stdout().lock(), &report)?;
+                println!();
+            } else {
+                print!("{report}");
+            }
+        }
+        Command::List {
+            corpus,
+            format,
+            errors_jsonl,
+            manifest_only_errors,
+        } => {
+            let ignore = IgnoreList::for_corpus(&corpus)?;
+            let errors = error_sink(errors_jsonl.as_deref())?;
+            let vault = Vault::open(corpus)?;
+            let walk: Box<dyn Iterator<Item = Result<CrateVersion, vault::Error>>> =
+                if manifest_only_errors {
+                    Box::new(vault.iter_crate_versions_lenient().filter_map(
+                        |result| match result {
+                            Ok(WalkedVersion::Version(version)) => Some(Ok(version)),
+                            Ok(WalkedVersion::ManifestError { error, .. }) => {
+                                if errors.is_enabled() {
+                                    errors.record(None, None, "list", &error);
+                                } else {
+                                    eprintln!("{error}");
+                                }
+                                None
+                            }
+                            Err(e) => Some(Err(e)),
+                        },
+                    ))
+                } else {
+                    Box::new(vault.iter_crate_versions())
+                };
+            let versions = walk
+                .filter(|result| {
+                    !matches!(result, Ok(version) if ignore.is_ignored(&version.crate_name, &version.version))
+                })
+                .filter(|result| match result {
+                    Err(e) if errors.is_enabled() => {
+                        errors.record(None, None, "list", e);
+                        false
+                    }
+                    _ => true,
+                })
+                .collect::<Result<Vec<_>, vault::Error>>()?;
+
+            format.write_versions(std::io::stdout().lock(), versions)?;
+        }
+        Command::LocalRegistry {
+            out,
+            crates,
+            min_version,
+            stable_only,
+            user_agent,
+            contact,
+            tarball_cache,
+        } => {
+            let mut downloader =
+                Downloader::new()?.with_identity(&user_agent, contact.as_deref())?;
+            if let Some(path) = tarball_cache {
+                downloader = downloader.with_tarball_cache(TarballCache::new(path)?);
+            }
+            let registry = LocalRegistry::new(out, downloader)?;
+            let crates = crates.map(|crates| crates.expand(&index)).transpose()?;
+            let filter = version_filter(crates.as_ref(), min_version, stable_only);
+            let crates = select_crates(&index, crates, None, OnInterrupt::Exit)?;
+
+            let failures: Vec<_> = crates
                 .into_par_iter()
                 .progress_with_style(ProgressStyle::with_template(
                     "Downloading crates {wide_bar} {pos}/{len} ETA: {eta}",
                 )?)
-                .try_for_each(|(name, num)| match corpus.populate(&name, &num) {
-                    Ok(_path) => Ok(()),
+                .filter_map(|krate| {
+                    let versions = krate
+                        .iter_versions()
+                        .filter(|(num, version)| filter.matches(version.name(), num))
+                        .map(|(_num, version)| version);
+
+                    let e = registry.add(&index, krate.name(), versions).err()?;
+                    Some((krate.name().to_string(), e))
+                })
+                .collect();
+
+            for (name, e) in &failures {
+                tracing::error!(?name, ?e, "error adding crate to registry");
+                eprintln!("{name}: {e}");
+            }
+            if !failures.is_empty() {
+                anyhow::bail!(
+                    "{} crate(s) failed to be added to the registry",
+                    failures.len()
+                );
+            }
+        }
+        Command::Missing { corpus, crates } => {
+            let ignore = IgnoreList::for_corpus(&corpus)?;
+            let vault = Vault::new(corpus);
+            let crates = crates.map(|crates| crates.expand(&index)).transpose()?;
+            let filter = version_filter(crates.as_ref(), None, false);
+            let names: Option<HashSet<String>> = crates
+                .as_ref()
+                .map(|crates| crates.0.keys().cloned().collect());
+            let crates = select_crates(&index, crates, None, OnInterrupt::KeepPartial)?;
+
+            let local: Box<dyn Iterator<Item = Result<CrateVersion, vault::Error>>> = match &names {
+                Some(names) => Box::new(vault.iter_versions_of(names)),
+                None => Box::new(vault.iter_crate_versions()),
+            };
+            let mut present = HashSet::new();
+            for result in local {
+                match result {
+                    Ok(version) => {
+                        present.insert((version.crate_name.to_ascii_lowercase(), version.version));
+                    }
                     Err(e) => {
-                        tracing::error!(?name, ?num, ?e, "error populating version");
-                        std::fs::remove_dir_all(corpus.path(&name, &num)?)
-                            .map_err(corpus::Error::from)
+                        tracing::warn!(
+                            ?e,
+                            "error reading corpus; version will be listed as missing"
+                        );
+                    }
+                }
+            }
+
+            let mut stdout = std::io::stdout().lock();
+            for krate in &crates {
+                for (num, version) in krate.iter_versions() {
+                    let name = version.name();
+                    if filter.matches(name, num)
+                        && !ignore.is_ignored(name, num)
+                        && !present.contains(&(name.to_ascii_lowercase(), num.clone()))
+                    {
+                        writeln!(stdout, "{name} {num}")?;
                     }
-                })?;
+                }
+            }
+        }
+        Command::Populate {
+            corpus,
+            crates,
+            exclude,
+            min_version,
+            stable_only,
+            user_agent,
+            contact,
+            tarball_cache,
+            tarball_cache_max_bytes,
+            order,
+            min_msrv,
+            max_msrv,
+            require_msrv,
+            use_async,
+            concurrency,
+            verify_manifests,
+            errors_jsonl,
+            timing_log,
+            stream_results,
+            max_versions_per_crate,
+            circuit_breaker_threshold,
+            circuit_breaker_window,
+            circuit_breaker_backoff,
+            max_bandwidth,
+            mirrors,
+            history,
+            keep_going: _,
+            fail_fast,
+        } => {
+            let errors = error_sink(errors_jsonl.as_deref())?;
+            let ignore = IgnoreList::for_corpus(&corpus)?;
+            let mut corpus = Corpus::new(corpus)?.with_identity(&user_agent, contact.as_deref())?;
+            let _lock = corpus.lock()?;
+            if let Some(path) = tarball_cache {
+                corpus = corpus.with_tarball_cache(TarballCache::new(path)?);
+            }
+            if let Some(path) = timing_log {
+                corpus = corpus.with_timing_log(TimingLog::open(&path)?);
+            }
+            if stream_results {
+                corpus = corpus.with_result_stream(ResultStream::new(std::io::stdout()));
+            }
+            if let Some(threshold) = circuit_breaker_threshold {
+                if circuit_breaker_window == 0 {
+                    anyhow::bail!("--circuit-breaker-window must be non-zero");
+                }
+                let backoff = Duration::from_secs(circuit_breaker_backoff);
+                corpus = corpus.with_circuit_breaker(
+                    CircuitBreaker::new(circuit_breaker_window, threshold)
+                        .with_backoff(backoff, backoff * 10),
+                );
+            }
+            if let Some(bytes_per_sec) = max_bandwidth {
+                corpus = corpus.with_bandwidth_limit(BandwidthLimiter::new(bytes_per_sec));
+            }
+            if !mirrors.is_empty() {
+                corpus = corpus.with_mirrors(mirrors);
+            }
+            if history {
+                corpus = corpus.with_history();
+            }
+            let crates = crates.map(|crates| crates.expand(&index)).transpose()?;
+            let filter = version_filter(crates.as_ref(), min_version, stable_only);
+            let msrv_filter = MsrvFilter {
+                min: min_msrv,
+                max: max_msrv,
+                require: require_msrv,
+            };
+            if exclude
+                .as_ref()
+                .is_some_and(|exclude| !exclude.requirements().is_empty())
+            {
+                anyhow::bail!("--exclude does not support version requirements");
+            }
+            let crates = select_crates(&index, crates, exclude.as_ref(), OnInterrupt::Exit)?;
+
+            let mut versions = crates
+                .into_par_iter()
+                .progress_with_style(ProgressStyle::with_template(
+                    "Hydrating crate versions {wide_bar} {pos}/{len} ETA: {eta}",
+                )?)
+                .map(|krate| {
+                    let mut selected: Vec<_> = match max_versions_per_crate {
+                        Some(_) => krate.iter_versions_sorted().collect(),
+                        None => krate.iter_versions().collect(),
+                    };
+                    selected.retain(|(num, version)| {
+                        filter.matches(version.name(), num)
+                            && msrv_filter.matches(version.rust_version())
+                            && !ignore.is_ignored(version.name(), num)
+                    });
+                    if let Some(max) = max_versions_per_crate {
+                        selected.retain(|(num, version)| {
+                            let valid = semver::Version::parse(num).is_ok();
+                            if !valid {
+                                tracing::warn!(
+                                    name = version.name(),
+                                    ?num,
+                                    "excluding version that isn't valid semver"
+                                );
+                            }
+                            valid
+                        });
+                        let excess = selected.len().saturating_sub(max);
+                        selected.drain(..excess);
+                    }
+
+                    selected
+                        .into_iter()
+                        .map(|(num, version)| {
+                            (
+                                version.name().to_string(),
+                                num.clone(),
+                                version.cksum().to_string(),
+                                version.crate_size(),
+                            )
+                        })
+                        .collect::<Vec<(String, String, String, Option<u64>)>>()
+                })
+                .flatten()
+                .collect::<Vec<_>>();
+            if let Some(order) = order {
+                order.apply(&mut versions, |(name, num, _cksum, size)| {
+                    (name, num, *size)
+                });
+            }
+
+            let on_error = if fail_fast {
+                OnError::FailFast
+            } else {
+                OnError::KeepGoing
+            };
+            if verify_manifests {
+                corpus = corpus.with_manifest_verification();
+            }
+            let progress = IndicatifProgress::new();
+            let failures = if use_async {
+                populate_async(
+                    corpus,
+                    &user_agent,
+                    contact.as_deref(),
+                    versions,
+                    concurrency,
+                    on_error,
+                    &progress,
+                )?
+            } else {
+                let failures = corpus.populate_many(versions, on_error, &progress);
+
+                if let (Some(cache), Some(max_bytes)) =
+                    (corpus.tarball_cache(), tarball_cache_max_bytes)
+                {
+                    cache.evict(max_bytes)?;
+                }
+                failures
+            };
+
+            for (name, num, e) in &failures {
+                tracing::error!(?name, ?num, ?e, "error populating version");
+                eprintln!("{name} {num}: {e}");
+                errors.record(Some(name), Some(num), "populate", e);
+            }
+            if !failures.is_empty() {
+                anyhow::bail!("{} crate version(s) failed to populate", failures.len());
+            }
+        }
+        Command::RenameCrate {
+            corpus,
+            old_name,
+            new_name,
+        } => {
+            let corpus = Corpus::new(corpus)?;
+            let _lock = corpus.lock()?;
+            corpus.vault().rename_crate(&old_name, &new_name)?;
+        }
+        Command::Sync {
+            corpus,
+            branch,
+            remote,
+            user_agent,
+            contact,
+            errors_jsonl,
+        } => {
+            let errors = error_sink(errors_jsonl.as_deref())?;
+            let ignore = IgnoreList::for_corpus(&corpus)?;
+            let corpus = Corpus::new(corpus)?.with_identity(&user_agent, contact.as_deref())?;
+            let _lock = corpus.lock()?;
+
+            let remote = index_remotes(&opt.index, remote)?;
+            let remotes: Vec<&str> = remote.iter().map(String::as_str).collect();
+            let remote = index.update(&remotes, &branch, &IndicatifProgress::new())?;
+            println!("Updated index from {remote}");
+            let head = index
+                .head()?
+                .ok_or_else(|| anyhow::anyhow!("index has no commits after updating"))?;
+
+            // If the last synced commit has been squashed away, then the whole index has to be
+            // checked, just as for a first sync.
+            let changed = match corpus.last_synced()? {
+                Some(since) if since == head => Some(Vec::new()),
+                Some(since) => index.changed_since(&since)?,
+                None => None,
+            };
+            let versions: Vec<(String, String, String, Option<u64>)> = match changed {
+                Some(changed) => changed
+                    .into_iter()
+                    .map(|version| {
+                        (
+                            version.name().to_string(),
+                            version.num().to_string(),
+                            version.cksum().to_string(),
+                            version.crate_size(),
+                        )
+                    })
+                    .collect(),
+                None => select_crates(&index, None, None, OnInterrupt::Exit)?
+                    .iter()
+                    .flat_map(|krate| krate.iter_versions())
+                    .map(|(num, version)| {
+                        (
+                            version.name().to_string(),
+                            num.clone(),
+                            version.cksum().to_string(),
+                            version.crate_size(),
+                        )
+                    })
+                    .collect(),
+            };
+            let versions: Vec<_> = versions
+                .into_iter()
+                .filter(|(name, num, _cksum, _size)| !ignore.is_ignored(name, num))
+                .collect();
+            println!("{} crate version(s) to populate", versions.len());
+
+            let failures =
+                corpus.populate_many(versions, OnError::KeepGoing, &IndicatifProgress::new());
+            for (name, num, e) in &failures {
+                tracing::error!(?name, ?num, ?e, "error populating version");
+                eprintln!("{name} {num}: {e}");
+                errors.record(Some(name), Some(num), "populate", e);
+            }
+            if !failures.is_empty() {
+                anyhow::bail!("{} crate version(s) failed to populate", failures.len());
+            }
+            corpus.record_synced(&head)?;
+        }
+        Command::Verify {
+            crates,
+            min_version,
+            stable_only,
+            user_agent,
+            contact,
+            ignore_file,
+            corpus,
+            max_age,
+            force,
+            jobs,
+            errors_jsonl,
+        } => {
+            let errors = error_sink(errors_jsonl.as_deref())?;
+            let markers = corpus.as_deref().map(VerifiedMarkers::new);
+            let max_age = max_age.filter(|_| !force).map(Duration::from_secs);
+            let vault = corpus.map(Vault::open).transpose()?;
+            let ignore = match ignore_file {
+                Some(path) => IgnoreList::load(&path)?,
+                None => IgnoreList::default(),
+            };
+            let downloader = Downloader::new()?.with_identity(&user_agent, contact.as_deref())?;
+            let crates = crates.map(|crates| crates.expand(&index)).transpose()?;
+            let filter = version_filter(crates.as_ref(), min_version, stable_only);
+            let crates = select_crates(&index, crates, None, OnInterrupt::KeepPartial)?;
+
+            let versions: Vec<(String, String, String)> = crates
+                .iter()
+                .flat_map(|krate| krate.iter_versions())
+                .filter(|(num, version)| {
+                    filter.matches(version.name(), num) && !ignore.is_ignored(version.name(), num)
+                })
+                .map(|(num, version)| {
+                    (
+                        version.name().to_string(),
+                        num.clone(),
+                        version.cksum().to_string(),
+                    )
+                })
+                .collect();
+
+            let versions = match (&markers, max_age) {
+                (Some(markers), Some(max_age)) => {
+                    let total = versions.len();
+                    let mut stale = Vec::new();
+                    for (name, num, cksum) in versions {
+                        let manifest = vault
+                            .as_ref()
+                            .and_then(|vault| vault.version_manifest_path(&name, &num).ok());
+                        if !markers.is_fresh(&name, &num, &cksum, max_age, manifest.as_deref())? {
+                            stale.push((name, num, cksum));
+                        }
+                    }
+                    println!(
+                        "skipping {} recently verified crate version(s)",
+                        total - stale.len()
+                    );
+                    stale
+                }
+                _ => versions,
+            };
+
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(jobs.map_or(0, NonZeroUsize::get))
+                .build()?;
+            let bar =
+                ProgressBar::new(versions.len() as u64).with_style(ProgressStyle::with_template(
+                    "Verifying crates {wide_bar} {pos}/{len} {msg} ETA: {eta}",
+                )?);
+            let started = Instant::now();
+            let bytes = AtomicU64::new(0);
+            let failures = pool.install(|| {
+                versions
+                    .into_par_iter()
+                    .progress_with(bar.clone())
+                    .filter(|(name, num, cksum)| {
+                        let mut failed = false;
+                        match downloader.verify(name, num, cksum) {
+                            Ok(downloaded) => {
+                                let total =
+                                    bytes.fetch_add(downloaded, Ordering::Relaxed) + downloaded;
+                                let rate = total as f64 / started.elapsed().as_secs_f64();
+                                bar.set_message(format!("{}/s", HumanBytes(rate as u64)));
+                            }
+                            Err(e) => {
+                                println!("{name} {num}: {e}");
+                                errors.record(Some(name), Some(num), "verify", &e);
+                                failed = true;
+                            }
+                        }
+
+                        let vault = vault.as_ref().filter(|vault| {
+                            let path = CrateName::new(name).and_then(|name| {
+                                vault.canonical_version_path(&name, &CrateVersionNum::new(num)?)
+                            });
+                            matches!(path, Ok(path) if path.is_dir())
+                        });
+                        if let Some(vault) = vault {
+                            if let Err(e) = vault.verify_manifest_matches(name, num) {
+                                println!("{name} {num}: {e}");
+                                errors.record(Some(name), Some(num), "verify-manifest", &e);
+                                failed = true;
+                            }
+                        }
+
+                        if !failed {
+                            if let Some(markers) = &markers {
+                                if let Err(e) = markers.record(name, num, cksum) {
+                                    errors.record(Some(name), Some(num), "verify-marker", &e);
+                                }
+                            }
+                        }
+
+                        failed
+                    })
+                    .count()
+            });
+            bar.finish();
+
+            if failures > 0 {
+                anyhow::bail!("{failures} crate version(s) failed verification");
+            }
+        }
+        Command::Versions { name } => {
+            let summaries = match index.version_summaries(&name) {
+                Err(e @ index::Error::NotFound(_)) => return Err(not_found(&index, &name, e)),
+                result => result?,
+            };
+
+            let mut stdout = std::io::stdout().lock();
+            for summary in summaries {
+                if summary.yanked {
+                    writeln!(stdout, "{} (yanked)", summary.num)?;
+                } else {
+                    writeln!(stdout, "{}", summary.num)?;
+                }
+            }
         }
     }
 
     Ok(())
 }
 
+fn version_filter(
+    crates: Option<&CrateSet>,
+    min_version: Option<semver::Version>,
+    stable_only: bool,
+) -> VersionFilter {
+    VersionFilter {
+        min_version,
+        stable_only,
+        requirements: crates.map(CrateSet::requirements).unwrap_or_default(),
+    }
+}
+
+#[cfg(feature = "async")]
+fn populate_async(
+    corpus: Corpus,
+    user_agent: &str,
+    contact: Option<&str>,
+    versions: Vec<(String, String, String, Option<u64>)>,
+    concurrency: usize,
+    on_error: OnError,
+    progress: &dyn Progress,
+) -> anyhow::Result<Vec<(String, String, corpus::Error)>> {
+    use futures::StreamExt;
+    use librarian::async_corpus::AsyncCorpus;
+
+    let corpus = AsyncCorpus::new(corpus)?.with_identity(user_agent, contact)?;
+    progress.start("Downloading crates", Some(versions.len() as u64));
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let mut failures = Vec::new();
+        // The async client always buffers downloads in memory, so sizes aren't needed.
+        let versions = versions
+            .into_iter()
+            .map(|(name, num, cksum, _size)| (name, num, cksum));
+        let mut results = corpus.populate_all(versions, concurrency);
+        while let Some((name, num, result)) = results.next().await {
+            progress.advance(1);
+            if let Err(e) = result {
+                corpus.corpus().discard(&name, &num);
+                failures.push((name, num, e));
+                if on_error == OnError::FailFast {
+                    break;
+                }
+            }
+        }
+        progress.finish();
+
+        Ok(failures)
+    })
+}
+
+#[cfg(not(feature = "async"))]
+fn populate_async(
+    _corpus: Corpus,
+    _user_agent: &str,
+    _contact: Option<&str>,
+    _versions: Vec<(String, String, String, Option<u64>)>,
+    _concurrency: usize,
+    _on_error: OnError,
+    _progress: &dyn Progress,
+) -> anyhow::Result<Vec<(String, String, corpus::Error)>> {
+    anyhow::bail!("--async requires librarian to be built with the `async` feature")
+}
+
+#[cfg(feature = "metrics")]
+fn install_metrics_exporter(addr: SocketAddr) -> anyhow::Result<()> {
+    Ok(librarian::telemetry::install_exporter(addr)?)
+}
+
+#[cfg(not(feature = "metrics"))]
+fn install_metrics_exporter(_addr: SocketAddr) -> anyhow::Result<()> {
+    anyhow::bail!("--metrics-listen requires librarian to be built with the `metrics` feature")
+}
+
+/// Returns the remotes to update the index from: the ones given with `--remote`, or else the
+/// registry that `--index` names in cargo's configuration, or else crates.io.
+fn index_remotes(index: &Path, remotes: Vec<String>) -> anyhow::Result<Vec<String>> {
+    if !remotes.is_empty() {
+        return Ok(remotes);
+    }
+
+    let registry = match index.components().collect::<Vec<_>>().as_slice() {
+        [Component::Normal(name)] => match name.to_str() {
+            // Most index directories aren't registry names at all, so a cargo config that can't
+            // be loaded shouldn't stop the index from being updated from crates.io.
+            Some(name) => match CargoConfig::load() {
+                Ok(config) => config.registry_remote(name)?,
+                Err(e) => {
+                    tracing::warn!(%e, "ignoring cargo config that couldn't be loaded");
+                    None
+                }
+            },
+            None => None,
+        },
+        _ => None,
+    };
+    if let Some(registry) = &registry {
+        tracing::info!(?index, %registry, "using registry index from cargo config");
+    }
+
+    Ok(vec![registry.map_or_else(
+        || CRATES_IO_INDEX.to_string(),
+        |registry| registry.to_string(),
+    )])
+}
+
+fn error_sink(path: Option<&Path>) -> std::io::Result<ErrorSink> {
+    path.map_or_else(|| Ok(ErrorSink::none()), ErrorSink::open)
+}
+
+fn parse_rust_version(s: &str) -> anyhow::Result<semver::Version> {
+    filter::parse_rust_version(s).ok_or_else(|| anyhow::anyhow!("invalid Rust version: {s}"))
+}
+
+fn parse_failure_fraction(s: &str) -> anyhow::Result<f64> {
+    let fraction: f64 = s.parse()?;
+    if fraction > 0.0 && fraction <= 1.0 {
+        Ok(fraction)
+    } else {
+        anyhow::bail!("must be greater than 0, and at most 1: {s}")
+    }
+}
+
+/// Adds suggestions for similarly named crates to a not found error, if there are any.
+fn not_found(index: &Index, name: &str, e: index::Error) -> anyhow::Error {
+    let suggestions = index.suggest(name, 3);
+    if suggestions.is_empty() {
+        e.into()
+    } else {
+        anyhow::anyhow!("{e} (did you mean {}?)", suggestions.join(", "))
+    }
+}
+
+fn select_crates(
+    index: &Index,
+    crates: Option<CrateSet>,
+    exclude: Option<&CrateSet>,
+    on_interrupt: OnInterrupt,
+) -> anyhow::Result<Vec<Krate>> {
+    // Excluded crates are dropped by name, before their index files are read.
+    let keep = |name: &str| exclude.is_none_or(|exclude| !exclude.contains(name));
+    match crates {
+        Some(crates) => {
+            let names: Vec<String> = crates.0.into_keys().filter(|name| keep(name)).collect();
+
+            // Report every unknown crate at once, rather than only the first one that's opened.
+            let mut missing: Vec<&String> =
+                names.iter().filter(|name| !index.exists(name)).collect();
+            missing.sort();
+            if !missing.is_empty() {
+                let missing: Vec<String> = missing
+                    .into_iter()
+                    .map(|name| match index.suggest(name, 3).as_slice() {
+                        [] => name.clone(),
+                        suggestions => format!("{name} (did you mean {}?)", suggestions.join(", ")),
+                    })
+                    .collect();
+                anyhow::bail!("crate(s) not found in the index: {}", missing.join("; "));
+            }
+
+            names
+                .into_par_iter()
+                .map(|name| match index.get(&CrateName::new(name.as_str())?) {
+                    Err(e @ index::Error::NotFound(_)) => Err(not_found(index, &name, e)),
+                    result => Ok(result?),
+                })
+                .collect()
+        }
+        None if on_interrupt == OnInterrupt::Exit => Ok(index
+            .all_matching(&AtomicBool::new(false), &keep, &IndicatifProgress::new())
+            .crates
+            .into_iter()
+            .collect::<Result<_, _>>()?),
+        None => {
+            // Ctrl-C stops walking the index rather than exiting, so that a long exploratory run
+            // can carry on with part of the index.
+            WALKING.store(true, Ordering::Relaxed);
+            ctrlc::set_handler(|| {
+                // A second Ctrl-C, or one once the walk is over, exits as usual.
+                if !WALKING.swap(false, Ordering::Relaxed) {
+                    std::process::exit(130);
+                }
+                INTERRUPTED.store(true, Ordering::Relaxed);
+            })?;
+            let partial = index.all_matching(&INTERRUPTED, &keep, &IndicatifProgress::new());
+            WALKING.store(false, Ordering::Relaxed);
+
+            if partial.interrupted {
+                eprintln!(
+                    "Interrupted: continuing with the {} crate(s) found so far",
+                    partial.crates.len()
+                );
+            }
+            Ok(partial.crates.into_iter().collect::<Result<_, _>>()?)
+        }
+    }
+}
+
+/// What Ctrl-C does while [`select_crates`] is walking the whole index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OnInterrupt {
+    /// Exit as usual. Commands that modify a corpus or registry use this, so that they never act
+    /// on part of the index as if it were the whole thing.
+    Exit,
+
+    /// Stop walking, and carry on with the crates found so far.
+    KeepPartial,
+}
+
+/// Set while [`select_crates`] is walking the whole index and will keep a partial result.
+static WALKING: AtomicBool = AtomicBool::new(false);
+
+/// Set when Ctrl-C is received while [`select_crates`] is walking the whole index.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
 #[derive(Clone)]
-struct CrateSet(HashSet<String>);
+struct CrateSet(HashMap<String, Option<VersionReq>>);
+
+impl CrateSet {
+    /// Returns true if the set contains the given crate, ignoring case as crates.io does.
+    ///
+    /// Entries containing `*` or `?` are matched as globs.
+    fn contains(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.0.keys().any(|key| {
+            let key = key.to_ascii_lowercase();
+            if is_glob(&key) {
+                ignore::glob_matches(&key, &name)
+            } else {
+                key == name
+            }
+        })
+    }
+
+    /// Replaces each glob in the set with the names of the crates in the index that match it,
+    /// carrying over its version requirement. Exact names are left as they are.
+    ///
+    /// An exact name takes precedence over any globs that also match it, so `serde@^1.0,serde*`
+    /// only populates `serde` versions matching `^1.0`. A crate matched by more than one glob has
+    /// to satisfy all of their requirements.
+    ///
+    /// A glob that doesn't match any crates is an error, since it's almost certainly a typo.
+    fn expand(self, index: &Index) -> anyhow::Result<Self> {
+        if !self.0.keys().any(|name| is_glob(name)) {
+            return Ok(self);
+        }
+
+        let names = index.crate_names()?;
+        let (globs, exact): (HashMap<_, _>, HashMap<_, _>) =
+            self.0.into_iter().partition(|(spec, _req)| is_glob(spec));
+        let exact_lower: HashSet<String> =
+            exact.keys().map(|name| name.to_ascii_lowercase()).collect();
+
+        // Globs are applied in a fixed order, so that the result doesn't depend on the order of
+        // the map.
+        let globs: BTreeMap<_, _> = globs.into_iter().collect();
+        let mut expanded = exact;
+        for (spec, req) in globs {
+            let glob = spec.to_ascii_lowercase();
+            let mut matched = false;
+            for name in names
+                .iter()
+                .filter(|name| ignore::glob_matches(&glob, name))
+            {
+                matched = true;
+                if exact_lower.contains(name) {
+                    continue;
+                }
+                let merged = match (expanded.remove(name), &req) {
+                    (None, req) => req.clone(),
+                    (Some(existing), None) => existing,
+                    (Some(None), Some(req)) => Some(req.clone()),
+                    (Some(Some(mut existing)), Some(req)) => {
+                        existing.comparators.extend(req.comparators.iter().cloned());
+                        Some(existing)
+                    }
+                };
+                expanded.insert(name.clone(), merged);
+            }
+            if !matched {
+                anyhow::bail!("no crates match {spec:?}");
+            }
+        }
+
+        Ok(Self(expanded))
+    }
+
+    /// Returns the version requirements in the set, keyed by lowercased crate name as
+    /// [`VersionFilter::requirements`] expects.
+    fn requirements(&self) -> HashMap<String, VersionReq> {
+        self.0
+            .iter()
+            .filter_map(|(name, req)| Some((name.to_ascii_lowercase(), req.clone()?)))
+            .collect()
+    }
+}
+
+fn is_glob(spec: &str) -> bool {
+    spec.contains(['*', '?'])
+}
 
 impl FromStr for CrateSet {
     type Err = anyhow::Error;
 
+    /// Parses a comma separated list of crates, each optionally given as `name@req`.
+    ///
+    /// A crate may only be given once, ignoring case: there's no obvious way to combine
+    /// `serde@^1.0,serde@^2.0`, and silently keeping one of them would be worse.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self(
-            s.split(',').map(str::trim).map(String::from).collect(),
-        ))
+        let mut crates = HashMap::new();
+        let mut seen = HashSet::new();
+        for spec in s.split(',').map(str::trim) {
+            let (name, req) = match spec.split_once('@') {
+                Some((name, req)) => (name, Some(VersionReq::parse(req)?)),
+                None => (spec, None),
+            };
+            if !seen.insert(name.to_ascii_lowercase()) {
+                anyhow::bail!("{name:?} is given more than once");
+            }
+            crates.insert(name.to_string(), req);
+        }
+
+        Ok(Self(crates))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    fn index_with(names: &[&str]) -> anyhow::Result<(tempfile::TempDir, Index)> {
+        let temp = tempfile::tempdir()?;
+        let index = Index::new(temp.path())?;
+        for name in names {
+            let path = index.crate_path(name)?;
+            std::fs::create_dir_all(path.parent().expect("index file parent"))?;
+            std::fs::write(path, "")?;
+        }
+        Ok((temp, index))
+    }
+
+    fn expanded(spec: &str, index: &Index) -> anyhow::Result<BTreeMap<String, Option<String>>> {
+        Ok(spec
+            .parse::<CrateSet>()?
+            .expand(index)?
+            .0
+            .into_iter()
+            .map(|(name, req)| (name, req.map(|req| req.to_string())))
+            .collect())
+    }
+
+    #[test]
+    fn test_parse_failure_fraction() {
+        assert_that!(parse_failure_fraction("0.5"), ok(eq(0.5)));
+        assert_that!(parse_failure_fraction("1"), ok(eq(1.0)));
+        for invalid in ["0", "-0.5", "1.5", "NaN", "half"] {
+            assert_that!(parse_failure_fraction(invalid), err(anything()));
+        }
+    }
+
+    #[test]
+    fn test_parse_crate_set() -> anyhow::Result<()> {
+        let crates = "serde@^1.0, tokio".parse::<CrateSet>()?;
+        assert_that!(crates.contains("Serde"), eq(true));
+        assert_that!(
+            crates.requirements().get("serde").map(ToString::to_string),
+            some(eq("^1.0"))
+        );
+        assert_that!(crates.requirements().get("tokio"), none());
+
+        // Requirements apply whatever case the crate is given in, or has in the index.
+        for spec in ["Serde@^1.0", "serde@^1.0"] {
+            let crates = spec.parse::<CrateSet>()?;
+            let filter = version_filter(Some(&crates), None, false);
+            for name in ["serde", "Serde", "SERDE"] {
+                assert_that!(filter.matches(name, "1.2.0"), eq(true));
+                assert_that!(filter.matches(name, "2.0.0"), eq(false));
+            }
+        }
+
+        for duplicated in ["serde,serde", "serde@^1.0,serde@^2.0", "serde,Serde@^1.0"] {
+            assert_that!(duplicated.parse::<CrateSet>(), err(anything()));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_crates_exclude() -> anyhow::Result<()> {
+        let (_temp, index) = index_with(&["serde", "serde_json", "tokio"])?;
+        let names = |crates: Option<&str>| -> anyhow::Result<Vec<String>> {
+            let crates = crates.map(str::parse).transpose()?;
+            let exclude = "serde*".parse::<CrateSet>()?;
+            let mut names: Vec<String> =
+                select_crates(&index, crates, Some(&exclude), OnInterrupt::Exit)?
+                    .iter()
+                    .map(|krate| krate.name().to_string())
+                    .collect();
+            names.sort();
+            Ok(names)
+        };
+
+        assert_that!(names(None)?, elements_are![eq("tokio")]);
+        assert_that!(names(Some("serde,tokio"))?, elements_are![eq("tokio")]);
+        // Excluded crates don't have to be in the index.
+        assert_that!(names(Some("serde_yaml,tokio"))?, elements_are![eq("tokio")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand() -> anyhow::Result<()> {
+        let (_temp, index) = index_with(&["serde", "serde_json", "serde_derive", "tokio"])?;
+
+        // Exact names are left alone.
+        assert_that!(
+            expanded("tokio@^1.0", &index)?,
+            eq(BTreeMap::from([(
+                "tokio".to_string(),
+                Some("^1.0".to_string())
+            )]))
+        );
+
+        // Globs expand to every matching crate, with the glob's requirement.
+        assert_that!(
+            expanded("serde_*@^1", &index)?,
+            eq(BTreeMap::from([
+                ("serde_derive".to_string(), Some("^1".to_string())),
+                ("serde_json".to_string(), Some("^1".to_string())),
+            ]))
+        );
+        assert_that!(expanded("nope*", &index), err(anything()));
+
+        // An exact name overrides a glob that also matches it, whichever comes first.
+        for spec in [
+            "serde@^1.0,serde*",
+            "serde*,serde@^1.0",
+            "serde*,Serde@^1.0",
+        ] {
+            let expanded = expanded(spec, &index)?;
+            assert_that!(expanded.len(), eq(3));
+            assert_that!(
+                expanded
+                    .iter()
+                    .find(|(name, _req)| name.eq_ignore_ascii_case("serde"))
+                    .and_then(|(_name, req)| req.clone()),
+                some(eq("^1.0"))
+            );
+            assert_that!(expanded.get("serde_json"), some(none()));
+        }
+
+        // Overlapping globs have their requirements combined.
+        assert_that!(
+            expanded("serde*@>=1.0,serde_*@<2", &index)?.get("serde_json"),
+            some(some(eq(">=1.0, <2")))
+        );
+
+        Ok(())
     }
 }
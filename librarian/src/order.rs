@@ -0,0 +1,128 @@
+use std::cmp::Reverse;
+
+use clap::ValueEnum;
+
+use crate::index::krate::cmp_version_nums;
+
+/// The order in which crate versions are dispatched for download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DownloadOrder {
+    /// By crate name, then by semver.
+    Alphabetical,
+
+    /// Shuffled, which also spreads the load across CDN shards.
+    Random,
+
+    /// The smallest `.crate` files first, by the size recorded in the index.
+    ///
+    /// Versions that the index doesn't record a size for come last.
+    BySizeAsc,
+
+    /// The largest `.crate` files first, by the size recorded in the index.
+    ///
+    /// Versions that the index doesn't record a size for come last.
+    BySizeDesc,
+}
+
+impl DownloadOrder {
+    /// Reorders the given items, using `key` to get the crate name, version number, and `.crate`
+    /// file size of each.
+    ///
+    /// Sorts are stable, so items that compare equal keep their original order.
+    pub fn apply<T, F>(&self, items: &mut [T], key: F)
+    where
+        F: Fn(&T) -> (&str, &str, Option<u64>),
+    {
+        match self {
+            Self::Alphabetical => items.sort_by(|a, b| {
+                let ((a_name, a_num, _), (b_name, b_num, _)) = (key(a), key(b));
+                a_name
+                    .cmp(b_name)
+                    .then_with(|| cmp_version_nums(a_num, b_num))
+            }),
+            Self::Random => fastrand::shuffle(items),
+            Self::BySizeAsc => items.sort_by_key(|item| {
+                let size = key(item).2;
+                (size.is_none(), size)
+            }),
+            Self::BySizeDesc => items.sort_by_key(|item| {
+                let size = key(item).2;
+                (size.is_none(), Reverse(size))
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    type Item = (String, String, Option<u64>);
+
+    #[test]
+    fn test_apply() {
+        let items = versions(&[
+            ("b", "1.0.0", Some(30)),
+            ("a", "0.10.0", Some(20)),
+            ("c", "1.0.0", None),
+            ("b", "0.1.0", Some(10)),
+            ("a", "0.9.0", Some(40)),
+        ]);
+
+        let mut alphabetical = items.clone();
+        DownloadOrder::Alphabetical.apply(&mut alphabetical, key);
+        assert_that!(
+            alphabetical,
+            eq(versions(&[
+                ("a", "0.9.0", Some(40)),
+                ("a", "0.10.0", Some(20)),
+                ("b", "0.1.0", Some(10)),
+                ("b", "1.0.0", Some(30)),
+                ("c", "1.0.0", None),
+            ]))
+        );
+
+        let mut desc = items.clone();
+        DownloadOrder::BySizeDesc.apply(&mut desc, key);
+        assert_that!(
+            desc,
+            eq(versions(&[
+                ("a", "0.9.0", Some(40)),
+                ("b", "1.0.0", Some(30)),
+                ("a", "0.10.0", Some(20)),
+                ("b", "0.1.0", Some(10)),
+                ("c", "1.0.0", None),
+            ]))
+        );
+
+        let mut asc = items.clone();
+        DownloadOrder::BySizeAsc.apply(&mut asc, key);
+        assert_that!(
+            asc,
+            eq(versions(&[
+                ("b", "0.1.0", Some(10)),
+                ("a", "0.10.0", Some(20)),
+                ("b", "1.0.0", Some(30)),
+                ("a", "0.9.0", Some(40)),
+                ("c", "1.0.0", None),
+            ]))
+        );
+
+        let mut random = items.clone();
+        DownloadOrder::Random.apply(&mut random, key);
+        assert_that!(random.len(), eq(items.len()));
+    }
+
+    fn versions(versions: &[(&str, &str, Option<u64>)]) -> Vec<Item> {
+        versions
+            .iter()
+            .map(|(name, num, size)| (name.to_string(), num.to_string(), *size))
+            .collect()
+    }
+
+    fn key((name, num, size): &Item) -> (&str, &str, Option<u64>) {
+        (name, num, *size)
+    }
+}
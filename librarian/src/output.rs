@@ -0,0 +1,98 @@
+use std::io::Write;
+
+use clap::ValueEnum;
+use vault::CrateVersion;
+
+/// Output formats for commands that list crate versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// A single JSON array.
+    Json,
+
+    /// One JSON object per line.
+    Jsonl,
+
+    /// Tab separated values, with a header row.
+    Tsv,
+}
+
+impl OutputFormat {
+    pub fn write_versions<W, I>(&self, mut w: W, versions: I) -> anyhow::Result<()>
+    where
+        W: Write,
+        I: IntoIterator<Item = CrateVersion>,
+    {
+        match self {
+            Self::Json => {
+                let versions: Vec<_> = versions.into_iter().collect();
+                serde_json::to_writer(&mut w, &versions)?;
+                writeln!(w)?;
+            }
+            Self::Jsonl => {
+                for version in versions {
+                    serde_json::to_writer(&mut w, &version)?;
+                    writeln!(w)?;
+                }
+            }
+            Self::Tsv => {
+                writeln!(w, "name\tversion\tpath")?;
+                for version in versions {
+                    writeln!(
+                        w,
+                        "{}\t{}\t{}",
+                        escape_tsv(&version.crate_name),
+                        escape_tsv(&version.version),
+                        escape_tsv(&version.path.to_string_lossy())
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Escapes the characters that would otherwise break a TSV row, using the same backslash escapes
+/// as PostgreSQL's `COPY` text format.
+fn escape_tsv(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_write_versions_tsv() -> anyhow::Result<()> {
+        let mut out = Vec::new();
+        OutputFormat::Tsv.write_versions(
+            &mut out,
+            [CrateVersion {
+                crate_name: "foo".into(),
+                version: "1.0.0".into(),
+                path: PathBuf::from("/corpus/f/fo/foo/1.0.0\tweird\n/Cargo.toml"),
+            }],
+        )?;
+
+        assert_that!(
+            String::from_utf8(out)?,
+            eq("name\tversion\tpath\nfoo\t1.0.0\t/corpus/f/fo/foo/1.0.0\\tweird\\n/Cargo.toml\n")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::Mutex;
+
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+
+/// Receives progress updates from long running operations, so that library consumers can drive
+/// their own UI.
+///
+/// Operations report progress as a series of phases: each is begun with [`Progress::start`],
+/// updated with [`Progress::advance`] or [`Progress::set_position`], and ended with
+/// [`Progress::finish`]. Updates may arrive from several threads at once.
+pub trait Progress: Send + Sync {
+    /// Starts a new phase, with the total number of steps if it's known up front.
+    fn start(&self, label: &str, total: Option<u64>);
+
+    /// Advances the current phase by the given number of steps.
+    fn advance(&self, delta: u64);
+
+    /// Sets the current position within the phase, for operations that report absolute progress.
+    fn set_position(&self, position: u64);
+
+    /// Ends the current phase.
+    fn finish(&self);
+
+    /// Reports the total number of bytes received so far by an operation that downloads, such as
+    /// fetching the index. This is independent of the current phase.
+    fn bytes(&self, _received: u64) {}
+
+    /// Reports a free-form message, such as the output of a git remote.
+    fn message(&self, _message: &str) {}
+}
+
+/// A [`Progress`] that ignores all updates.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn start(&self, _label: &str, _total: Option<u64>) {}
+
+    fn advance(&self, _delta: u64) {}
+
+    fn set_position(&self, _position: u64) {}
+
+    fn finish(&self) {}
+}
+
+/// A [`Progress`] that displays each phase as an indicatif progress bar.
+#[derive(Debug)]
+pub struct IndicatifProgress(Mutex<ProgressBar>);
+
+impl IndicatifProgress {
+    pub fn new() -> Self {
+        Self(Mutex::new(ProgressBar::hidden()))
+    }
+
+    fn bar(&self) -> ProgressBar {
+        self.0.lock().expect("progress bar lock").clone()
+    }
+}
+
+impl Default for IndicatifProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Progress for IndicatifProgress {
+    fn start(&self, label: &str, total: Option<u64>) {
+        // The label goes in the prefix rather than the template, so that braces in it aren't
+        // parsed as placeholders.
+        let bar = match total {
+            Some(total) => ProgressBar::new(total).with_style(
+                ProgressStyle::with_template("{prefix} {wide_bar} {pos}/{len} ETA: {eta} {msg}")
+                    .expect("bar template"),
+            ),
+            None => ProgressBar::new(0).with_style(
+                ProgressStyle::with_template("{prefix}: {pos} {msg}").expect("bar template"),
+            ),
+        }
+        .with_prefix(label.to_string());
+
+        *self.0.lock().expect("progress bar lock") = bar;
+    }
+
+    fn advance(&self, delta: u64) {
+        self.bar().inc(delta);
+    }
+
+    fn set_position(&self, position: u64) {
+        self.bar().set_position(position);
+    }
+
+    fn finish(&self) {
+        self.bar().finish();
+    }
+
+    fn bytes(&self, received: u64) {
+        self.bar().set_message(HumanBytes(received).to_string());
+    }
+
+    fn message(&self, message: &str) {
+        self.bar().println(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_with_braces() {
+        let progress = IndicatifProgress::new();
+        for total in [Some(1), None] {
+            progress.start("Populating {foo} }{", total);
+            progress.advance(1);
+            progress.finish();
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    fs::File,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use tempfile::NamedTempFile;
+use thiserror::Error;
+
+use crate::{
+    download::{self, Downloader},
+    index::{self, krate::Version, Index},
+};
+
+/// A directory in the format used by cargo's `local-registry` source replacement: `.crate` files
+/// at the top level, alongside an `index` directory laid out like the crates.io index.
+///
+/// To build against it, point cargo at it with something like:
+///
+/// ```toml
+/// [source.crates-io]
+/// replace-with = "librarian"
+///
+/// [source.librarian]
+/// local-registry = "/path/to/registry"
+/// ```
+#[derive(Debug)]
+pub struct LocalRegistry {
+    root: PathBuf,
+    downloader: Downloader,
+}
+
+impl LocalRegistry {
+    #[tracing::instrument(skip(downloader), err)]
+    pub fn new(root: PathBuf, downloader: Downloader) -> Result<Self, Error> {
+        std::fs::create_dir_all(root.join("index"))?;
+        let root = std::fs::canonicalize(root)?;
+
+        let config = serde_json::json!({
+            "dl": format!("file://{}/{{crate}}-{{version}}.crate", root.display()),
+        });
+        std::fs::write(
+            root.join("index").join("config.json"),
+            serde_json::to_vec_pretty(&config)?,
+        )?;
+
+        Ok(Self { root, downloader })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path to the `.crate` file for the given crate version, whether or not it
+    /// exists.
+    pub fn crate_file_path(&self, name: &str, num: &str) -> PathBuf {
+        self.root.join(format!("{name}-{num}.crate"))
+    }
+
+    /// Adds the given versions of a crate to the registry, then rewrites the crate's index file.
+    ///
+    /// Each `.crate` file is verified against the index checksum as it is downloaded, since cargo
+    /// will refuse to use it otherwise. Versions that are already present are not downloaded
+    /// again.
+    #[tracing::instrument(skip(self, index, versions), err)]
+    pub fn add<'a, I>(&self, index: &Index, name: &str, versions: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = &'a Version>,
+    {
+        for version in versions {
+            let path = self.crate_file_path(version.name(), version.num());
+            if path.exists() {
+                continue;
+            }
+
+            // Verified downloads are always cached, so we can clone the cached file rather than
+            // storing a second copy, if the filesystem allows. The clone is verified like any
+            // other read from the cache.
+            if let Some(cache) = self.downloader.tarball_cache() {
+                if cache.copy_to(version.name(), version.num(), &path)? {
+                    let actual = download::sha256_reader(
+                        File::open(&path)?,
+                        download::DEFAULT_VERIFY_BUFFER_SIZE,
+                    )?;
+                    if actual == version.cksum() {
+                        continue;
+                    }
+
+                    tracing::warn!(
+                        name = version.name(),
+                        num = version.num(),
+                        "cached tarball failed verification; removing"
+                    );
+                    std::fs::remove_file(&path)?;
+                    cache.remove(version.name(), version.num())?;
+                }
+            }
+
+            self.downloader
+                .download_to_file(version.name(), version.num(), version.cksum(), &self.root)?
+                .persist(&path)
+                .map_err(|e| e.error)?;
+        }
+
+        self.write_index(index, name)
+    }
+
+    /// Writes the registry index file for a crate, containing the upstream index entries for each
+    /// version that has a `.crate` file in the registry.
+    ///
+    /// Entries are copied verbatim, so the dependency and feature metadata cargo needs for
+    /// resolution is preserved.
+    fn write_index(&self, index: &Index, name: &str) -> Result<(), Error> {
+        // Going through the index, rather than to the file, finds gzipped and packed entries too.
+        let source = index.get_raw(name)?;
+
+        let mut content = String::new();
+        for line in source.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry: Entry = serde_json::from_str(&line)?;
+            if self.crate_file_path(&entry.name, &entry.vers).exists() {
+                content.push_str(&line);
+                content.push('\n');
+            }
+        }
+
+        let path = self
+            .root
+            .join("index")
+            .join(index::relative_crate_path(name)?);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut temp = NamedTempFile::new_in(&self.root)?;
+        temp.write_all(content.as_bytes())?;
+        temp.persist(&path).map_err(|e| e.error)?;
+
+        Ok(())
+    }
+}
+
+/// The parts of an index entry needed to find its `.crate` file.
+#[derive(Deserialize)]
+struct Entry {
+    name: String,
+    vers: String,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("download error: {0:?}")]
+    Download(#[from] download::Error),
+
+    #[error("index error: {0:?}")]
+    Index(#[from] index::Error),
+
+    #[error("io error: {0:?}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0:?}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_add() -> anyhow::Result<()> {
+        let index_dir = tempfile::tempdir()?;
+        let index = Index::new(index_dir.path())?;
+        let lines = ["0.1.0", "0.2.0", "0.3.0"].map(|num| {
+            format!(
+                r#"{{"name":"Foo","vers":"{num}","deps":[],"cksum":"","features":{{}},"yanked":false}}"#
+            )
+        });
+        std::fs::create_dir_all(index_dir.path().join("3/f"))?;
+        std::fs::write(index_dir.path().join("3/f/foo"), lines.join("\n"))?;
+
+        let temp = tempfile::tempdir()?;
+        let registry = LocalRegistry::new(temp.path().join("registry"), Downloader::new()?)?;
+
+        // Pretend these versions were downloaded previously, so add() doesn't go to the network.
+        std::fs::write(registry.crate_file_path("Foo", "0.1.0"), "")?;
+        std::fs::write(registry.crate_file_path("Foo", "0.3.0"), "")?;
+
+        let krate = index.get(&"foo".parse()?)?;
+        let versions: Vec<&Version> = krate
+            .iter_versions()
+            .filter(|(num, _version)| *num != "0.2.0")
+            .map(|(_num, version)| version)
+            .collect();
+        registry.add(&index, "foo", versions)?;
+
+        assert_that!(
+            std::fs::read_to_string(registry.path().join("index/3/f/foo"))?,
+            eq(format!("{}\n{}\n", lines[0], lines[2]))
+        );
+
+        // Packed index entries are found too.
+        assert_that!(index.pack_crate("foo")?, eq(true));
+        std::fs::remove_file(registry.path().join("index/3/f/foo"))?;
+        registry.add(&index, "foo", std::iter::empty())?;
+        assert_that!(
+            std::fs::read_to_string(registry.path().join("index/3/f/foo"))?,
+            eq(format!("{}\n{}\n", lines[0], lines[2]))
+        );
+        assert_that!(
+            std::fs::read_to_string(registry.path().join("index/config.json"))?,
+            contains_substring("{crate}-{version}.crate")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+#[cfg(feature = "metrics")]
+use std::net::SocketAddr;
+
+use crate::corpus;
+
+/// Installs a global recorder that serves the metrics in the Prometheus text format over HTTP on
+/// the given address, from a background thread.
+///
+/// The exporter responds to every path, so `/metrics` and a health check of `/` both work. The
+/// recording functions in this module do nothing unless the `metrics` feature is enabled.
+#[cfg(feature = "metrics")]
+pub fn install_exporter(addr: SocketAddr) -> Result<(), metrics_exporter_prometheus::BuildError> {
+    metrics_exporter_prometheus::PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .install()
+}
+
+/// Records that a crate version was downloaded and populated.
+#[cfg(feature = "metrics")]
+pub(crate) fn version_populated(bytes: u64) {
+    ::metrics::counter!("librarian_versions_populated_total").increment(1);
+    ::metrics::counter!("librarian_downloaded_bytes_total").increment(bytes);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn version_populated(_bytes: u64) {}
+
+/// Records that a crate version failed to populate, labelled by the broad category of error.
+#[cfg(feature = "metrics")]
+pub(crate) fn version_failed(e: &corpus::Error) {
+    let category = match e {
+        corpus::Error::Download(_) => "download",
+        corpus::Error::Io(_) => "io",
+        corpus::Error::MissingManifest { .. } | corpus::Error::TomlDe(_) => "manifest",
+        corpus::Error::DuplicateEntry(_) | corpus::Error::UnsafePath(_) => "archive",
+        corpus::Error::Vault(_) => "vault",
+        _ => "other",
+    };
+    ::metrics::counter!("librarian_populate_errors_total", "category" => category).increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn version_failed(_e: &corpus::Error) {}
+
+/// Sets the number of crate versions waiting to be populated.
+#[cfg(feature = "metrics")]
+pub(crate) fn queue_depth(depth: usize) {
+    ::metrics::gauge!("librarian_populate_queue_depth").set(depth as f64);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn queue_depth(_depth: usize) {}
+
+/// Records that a crate version has been taken from the queue to be populated.
+#[cfg(feature = "metrics")]
+pub(crate) fn dequeued() {
+    ::metrics::gauge!("librarian_populate_queue_depth").decrement(1.0);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn dequeued() {}
+
+/// Records the outcome of an index update.
+#[cfg(feature = "metrics")]
+pub(crate) fn index_updated(success: bool) {
+    let result = if success { "success" } else { "failure" };
+    ::metrics::counter!("librarian_index_updates_total", "result" => result).increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn index_updated(_success: bool) {}
@@ -0,0 +1,143 @@
+use std::{
+    io::Read,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The most that a single read through a [`Throttled`] reader will request, so that no caller
+/// has to wait long for its share of the bandwidth.
+const MAX_CHUNK: usize = 16 * 1024;
+
+/// Caps the aggregate throughput of downloads, however many of them are running at once.
+///
+/// This is a token bucket holding up to one second's worth of bytes. Cloning a limiter shares the
+/// bucket, so every clone counts against the same cap.
+#[derive(Debug, Clone)]
+pub struct BandwidthLimiter {
+    bytes_per_sec: f64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// The bytes that can be read without waiting. This goes negative when readers have reserved
+    /// more than is available, and are sleeping until the bucket refills.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl BandwidthLimiter {
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_sec` is zero.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        assert!(bytes_per_sec > 0, "bandwidth limit must be non-zero");
+        let bytes_per_sec = bytes_per_sec as f64;
+
+        Self {
+            bytes_per_sec,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: bytes_per_sec,
+                refilled: Instant::now(),
+            })),
+        }
+    }
+
+    /// Wraps a reader so that reads from it count against the limit.
+    pub fn reader<R: Read>(&self, inner: R) -> Throttled<R> {
+        Throttled {
+            inner,
+            limiter: Some(self.clone()),
+        }
+    }
+
+    /// Takes `bytes` from the bucket, sleeping until the bucket would have refilled enough to
+    /// cover them.
+    fn take(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().expect("bandwidth bucket lock");
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled).as_secs_f64() * self.bytes_per_sec;
+            bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_sec) - bytes as f64;
+            bucket.refilled = now;
+            Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.bytes_per_sec)
+        };
+
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+
+    fn chunk_size(&self) -> usize {
+        (self.bytes_per_sec as usize).clamp(1, MAX_CHUNK)
+    }
+}
+
+/// A reader that is optionally throttled by a [`BandwidthLimiter`].
+#[derive(Debug)]
+pub struct Throttled<R> {
+    inner: R,
+    limiter: Option<BandwidthLimiter>,
+}
+
+impl<R> Throttled<R> {
+    /// Wraps a reader that is only throttled if a limiter is given.
+    pub fn new(inner: R, limiter: Option<&BandwidthLimiter>) -> Self {
+        Self {
+            inner,
+            limiter: limiter.cloned(),
+        }
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(limiter) = &self.limiter else {
+            return self.inner.read(buf);
+        };
+
+        let len = buf.len().min(limiter.chunk_size());
+        let read = self.inner.read(&mut buf[..len])?;
+        limiter.take(read);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_throttled() -> anyhow::Result<()> {
+        let limiter = BandwidthLimiter::new(10_000);
+        let data = vec![0u8; 10_000];
+
+        // The first second's worth is available immediately, and the rest is shared between
+        // readers, so two readers of 10 KB need about another second between them.
+        let started = Instant::now();
+        std::thread::scope(|scope| -> anyhow::Result<()> {
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut read = Vec::new();
+                        limiter.reader(data.as_slice()).read_to_end(&mut read)?;
+                        anyhow::Ok(read.len())
+                    })
+                })
+                .collect();
+            for handle in handles {
+                assert_that!(handle.join().expect("reader thread")?, eq(10_000));
+            }
+            Ok(())
+        })?;
+        assert_that!(started.elapsed(), ge(Duration::from_millis(900)));
+
+        let mut read = Vec::new();
+        Throttled::new(data.as_slice(), None).read_to_end(&mut read)?;
+        assert_that!(read.len(), eq(10_000));
+
+        Ok(())
+    }
+}
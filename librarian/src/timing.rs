@@ -0,0 +1,227 @@
+use std::{
+    fmt,
+    io::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::jsonl::JsonlWriter;
+
+/// Somewhere to report how long each crate version took to download and extract, as JSON lines.
+///
+/// Each crate version is written as a single object with `crate`, `version`, `bytes`,
+/// `download_ms`, `extract_ms`, and `result` fields. `result` is `"ok"`, or the error that the
+/// version failed with. The other fields are `null` if the version failed before reaching that
+/// phase.
+#[derive(Debug)]
+pub struct TimingLog(JsonlWriter);
+
+impl TimingLog {
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Self(JsonlWriter::new("timing log", writer))
+    }
+
+    /// Opens a log that writes to the given file, or to stderr if the path is `-`.
+    #[tracing::instrument(err)]
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(Self(JsonlWriter::open("timing log", path)?))
+    }
+
+    /// Writes the timings of a crate version to the log.
+    ///
+    /// As with [`ErrorSink`](crate::error_sink::ErrorSink), write errors are only logged.
+    pub(crate) fn record<T, E>(
+        &self,
+        krate: &str,
+        version: &str,
+        timings: &Timings,
+        result: &Result<T, E>,
+    ) where
+        E: fmt::Display,
+    {
+        let record = Record {
+            krate,
+            version,
+            bytes: timings.bytes,
+            download_ms: timings.download.map(|elapsed| elapsed.as_millis()),
+            extract_ms: timings
+                .extract_started
+                .map(|started| started.elapsed().as_millis()),
+            result: match result {
+                Ok(_) => "ok".to_string(),
+                Err(e) => e.to_string(),
+            },
+        };
+        self.0.write(&record);
+    }
+}
+
+/// Somewhere to report the result of each crate version as soon as it completes, as JSON lines.
+///
+/// Each crate version is written as a single object with `crate`, `version`, `status`, `bytes`,
+/// and `duration_ms` fields, plus an `error` field if it failed. `status` is `"populated"`,
+/// `"existing"` if the version was already in the corpus, or `"failed"`. `bytes` is the size of
+/// the `.crate` file according to the index, and is `null` for existing versions.
+#[derive(Debug)]
+pub struct ResultStream(JsonlWriter);
+
+impl ResultStream {
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Self(JsonlWriter::new("result stream", writer))
+    }
+
+    /// Writes the result of a crate version to the stream.
+    ///
+    /// As with [`TimingLog`], write errors are only logged.
+    pub(crate) fn record<T, E>(
+        &self,
+        krate: &str,
+        version: &str,
+        existing: bool,
+        bytes: Option<u64>,
+        duration: Duration,
+        result: &Result<T, E>,
+    ) where
+        E: fmt::Display,
+    {
+        let record = ResultRecord {
+            krate,
+            version,
+            status: match (result, existing) {
+                (Err(_), _) => "failed",
+                (Ok(_), true) => "existing",
+                (Ok(_), false) => "populated",
+            },
+            bytes: bytes.filter(|_| !existing),
+            duration_ms: duration.as_millis(),
+            error: result.as_ref().err().map(ToString::to_string),
+        };
+        self.0.write(&record);
+    }
+}
+
+/// What has been measured so far while populating a crate version.
+#[derive(Debug, Default)]
+pub(crate) struct Timings {
+    /// The size of the downloaded `.crate` file.
+    pub(crate) bytes: Option<u64>,
+    pub(crate) download: Option<Duration>,
+    /// When extraction began: it's considered to last until the record is written.
+    pub(crate) extract_started: Option<Instant>,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(rename = "crate")]
+    krate: &'a str,
+    version: &'a str,
+    bytes: Option<u64>,
+    download_ms: Option<u128>,
+    extract_ms: Option<u128>,
+    result: String,
+}
+
+#[derive(Serialize)]
+struct ResultRecord<'a> {
+    #[serde(rename = "crate")]
+    krate: &'a str,
+    version: &'a str,
+    status: &'static str,
+    bytes: Option<u64>,
+    duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+    use crate::jsonl::tests::Shared;
+
+    #[test]
+    fn test_record() -> anyhow::Result<()> {
+        let buf = Shared::default();
+        let log = TimingLog::new(buf.clone());
+
+        let timings = Timings {
+            bytes: Some(1234),
+            download: Some(Duration::from_millis(56)),
+            extract_started: None,
+        };
+        log.record("foo", "1.0.0", &timings, &Ok::<_, String>(()));
+        log.record(
+            "bar",
+            "0.1.0",
+            &Timings::default(),
+            &Err::<(), _>("not found"),
+        );
+
+        assert_that!(
+            buf.contents()?,
+            eq(concat!(
+                r#"{"crate":"foo","version":"1.0.0","bytes":1234,"download_ms":56,"extract_ms":null,"result":"ok"}"#,
+                "\n",
+                r#"{"crate":"bar","version":"0.1.0","bytes":null,"download_ms":null,"extract_ms":null,"result":"not found"}"#,
+                "\n",
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_result_stream() -> anyhow::Result<()> {
+        let buf = Shared::default();
+        let stream = ResultStream::new(buf.clone());
+
+        let duration = Duration::from_millis(12);
+        stream.record(
+            "foo",
+            "1.0.0",
+            false,
+            Some(1234),
+            duration,
+            &Ok::<_, String>(()),
+        );
+        stream.record(
+            "foo",
+            "1.0.1",
+            true,
+            Some(1234),
+            duration,
+            &Ok::<_, String>(()),
+        );
+        stream.record(
+            "bar",
+            "0.1.0",
+            false,
+            None,
+            duration,
+            &Err::<(), _>("not found"),
+        );
+
+        assert_that!(
+            buf.contents()?,
+            eq(concat!(
+                r#"{"crate":"foo","version":"1.0.0","status":"populated","bytes":1234,"duration_ms":12}"#,
+                "\n",
+                r#"{"crate":"foo","version":"1.0.1","status":"existing","bytes":null,"duration_ms":12}"#,
+                "\n",
+                r#"{"crate":"bar","version":"0.1.0","status":"failed","bytes":null,"duration_ms":12,"error":"not found"}"#,
+                "\n",
+            ))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+use thiserror::Error;
+
+/// The directory within a corpus that verification markers are kept in.
+///
+/// The vault ignores it, since it doesn't contain any manifests.
+pub const MARKER_DIR: &str = ".verified";
+
+/// The checksum algorithm recorded in markers. Markers recorded with anything else are ignored.
+const ALGORITHM: &str = "sha256";
+
+/// Records which crate versions have passed `verify`, and when, so that periodic verification
+/// runs can skip versions that were verified recently.
+///
+/// Each version has a small JSON marker at `.verified/<name>/<version>.json` within the corpus.
+#[derive(Debug, Clone)]
+pub struct VerifiedMarkers {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Marker {
+    /// When the version was verified, in seconds since the Unix epoch.
+    verified_at: u64,
+    algorithm: String,
+    cksum: String,
+}
+
+impl VerifiedMarkers {
+    pub fn new(corpus: &Path) -> Self {
+        Self {
+            dir: corpus.join(MARKER_DIR),
+        }
+    }
+
+    fn marker_path(&self, name: &str, num: &str) -> PathBuf {
+        self.dir.join(name).join(format!("{num}.json"))
+    }
+
+    /// Returns true if the given version was verified against the given checksum within
+    /// `max_age`.
+    ///
+    /// If `manifest` is given and was modified after the version was verified, the version has
+    /// been repopulated since, and the marker is ignored. A marker that can't be parsed is
+    /// treated as missing, with a warning, so that the version is verified again.
+    #[tracing::instrument(skip(self), err)]
+    pub fn is_fresh(
+        &self,
+        name: &str,
+        num: &str,
+        cksum: &str,
+        max_age: Duration,
+        manifest: Option<&Path>,
+    ) -> Result<bool, Error> {
+        let path = self.marker_path(name, num);
+        let marker: Marker = match File::open(&path) {
+            Ok(file) => match serde_json::from_reader(file) {
+                Ok(marker) => marker,
+                Err(e) => {
+                    tracing::warn!(?path, ?e, "ignoring unreadable verification marker");
+                    return Ok(false);
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        if marker.algorithm != ALGORITHM || marker.cksum != cksum {
+            return Ok(false);
+        }
+
+        let verified_at = UNIX_EPOCH + Duration::from_secs(marker.verified_at);
+        if SystemTime::now()
+            .duration_since(verified_at)
+            .is_ok_and(|age| age > max_age)
+        {
+            return Ok(false);
+        }
+
+        if let Some(manifest) = manifest {
+            match std::fs::metadata(manifest) {
+                Ok(metadata) if metadata.modified()? > verified_at => return Ok(false),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Records that the given version was just verified against the given checksum.
+    #[tracing::instrument(skip(self), err)]
+    pub fn record(&self, name: &str, num: &str, cksum: &str) -> Result<(), Error> {
+        let marker = Marker {
+            verified_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            algorithm: ALGORITHM.to_string(),
+            cksum: cksum.to_string(),
+        };
+
+        let path = self.marker_path(name, num);
+        let dir = path.parent().expect("marker path has a parent");
+        std::fs::create_dir_all(dir)?;
+        let mut temp = NamedTempFile::new_in(dir)?;
+        temp.write_all(&serde_json::to_vec(&marker)?)?;
+        temp.persist(&path).map_err(|e| e.error)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error: {0:?}")]
+    Io(#[from] std::io::Error),
+
+    #[error("JSON error: {0:?}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_markers() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let markers = VerifiedMarkers::new(dir.path());
+        let hour = Duration::from_secs(3600);
+
+        assert_that!(
+            markers.is_fresh("foo", "1.0.0", "abc", hour, None)?,
+            eq(false)
+        );
+
+        markers.record("foo", "1.0.0", "abc")?;
+        assert_that!(
+            markers.is_fresh("foo", "1.0.0", "abc", hour, None)?,
+            eq(true)
+        );
+        assert_that!(
+            markers.is_fresh("foo", "1.0.0", "def", hour, None)?,
+            eq(false)
+        );
+        assert_that!(
+            markers.is_fresh("foo", "1.0.1", "abc", hour, None)?,
+            eq(false)
+        );
+
+        // A corrupt marker just means the version is verified again.
+        std::fs::write(markers.marker_path("foo", "1.0.1"), "{\"verified_at\":")?;
+        assert_that!(
+            markers.is_fresh("foo", "1.0.1", "abc", hour, None)?,
+            eq(false)
+        );
+
+        // A manifest written after the marker invalidates it. Markers only have a resolution of
+        // a second, so backdate the marker rather than sleeping.
+        let marker = Marker {
+            verified_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() - 60,
+            algorithm: ALGORITHM.to_string(),
+            cksum: "abc".to_string(),
+        };
+        std::fs::write(
+            markers.marker_path("foo", "1.0.0"),
+            serde_json::to_vec(&marker)?,
+        )?;
+        let manifest = dir.path().join("Cargo.toml");
+        std::fs::write(&manifest, "")?;
+        assert_that!(
+            markers.is_fresh("foo", "1.0.0", "abc", hour, Some(&manifest))?,
+            eq(false)
+        );
+        assert_that!(
+            markers.is_fresh("foo", "1.0.0", "abc", Duration::from_secs(30), None)?,
+            eq(false)
+        );
+        assert_that!(
+            markers.is_fresh("foo", "1.0.0", "abc", hour, None)?,
+            eq(true)
+        );
+
+        Ok(())
+    }
+}
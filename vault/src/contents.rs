@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+use crate::Error;
+
+/// The name of the contents index file, relative to the vault root.
+pub(crate) const FILE_NAME: &str = ".librarian-contents";
+
+/// An on-disk record of which crate versions a vault contains, so that membership can be checked
+/// without touching the filesystem.
+///
+/// The file is an append-only log of tab separated lines: `+ name version path` when a version is
+/// added, and `- name version` when one is removed. Paths are relative to the vault root. The
+/// log is replayed into memory the first time it's needed, and compacted by
+/// [`Vault::reindex`](crate::Vault::reindex).
+#[derive(Debug)]
+pub(crate) struct ContentsIndex {
+    path: PathBuf,
+    /// Keyed by the lowercased crate name, since crate names are case insensitive.
+    entries: RwLock<Option<HashMap<(String, String), PathBuf>>>,
+    /// Serialises appends, so that lines from concurrent populates aren't interleaved.
+    append: Mutex<()>,
+}
+
+impl ContentsIndex {
+    pub(crate) fn new(root: &Path) -> Self {
+        Self {
+            path: root.join(FILE_NAME),
+            entries: RwLock::new(None),
+            append: Mutex::new(()),
+        }
+    }
+
+    pub(crate) fn get(&self, crate_name: &str, version: &str) -> Result<Option<PathBuf>, Error> {
+        let key = key(crate_name, version);
+        if let Some(entries) = &*self.entries.read().expect("contents lock") {
+            return Ok(entries.get(&key).cloned());
+        }
+
+        let mut entries = self.entries.write().expect("contents lock");
+        if entries.is_none() {
+            *entries = Some(self.load()?);
+        }
+        Ok(entries
+            .as_ref()
+            .and_then(|entries| entries.get(&key).cloned()))
+    }
+
+    pub(crate) fn insert(&self, crate_name: &str, version: &str, path: &Path) -> Result<(), Error> {
+        let Some(relative) = path.to_str() else {
+            tracing::warn!(?path, "not recording non-UTF-8 path in contents index");
+            return Ok(());
+        };
+        self.append(&format!("+\t{crate_name}\t{version}\t{relative}\n"))?;
+
+        if let Some(entries) = &mut *self.entries.write().expect("contents lock") {
+            entries.insert(key(crate_name, version), path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, crate_name: &str, version: &str) -> Result<(), Error> {
+        self.append(&format!("-\t{crate_name}\t{version}\n"))?;
+
+        if let Some(entries) = &mut *self.entries.write().expect("contents lock") {
+            entries.remove(&key(crate_name, version));
+        }
+        Ok(())
+    }
+
+    /// Replaces the index with the given entries.
+    pub(crate) fn replace(&self, entries: HashMap<(String, String), PathBuf>) -> Result<(), Error> {
+        let _guard = self.append.lock().expect("contents append lock");
+
+        let temp = self.path.with_extension("tmp");
+        let write = || -> std::io::Result<()> {
+            let mut file = BufWriter::new(File::create(&temp)?);
+            for ((crate_name, version), path) in &entries {
+                if let Some(path) = path.to_str() {
+                    writeln!(file, "+\t{crate_name}\t{version}\t{path}")?;
+                }
+            }
+            file.into_inner()?.sync_all()?;
+            std::fs::rename(&temp, &self.path)
+        };
+        write().map_err(|e| Error::Io(self.path.clone(), e))?;
+
+        *self.entries.write().expect("contents lock") = Some(entries);
+        Ok(())
+    }
+
+    fn append(&self, line: &str) -> Result<(), Error> {
+        let _guard = self.append.lock().expect("contents append lock");
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| Error::Io(self.path.clone(), e))
+    }
+
+    fn load(&self) -> Result<HashMap<(String, String), PathBuf>, Error> {
+        let mut entries = HashMap::new();
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(Error::Io(self.path.clone(), e)),
+        };
+
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| Error::Io(self.path.clone(), e))?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["+", crate_name, version, path] => {
+                    entries.insert(key(crate_name, version), PathBuf::from(path));
+                }
+                ["-", crate_name, version] => {
+                    entries.remove(&key(crate_name, version));
+                }
+                // A line may have been cut short by a crash.
+                _ => tracing::warn!(?line, "ignoring malformed line in contents index"),
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+pub(crate) fn key(crate_name: &str, version: &str) -> (String, String) {
+    (crate_name.to_ascii_lowercase(), version.to_string())
+}
@@ -0,0 +1,216 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
+
+use rayon::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{CrateVersion, Error, Vault};
+
+/// The differences between two vaults, as returned by [`Vault::diff`] and
+/// [`Vault::diff_contents`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct VaultDiff {
+    /// Crate versions that are only in the vault `diff` was called on.
+    pub only_in_self: Vec<VersionId>,
+
+    /// Crate versions that are only in the other vault.
+    pub only_in_other: Vec<VersionId>,
+
+    /// Crate versions in both vaults whose files differ. Only populated by
+    /// [`Vault::diff_contents`].
+    pub changed: Vec<VersionChange>,
+}
+
+impl VaultDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct VersionId {
+    pub crate_name: String,
+    pub version: String,
+}
+
+/// A crate version whose files differ between two vaults.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionChange {
+    #[serde(flatten)]
+    pub id: VersionId,
+    pub files: Vec<FileChange>,
+}
+
+/// A file that differs between two copies of a crate version, relative to the crate version root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", content = "path", rename_all = "snake_case")]
+pub enum FileChange {
+    OnlyInSelf(PathBuf),
+    OnlyInOther(PathBuf),
+    Modified(PathBuf),
+}
+
+impl Vault {
+    /// Compares the crate versions in this vault with those in `other`, without looking at their
+    /// contents.
+    pub fn diff(&self, other: &Vault) -> Result<VaultDiff, Error> {
+        self.diff_inner(other, false)
+    }
+
+    /// Like [`Vault::diff`], but also compares the SHA-256 checksum of each file of the crate
+    /// versions that are in both vaults.
+    pub fn diff_contents(&self, other: &Vault) -> Result<VaultDiff, Error> {
+        self.diff_inner(other, true)
+    }
+
+    fn diff_inner(&self, other: &Vault, compare_contents: bool) -> Result<VaultDiff, Error> {
+        let ours = self.versions_by_id()?;
+        let theirs = other.versions_by_id()?;
+
+        let mut diff = VaultDiff {
+            only_in_self: ours
+                .keys()
+                .filter(|id| !theirs.contains_key(*id))
+                .cloned()
+                .collect(),
+            only_in_other: theirs
+                .keys()
+                .filter(|id| !ours.contains_key(*id))
+                .cloned()
+                .collect(),
+            changed: Vec::new(),
+        };
+
+        if compare_contents {
+            let common: Vec<_> = ours
+                .iter()
+                .filter_map(|(id, ours)| Some((id, ours, theirs.get(id)?)))
+                .collect();
+            diff.changed = common
+                .into_par_iter()
+                .map(|(id, ours, theirs)| {
+                    let files =
+                        diff_files(&self.file_checksums(ours)?, &other.file_checksums(theirs)?);
+                    Ok((!files.is_empty()).then(|| VersionChange {
+                        id: id.clone(),
+                        files,
+                    }))
+                })
+                .filter_map(Result::transpose)
+                .collect::<Result<_, Error>>()?;
+        }
+
+        Ok(diff)
+    }
+
+    fn versions_by_id(&self) -> Result<BTreeMap<VersionId, CrateVersion>, Error> {
+        self.iter_crate_versions()
+            .map(|result| {
+                result.map(|version| {
+                    let id = VersionId {
+                        crate_name: version.crate_name.clone(),
+                        version: version.version.clone(),
+                    };
+                    (id, version)
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the SHA-256 checksum of each file in a crate version, keyed by its path relative to
+    /// the crate version root.
+    fn file_checksums(&self, version: &CrateVersion) -> Result<BTreeMap<PathBuf, String>, Error> {
+        let root = version.root()?;
+        self.walk_files(version)
+            .map(|result| {
+                let path = result?;
+                let mut hasher = Sha256::new();
+                self.storage()
+                    .open(&path)
+                    .and_then(|mut reader| std::io::copy(&mut reader, &mut hasher))
+                    .map_err(|e| Error::Io(path.clone(), e))?;
+
+                let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+                Ok((relative, format!("{:x}", hasher.finalize())))
+            })
+            .collect()
+    }
+}
+
+fn diff_files(
+    ours: &BTreeMap<PathBuf, String>,
+    theirs: &BTreeMap<PathBuf, String>,
+) -> Vec<FileChange> {
+    let paths: BTreeSet<&PathBuf> = ours.keys().chain(theirs.keys()).collect();
+    paths
+        .into_iter()
+        .filter_map(|path| match (ours.get(path), theirs.get(path)) {
+            (Some(_), None) => Some(FileChange::OnlyInSelf(path.clone())),
+            (None, Some(_)) => Some(FileChange::OnlyInOther(path.clone())),
+            (Some(a), Some(b)) if a != b => Some(FileChange::Modified(path.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_diff() -> anyhow::Result<()> {
+        let a = tempfile::tempdir()?;
+        let b = tempfile::tempdir()?;
+        let ours = Vault::new(a.path().to_path_buf());
+        let theirs = Vault::new(b.path().to_path_buf());
+
+        for (vault, versions) in [
+            (&ours, ["1.0.0", "1.0.1"].as_slice()),
+            (&theirs, ["1.0.1", "1.0.2"].as_slice()),
+        ] {
+            for version in versions {
+                let path = vault.crate_version_path(&"foo".parse()?, &version.parse()?)?;
+                std::fs::create_dir_all(path.join("src"))?;
+                std::fs::write(
+                    path.join("Cargo.toml"),
+                    format!("[package]\nname = \"foo\"\nversion = \"{version}\"\n"),
+                )?;
+                std::fs::write(path.join("src/lib.rs"), "fn foo() {}\n")?;
+            }
+        }
+
+        let id = |version: &str| VersionId {
+            crate_name: "foo".to_string(),
+            version: version.to_string(),
+        };
+        let expected = VaultDiff {
+            only_in_self: vec![id("1.0.0")],
+            only_in_other: vec![id("1.0.2")],
+            changed: Vec::new(),
+        };
+        assert_that!(ours.diff(&theirs)?, eq(expected.clone()));
+        assert_that!(ours.diff_contents(&theirs)?, eq(expected.clone()));
+
+        let path = theirs.crate_version_path(&"foo".parse()?, &"1.0.1".parse()?)?;
+        std::fs::write(path.join("src/lib.rs"), "fn bar() {}\n")?;
+        std::fs::write(path.join("README.md"), "")?;
+        assert_that!(ours.diff(&theirs)?, eq(expected.clone()));
+        assert_that!(
+            ours.diff_contents(&theirs)?.changed,
+            eq(vec![VersionChange {
+                id: id("1.0.1"),
+                files: vec![
+                    FileChange::OnlyInOther(PathBuf::from("README.md")),
+                    FileChange::Modified(PathBuf::from("src/lib.rs")),
+                ],
+            }])
+        );
+
+        Ok(())
+    }
+}
@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use thiserror::Error;
 
+use crate::manifest::ManifestIssue;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("invalid crate name: {0:?}")]
@@ -10,18 +12,64 @@ pub enum Error {
     #[error("invalid crate version: {0:?}")]
     InvalidCrateVersion(String),
 
+    #[error("io error at {0:?}: {1:?}")]
+    Io(PathBuf, #[source] std::io::Error),
+
     #[error("manifest does not have a parent: {0:?}")]
     ManifestAncestry(PathBuf),
 
+    #[error("manifest does not match its location: expected {expected}, found {found}")]
+    ManifestMismatch { expected: String, found: String },
+
     #[error("opening manifest at {0:?}: {1:?}")]
     ManifestOpen(PathBuf, #[source] std::io::Error),
 
-    #[error("parsing manifest at {0:?}: {1:?}")]
-    ManifestParse(PathBuf, #[source] toml::de::Error),
+    #[error("parsing manifest at {0:?} ({1}): {2:?}")]
+    ManifestParse(PathBuf, ManifestIssue, #[source] Box<toml::de::Error>),
+
+    #[error("parsing manifest ({0}): {1:?}")]
+    ManifestParseStr(ManifestIssue, #[source] Box<toml::de::Error>),
 
     #[error("reading manifest at {0:?}: {1:?}")]
     ManifestRead(PathBuf, #[source] std::io::Error),
 
+    #[error("reading manifest: {0:?}")]
+    ManifestReadStream(#[source] std::io::Error),
+
+    #[error("manifest at {0:?} is too large: {1} bytes")]
+    ManifestTooLarge(PathBuf, u64),
+
+    #[error("manifest is too large: at least {0} bytes")]
+    ManifestTooLargeStream(u64),
+
+    #[error("manifest at {0:?} is not valid UTF-8: {1:?}")]
+    ManifestUtf8(PathBuf, #[source] std::str::Utf8Error),
+
+    #[error("not a directory: {0:?}")]
+    NotADirectory(PathBuf),
+
+    #[error("directory doesn't look like a vault: {0:?}")]
+    NotAVault(PathBuf),
+
+    #[error("building thread pool: {0:?}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+
+    #[error("parsing VCS info at {0:?}: {1:?}")]
+    VcsInfo(PathBuf, #[source] serde_json::Error),
+
+    #[error("crate version already exists: {0:?}")]
+    VersionExists(PathBuf),
+
     #[error("walking vault directories: {0:?}")]
     WalkDir(#[from] walkdir::Error),
 }
+
+impl Error {
+    /// Returns the category of manifest parse failure, if this is a manifest parse error.
+    pub fn manifest_issue(&self) -> Option<ManifestIssue> {
+        match self {
+            Self::ManifestParse(_, issue, _) | Self::ManifestParseStr(issue, _) => Some(*issue),
+            _ => None,
+        }
+    }
+}
@@ -1,42 +1,421 @@
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::OsStr,
+    io::ErrorKind,
     ops::Deref,
-    path::{Path, PathBuf},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
 };
 
+use contents::ContentsIndex;
+use rayon::prelude::*;
+use serde::Serialize;
+
+mod contents;
+mod diff;
 mod error;
 mod manifest;
+mod names;
+mod storage;
+mod vcs_info;
 mod walk;
 
+pub use diff::{FileChange, VaultDiff, VersionChange, VersionId};
 pub use error::Error;
-use manifest::Manifest;
+pub use manifest::{Manifest, ManifestIssue, DEFAULT_MAX_MANIFEST_SIZE};
+pub use names::{CrateName, CrateVersionNum};
+pub use storage::{LocalStorage, Storage};
+pub use vcs_info::{GitInfo, VcsInfo};
+
+/// The directory that content addressed crate versions are stored in, relative to the vault root.
+const OBJECTS_DIR: &str = ".objects";
+
+/// How crate versions are stored within a vault.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Each crate version is a directory at [`Vault::crate_version_path`].
+    #[default]
+    ByVersion,
+
+    /// Each crate version is stored in a directory named by the SHA-256 checksum of its `.crate`
+    /// file, at [`Vault::object_path`], with a symlink to it at [`Vault::crate_version_path`].
+    ///
+    /// Versions with identical contents share a single directory.
+    ContentAddressed,
+
+    /// Each crate version is stored as its original, unextracted `.crate` file at
+    /// [`Vault::archive_path`].
+    ///
+    /// Since there are no manifests on disk, archived versions are found with
+    /// [`Vault::iter_archives`] rather than [`Vault::iter_crate_versions`].
+    ArchiveOnly,
+}
 
 #[derive(Debug)]
-pub struct Vault(PathBuf);
+pub struct Vault {
+    path: PathBuf,
+    layout: Layout,
+    max_manifest_size: u64,
+    storage: Arc<dyn Storage>,
+    contents: Option<ContentsIndex>,
+}
 
 impl Vault {
     pub fn new<T>(path: T) -> Self
     where
         T: ToOwned<Owned = PathBuf>,
     {
-        Self(path.to_owned())
+        Self {
+            path: path.to_owned(),
+            layout: Layout::default(),
+            max_manifest_size: DEFAULT_MAX_MANIFEST_SIZE,
+            storage: Arc::new(LocalStorage),
+            contents: None,
+        }
+    }
+
+    /// Opens an existing vault, failing if `path` isn't a directory, or if it's a non-empty
+    /// directory without any of the bucket directories that crate versions are stored in.
+    ///
+    /// The layout is detected from the contents of the directory.
+    pub fn open<T>(path: T) -> Result<Self, Error>
+    where
+        T: ToOwned<Owned = PathBuf>,
+    {
+        let path = path.to_owned();
+        let metadata = std::fs::metadata(&path).map_err(|e| Error::Io(path.clone(), e))?;
+        if !metadata.is_dir() {
+            return Err(Error::NotADirectory(path));
+        }
+
+        let mut empty = true;
+        let mut has_bucket = false;
+        for entry in std::fs::read_dir(&path).map_err(|e| Error::Io(path.clone(), e))? {
+            let entry = entry.map_err(|e| Error::Io(path.clone(), e))?;
+            let name = entry.file_name();
+            if name.as_encoded_bytes().starts_with(b".") && name != OBJECTS_DIR {
+                continue;
+            }
+
+            empty = false;
+            if entry.path().is_dir()
+                && (name == OBJECTS_DIR || name.to_str().is_some_and(|s| s.chars().count() == 1))
+            {
+                has_bucket = true;
+                break;
+            }
+        }
+        if !empty && !has_bucket {
+            return Err(Error::NotAVault(path));
+        }
+
+        let vault = Self::new(path);
+        Ok(if vault.objects_path().is_dir() {
+            vault.with_layout(Layout::ContentAddressed)
+        } else {
+            vault
+        })
+    }
+
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Sets the size, in bytes, above which manifests are rejected with
+    /// [`Error::ManifestTooLarge`] rather than parsed.
+    ///
+    /// This bounds the memory used by a walk of a vault populated from an untrusted mirror.
+    pub fn with_max_manifest_size(mut self, limit: u64) -> Self {
+        self.max_manifest_size = limit;
+        self
+    }
+
+    /// Returns the [`Storage`] that some of the vault's files are accessed through.
+    ///
+    /// This is always [`LocalStorage`] for now: walks, extraction and the contents index still go
+    /// straight to the local filesystem, so there's no way to swap in another backend yet.
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
+
+    /// Keeps an index of the crate versions in the vault in `.librarian-contents`, so that
+    /// [`Vault::contains`] and [`Vault::lookup`] don't need to touch the filesystem.
+    ///
+    /// The index is only as accurate as whatever is writing to the vault: [`Vault::record`] and
+    /// [`Vault::forget`] need to be called as versions are added and removed, which `Corpus` does.
+    /// If it drifts, [`Vault::reindex`] rebuilds it.
+    pub fn with_contents_index(mut self) -> Self {
+        self.contents = Some(ContentsIndex::new(&self.path));
+        self
+    }
+
+    /// Returns true if the vault contains the given crate version.
+    ///
+    /// With a contents index, this is a hash lookup once the index has been loaded. Otherwise,
+    /// the version's directory (or archive, with [`Layout::ArchiveOnly`]) is looked for on disk.
+    pub fn contains(&self, crate_name: &str, version: &str) -> Result<bool, Error> {
+        Ok(self.lookup(crate_name, version)?.is_some())
+    }
+
+    /// Returns the path that the given crate version is stored at, if the vault contains it.
+    pub fn lookup(&self, crate_name: &str, version: &str) -> Result<Option<PathBuf>, Error> {
+        if let Some(contents) = &self.contents {
+            return Ok(contents
+                .get(crate_name, version)?
+                .map(|relative| self.path.join(relative)));
+        }
+
+        let path = match self.layout {
+            Layout::ArchiveOnly => self.archive_path(crate_name, version)?,
+            Layout::ByVersion | Layout::ContentAddressed => {
+                self.canonical_version_path_str(crate_name, version)?
+            }
+        };
+        let exists = match self.layout {
+            Layout::ArchiveOnly => path.is_file(),
+            Layout::ByVersion | Layout::ContentAddressed => path.is_dir(),
+        };
+        Ok(exists.then_some(path))
+    }
+
+    /// Records in the contents index that a crate version has been stored at `path`. Does
+    /// nothing if the vault doesn't have a contents index.
+    pub fn record(&self, crate_name: &str, version: &str, path: &Path) -> Result<(), Error> {
+        match &self.contents {
+            Some(contents) => contents.insert(
+                crate_name,
+                version,
+                path.strip_prefix(&self.path).unwrap_or(path),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Records in the contents index that a crate version has been removed. Does nothing if the
+    /// vault doesn't have a contents index.
+    pub fn forget(&self, crate_name: &str, version: &str) -> Result<(), Error> {
+        match &self.contents {
+            Some(contents) => contents.remove(crate_name, version),
+            None => Ok(()),
+        }
+    }
+
+    /// Rebuilds the contents index from a full walk of the vault, returning the number of crate
+    /// versions found. Does nothing if the vault doesn't have a contents index.
+    #[tracing::instrument(err)]
+    pub fn reindex(&self) -> Result<usize, Error> {
+        let Some(contents) = &self.contents else {
+            return Ok(0);
+        };
+
+        let mut entries = HashMap::new();
+        let mut insert = |crate_name: &str, version: &str, path: &Path| {
+            entries.insert(
+                contents::key(crate_name, version),
+                path.strip_prefix(&self.path).unwrap_or(path).to_path_buf(),
+            );
+        };
+        if self.layout == Layout::ArchiveOnly {
+            for archive in self.iter_archives() {
+                let archive = archive?;
+                insert(&archive.crate_name, &archive.version, &archive.path);
+            }
+        } else {
+            for version in self.iter_crate_versions() {
+                let version = version?;
+                insert(&version.crate_name, &version.version, version.root()?);
+            }
+        }
+
+        let count = entries.len();
+        contents.replace(entries)?;
+        Ok(count)
+    }
+
+    /// Parses the manifest at `path`, failing with [`Error::ManifestTooLarge`] if it's larger than
+    /// the limit set with [`Vault::with_max_manifest_size`].
+    pub fn parse_manifest(&self, path: &Path) -> Result<Manifest, Error> {
+        Manifest::parse_file_with_limit(path, self.max_manifest_size)
+    }
+
+    fn crate_version(&self, path: PathBuf) -> Result<CrateVersion, Error> {
+        let manifest = self.parse_manifest(&path)?;
+        Ok(CrateVersion::from_manifest(path, &manifest))
+    }
+
+    /// Returns the directory that a [`Layout::ContentAddressed`] vault stores crate versions in.
+    pub fn objects_path(&self) -> PathBuf {
+        self.path.join(OBJECTS_DIR)
+    }
+
+    /// Returns the path that a crate version with the given `.crate` checksum is stored at in a
+    /// [`Layout::ContentAddressed`] vault.
+    pub fn object_path(&self, cksum: &str) -> PathBuf {
+        self.objects_path().join(cksum)
+    }
+
+    /// Returns the `.crate` checksum that a crate version is stored under, if it was populated
+    /// into a [`Layout::ContentAddressed`] vault.
+    pub fn recorded_checksum(
+        &self,
+        crate_name: &str,
+        version: &str,
+    ) -> Result<Option<String>, Error> {
+        let path = self.canonical_version_path_str(crate_name, version)?;
+        let target = match std::fs::read_link(&path) {
+            Ok(target) => target,
+            // Either the version isn't there, or it's a real directory.
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::InvalidInput) => {
+                return Ok(None);
+            }
+            Err(e) => return Err(Error::Io(path, e)),
+        };
+
+        Ok(
+            match (
+                target.parent().and_then(Path::file_name),
+                target.file_name(),
+            ) {
+                (Some(dir), Some(cksum)) if dir == OBJECTS_DIR => cksum.to_str().map(String::from),
+                _ => None,
+            },
+        )
     }
 
     pub fn iter_crate_versions(&self) -> impl Iterator<Item = Result<CrateVersion, Error>> + '_ {
-        walk::top_level_manifests(&self.0).map(|result| {
-            result.and_then(|path| -> Result<CrateVersion, Error> {
-                let manifest = Manifest::parse_file(&path)?;
-
-                Ok(CrateVersion {
-                    crate_name: manifest.crate_name().to_string(),
-                    version: manifest.crate_version().to_string(),
-                    path,
-                })
+        self.top_level_manifests(&self.path)
+            .map(|result| result.and_then(|path| self.crate_version(path)))
+    }
+
+    /// Like [`Vault::iter_crate_versions`], but manifests that can't be read or parsed are yielded
+    /// as [`WalkedVersion::ManifestError`] rather than as errors.
+    ///
+    /// This lets callers that stop at the first error, such as `collect::<Result<_, _>>()`, skip
+    /// bad manifests while still reporting them. Errors walking the vault itself are still
+    /// returned as errors.
+    pub fn iter_crate_versions_lenient(
+        &self,
+    ) -> impl Iterator<Item = Result<WalkedVersion, Error>> + '_ {
+        self.top_level_manifests(&self.path).map(|result| {
+            let path = result?;
+            Ok(match self.crate_version(path.clone()) {
+                Ok(version) => WalkedVersion::Version(version),
+                Err(error) => WalkedVersion::ManifestError { path, error },
             })
         })
     }
 
-    pub fn crate_version_path(&self, crate_name: &str, version: &str) -> Result<PathBuf, Error> {
-        let mut path = self.0.join(
+    fn top_level_manifests(&self, path: &Path) -> impl Iterator<Item = Result<PathBuf, Error>> {
+        walk::top_level_manifests(path, self.layout == Layout::ContentAddressed)
+    }
+
+    /// Iterates over the `.crate` files stored in a [`Layout::ArchiveOnly`] vault.
+    ///
+    /// Any other files in the vault are ignored.
+    pub fn iter_archives(&self) -> impl Iterator<Item = Result<CrateArchive, Error>> {
+        walk::archives(&self.path).filter_map(|result| match result {
+            Ok(path) => CrateArchive::from_path(path).map(Ok),
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    /// Like [`Vault::iter_crate_versions`], but parses manifests in parallel on a dedicated pool
+    /// of `num_threads` threads, returning the results in the same order.
+    ///
+    /// The directory walk itself is serial, so this only helps once the walk is no longer the
+    /// bottleneck. On spinning disks, where parsing mostly waits on seeks, more than 2-4 threads
+    /// rarely helps; on SSDs, one thread per core is a reasonable starting point. Passing 0 uses
+    /// rayon's default of one thread per core.
+    pub fn par_crate_versions(
+        &self,
+        num_threads: usize,
+    ) -> Result<Vec<Result<CrateVersion, Error>>, Error> {
+        let manifests: Vec<_> = self.top_level_manifests(&self.path).collect();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()?;
+
+        Ok(pool.install(|| {
+            manifests
+                .into_par_iter()
+                .map(|result| result.and_then(|path| self.crate_version(path)))
+                .collect()
+        }))
+    }
+
+    /// Like [`Vault::iter_crate_versions`], but only walks the directories of the named crates,
+    /// rather than the whole vault.
+    ///
+    /// Crates that aren't in the vault are ignored.
+    pub fn iter_versions_of<'a>(
+        &'a self,
+        names: &'a HashSet<String>,
+    ) -> impl Iterator<Item = Result<CrateVersion, Error>> + 'a {
+        names
+            .iter()
+            .flat_map(|name| -> Box<dyn Iterator<Item = Result<PathBuf, Error>>> {
+                match self.canonical_crate_path(name) {
+                    Ok(path) if path.is_dir() => Box::new(self.top_level_manifests(&path)),
+                    Ok(_) => Box::new(std::iter::empty()),
+                    Err(e) => Box::new(std::iter::once(Err(e))),
+                }
+            })
+            .map(|result| result.and_then(|path| self.crate_version(path)))
+    }
+
+    /// Iterates over the regular files of a crate version, in a consistent order.
+    ///
+    /// Symlinks within the crate version are skipped rather than followed.
+    pub fn walk_files(
+        &self,
+        version: &CrateVersion,
+    ) -> impl Iterator<Item = Result<PathBuf, Error>> {
+        match version.root() {
+            Ok(root) => Box::new(walk::version_files(root)) as Box<dyn Iterator<Item = _> + Send>,
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    /// Like [`Vault::walk_files`], but walks several crate versions in parallel.
+    pub fn par_walk_files<'a>(
+        &'a self,
+        versions: &'a [CrateVersion],
+    ) -> impl ParallelIterator<Item = Result<PathBuf, Error>> + 'a {
+        versions
+            .par_iter()
+            .flat_map_iter(|version| self.walk_files(version))
+    }
+
+    /// Returns the directory that the given crate version is stored in.
+    pub fn crate_version_path(
+        &self,
+        crate_name: &CrateName,
+        version: &CrateVersionNum,
+    ) -> Result<PathBuf, Error> {
+        Ok(self.crate_path(crate_name)?.join(version.as_str()))
+    }
+
+    /// Returns the directory that all versions of the given crate are stored in.
+    pub fn crate_path(&self, crate_name: &CrateName) -> Result<PathBuf, Error> {
+        self.crate_path_str(crate_name)
+    }
+
+    /// Like [`Vault::crate_path`], for names that haven't been validated as a [`CrateName`].
+    ///
+    /// Names that are empty or contain path separators or `..` are rejected, since they would
+    /// otherwise build a path outside the crate's bucket.
+    fn crate_path_str(&self, crate_name: &str) -> Result<PathBuf, Error> {
+        if !is_path_component(crate_name) {
+            return Err(Error::InvalidCrateName(crate_name.to_string()));
+        }
+
+        let mut path = self.path.join(
             crate_name
                 .get(0..1)
                 .ok_or_else(|| Error::InvalidCrateName(crate_name.to_string()))?,
@@ -46,19 +425,370 @@ impl Vault {
             path = path.join(two);
         }
 
-        path = path.join(crate_name);
+        Ok(path.join(crate_name))
+    }
+
+    /// Like [`Vault::crate_version_path`], but if a directory for the crate already exists with a
+    /// different case (eg `Serde` instead of `serde`), that directory is used instead.
+    ///
+    /// Crate names on crates.io are case insensitive, so this avoids populating duplicate
+    /// directories for the same crate. The version itself is always matched exactly.
+    pub fn canonical_version_path(
+        &self,
+        crate_name: &CrateName,
+        version: &CrateVersionNum,
+    ) -> Result<PathBuf, Error> {
+        Ok(self
+            .canonical_crate_path(crate_name)?
+            .join(version.as_str()))
+    }
+
+    /// Like [`Vault::canonical_version_path`], for names and versions that haven't been
+    /// validated.
+    fn canonical_version_path_str(
+        &self,
+        crate_name: &str,
+        version: &str,
+    ) -> Result<PathBuf, Error> {
+        let version = CrateVersionNum::new(version)?;
+
+        Ok(self
+            .canonical_crate_path(crate_name)?
+            .join(version.as_str()))
+    }
+
+    /// Like [`Vault::crate_path`], but resolves the crate directory case insensitively in the
+    /// same way as [`Vault::canonical_version_path`].
+    pub fn canonical_crate_path(&self, crate_name: &str) -> Result<PathBuf, Error> {
+        let crate_path = self.crate_path_str(crate_name)?;
+        let components: Vec<&OsStr> = crate_path
+            .strip_prefix(&self.path)
+            .unwrap_or(&crate_path)
+            .iter()
+            .collect();
+
+        Ok(resolve_case_insensitive(&self.path, &components)?.unwrap_or(crate_path))
+    }
 
-        if version.is_empty() {
-            Err(Error::InvalidCrateVersion(version.to_string()))
+    /// Returns the path to the `.crate` file of a crate version in a [`Layout::ArchiveOnly`]
+    /// vault, within the directory at [`Vault::canonical_version_path`].
+    pub fn archive_path(&self, crate_name: &str, version: &str) -> Result<PathBuf, Error> {
+        Ok(self
+            .canonical_version_path_str(crate_name, version)?
+            .join(format!("{crate_name}-{version}.crate")))
+    }
+
+    /// Returns the path to the top-level manifest of a crate version.
+    ///
+    /// `Cargo.toml` is preferred, but some old crates were published with a `cargo.toml` instead.
+    /// If neither exists, the `Cargo.toml` path is returned.
+    pub fn version_manifest_path(&self, crate_name: &str, version: &str) -> Result<PathBuf, Error> {
+        let path = self.canonical_version_path_str(crate_name, version)?;
+        let manifest = path.join("Cargo.toml");
+        if manifest.is_file() {
+            return Ok(manifest);
+        }
+
+        let lower_case = path.join("cargo.toml");
+        Ok(if lower_case.is_file() {
+            lower_case
         } else {
-            Ok(path.join(version))
+            manifest
+        })
+    }
+
+    /// Checks that the manifest of a crate version declares the name and version that it is
+    /// stored under, which may not be the case if the wrong tarball was served.
+    pub fn verify_manifest_matches(&self, crate_name: &str, version: &str) -> Result<(), Error> {
+        let manifest = self.parse_manifest(&self.version_manifest_path(crate_name, version)?)?;
+        if manifest.crate_name() != crate_name || manifest.crate_version() != version {
+            return Err(Error::ManifestMismatch {
+                expected: format!("{crate_name}@{version}"),
+                found: format!("{}@{}", manifest.crate_name(), manifest.crate_version()),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if a crate version has a build script, taking into account the `build` key in
+    /// its manifest.
+    pub fn has_build_script(&self, crate_name: &str, version: &str) -> Result<bool, Error> {
+        let manifest_path = self.version_manifest_path(crate_name, version)?;
+        let manifest = self.parse_manifest(&manifest_path)?;
+
+        Ok(match (manifest.build_script(), manifest_path.parent()) {
+            (Some(script), Some(root)) => root.join(script).is_file(),
+            _ => false,
+        })
+    }
+
+    /// Returns the upstream version control information that `cargo package` recorded in a crate
+    /// version's `.cargo_vcs_info.json`, or `None` if it wasn't published with one.
+    pub fn vcs_info(&self, version: &CrateVersion) -> Result<Option<VcsInfo>, Error> {
+        VcsInfo::load(self.storage(), &version.root()?.join(vcs_info::FILE_NAME))
+    }
+
+    /// Finds crate directories within the vault whose names differ only by case.
+    ///
+    /// Only directories in the same bucket are compared: the same crate in different buckets is
+    /// reported by [`Vault::bucket_duplicates`] instead.
+    pub fn case_duplicates(&self) -> Result<Vec<CaseDuplicate>, Error> {
+        let mut crates: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+        for crate_path in self.crate_dirs()? {
+            let relative = crate_path.strip_prefix(&self.path).unwrap_or(&crate_path);
+            let Some(key) = relative.to_str().map(str::to_ascii_lowercase) else {
+                continue;
+            };
+            crates
+                .entry(PathBuf::from(key))
+                .or_default()
+                .push(crate_path);
         }
+
+        Ok(crates
+            .into_iter()
+            .filter(|(_key, paths)| paths.len() > 1)
+            .filter_map(|(key, paths)| {
+                let name = key.file_name()?.to_str()?.to_string();
+                Some(CaseDuplicate { name, paths })
+            })
+            .collect())
     }
+
+    /// Finds crates that have directories in more than one bucket, such as `s/se/serde` and
+    /// `se/rd/serde`.
+    ///
+    /// This can only happen if the bucket layout used to populate the vault changed, so any
+    /// results mean that some versions of the crate won't be found at [`Vault::crate_path`].
+    /// Bucket names are compared case insensitively.
+    pub fn bucket_duplicates(&self) -> Result<Vec<BucketDuplicate>, Error> {
+        let mut crates: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for crate_path in self.crate_dirs()? {
+            let Some(name) = crate_path.file_name().and_then(OsStr::to_str) else {
+                continue;
+            };
+            crates
+                .entry(name.to_ascii_lowercase())
+                .or_default()
+                .push(crate_path);
+        }
+
+        crates
+            .into_iter()
+            .filter(|(_name, paths)| {
+                let buckets: HashSet<String> = paths
+                    .iter()
+                    .filter_map(|path| path.parent()?.to_str().map(str::to_ascii_lowercase))
+                    .collect();
+                buckets.len() > 1
+            })
+            .map(|(name, paths)| {
+                Ok(BucketDuplicate {
+                    expected: self.crate_path_str(&name)?,
+                    name,
+                    paths,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the distinct crate directories in the vault, in walk order.
+    fn crate_dirs(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut seen = HashSet::new();
+        for result in self.top_level_manifests(&self.path) {
+            let manifest = result?;
+            let Some(crate_path) = manifest.parent().and_then(Path::parent) else {
+                continue;
+            };
+            if seen.insert(crate_path.to_path_buf()) {
+                dirs.push(crate_path.to_path_buf());
+            }
+        }
+
+        Ok(dirs)
+    }
+
+    /// Merges the versions in a set of case duplicate crate directories into a single directory,
+    /// returning the path to that directory.
+    ///
+    /// The canonical name is taken from the manifests of the crate versions. Where the same
+    /// version exists in more than one directory, the copy already in the canonical directory is
+    /// kept and the others are removed.
+    pub fn merge_case_duplicates(&self, duplicate: &CaseDuplicate) -> Result<PathBuf, Error> {
+        let manifests: Vec<Manifest> = duplicate
+            .paths
+            .iter()
+            .flat_map(|path| self.top_level_manifests(path))
+            .map(|result| result.and_then(|path| self.parse_manifest(&path)))
+            .collect::<Result<_, _>>()?;
+
+        let crate_name = manifests
+            .iter()
+            .map(Manifest::crate_name)
+            .find(|name| name.eq_ignore_ascii_case(&duplicate.name))
+            .ok_or_else(|| Error::InvalidCrateName(duplicate.name.clone()))?;
+        let target = self.crate_path_str(crate_name)?;
+        std::fs::create_dir_all(&target).map_err(|e| Error::Io(target.clone(), e))?;
+
+        for path in duplicate.paths.iter().filter(|path| **path != target) {
+            for entry in std::fs::read_dir(path).map_err(|e| Error::Io(path.clone(), e))? {
+                let entry = entry.map_err(|e| Error::Io(path.clone(), e))?;
+                let dest = target.join(entry.file_name());
+
+                if dest.exists() {
+                    tracing::warn!(from = ?entry.path(), to = ?dest, "version already exists in canonical crate directory; removing duplicate");
+                    std::fs::remove_dir_all(entry.path())
+                        .map_err(|e| Error::Io(entry.path(), e))?;
+                } else {
+                    std::fs::rename(entry.path(), &dest).map_err(|e| Error::Io(dest, e))?;
+                }
+            }
+
+            std::fs::remove_dir(path).map_err(|e| Error::Io(path.clone(), e))?;
+        }
+
+        Ok(target)
+    }
+
+    /// Moves every version of the crate `old_name` to the directory for `new_name`, for crates
+    /// that have been renamed upstream.
+    ///
+    /// The two names may be in different buckets, or at different depths if one of them is a
+    /// single character, so relative symlinks into [`Vault::objects_path`] are rewritten and
+    /// [`Layout::ArchiveOnly`] archives are renamed to match. Manifests are left as they are, so
+    /// they will still name the crate `old_name`.
+    ///
+    /// Nothing is moved if any version of `old_name` already exists under `new_name`.
+    ///
+    /// Entries in the crate directory whose names start with a dot, such as a history repository,
+    /// aren't versions. They're moved along with the crate unless `new_name` already has an entry
+    /// of the same name, in which case they're left behind in the old crate directory.
+    #[tracing::instrument(err)]
+    pub fn rename_crate(&self, old_name: &str, new_name: &str) -> Result<(), Error> {
+        let source = self.canonical_crate_path(old_name)?;
+        let target = self.canonical_crate_path(new_name)?;
+        if !source.is_dir() {
+            return Err(Error::NotADirectory(source));
+        }
+        if source == target {
+            return Ok(());
+        }
+
+        let (others, entries): (Vec<_>, Vec<_>) = std::fs::read_dir(&source)
+            .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| Error::Io(source.clone(), e))?
+            .into_iter()
+            .partition(|entry| entry.file_name().as_bytes().starts_with(b"."));
+        if let Some(existing) = entries
+            .iter()
+            .map(|entry| target.join(entry.file_name()))
+            .find(|dest| std::fs::symlink_metadata(dest).is_ok())
+        {
+            return Err(Error::VersionExists(existing));
+        }
+
+        std::fs::create_dir_all(&target).map_err(|e| Error::Io(target.clone(), e))?;
+        // The depths of the version directories, rather than the crate directories.
+        let source_depth = self.depth(&source) + 1;
+        let target_depth = self.depth(&target) + 1;
+        for entry in entries {
+            let dest = target.join(entry.file_name());
+            let version = entry.file_name().to_string_lossy().into_owned();
+            let file_type = entry.file_type().map_err(|e| Error::Io(entry.path(), e))?;
+
+            match relink_target(
+                &entry.path(),
+                file_type.is_symlink(),
+                source_depth,
+                target_depth,
+            ) {
+                Some(link) => {
+                    std::os::unix::fs::symlink(&link, &dest)
+                        .map_err(|e| Error::Io(dest.clone(), e))?;
+                    std::fs::remove_file(entry.path()).map_err(|e| Error::Io(entry.path(), e))?;
+                }
+                None => {
+                    std::fs::rename(entry.path(), &dest).map_err(|e| Error::Io(dest.clone(), e))?;
+                }
+            }
+
+            if self.layout == Layout::ArchiveOnly {
+                let archive = dest.join(format!("{old_name}-{version}.crate"));
+                if archive.is_file() {
+                    let renamed = dest.join(format!("{new_name}-{version}.crate"));
+                    std::fs::rename(&archive, &renamed).map_err(|e| Error::Io(renamed, e))?;
+                }
+            }
+
+            self.forget(old_name, &version)?;
+            let recorded = match self.layout {
+                Layout::ArchiveOnly => self.archive_path(new_name, &version)?,
+                Layout::ByVersion | Layout::ContentAddressed => dest,
+            };
+            self.record(new_name, &version, &recorded)?;
+        }
+
+        let mut left_behind = false;
+        for entry in others {
+            let dest = target.join(entry.file_name());
+            if std::fs::symlink_metadata(&dest).is_ok() {
+                tracing::warn!(from = ?entry.path(), to = ?dest, "not replacing existing entry in renamed crate directory");
+                left_behind = true;
+            } else {
+                std::fs::rename(entry.path(), &dest).map_err(|e| Error::Io(dest, e))?;
+            }
+        }
+
+        if !left_behind {
+            std::fs::remove_dir(&source).map_err(|e| Error::Io(source.clone(), e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of path components between the vault root and `path`.
+    fn depth(&self, path: &Path) -> usize {
+        path.strip_prefix(&self.path)
+            .unwrap_or(path)
+            .components()
+            .count()
+    }
+}
+
+/// Returns the link that a relative symlink into the vault at `path`, `source_depth` components
+/// below the vault root, should be replaced with at `target_depth` components below it.
+///
+/// Returns `None` if `path` isn't a symlink that climbs exactly to the vault root, in which case
+/// it can be moved as is.
+fn relink_target(
+    path: &Path,
+    is_symlink: bool,
+    source_depth: usize,
+    target_depth: usize,
+) -> Option<PathBuf> {
+    if !is_symlink || source_depth == target_depth {
+        return None;
+    }
+
+    let link = std::fs::read_link(path).ok()?;
+    let climbs = link
+        .components()
+        .take_while(|component| *component == Component::ParentDir)
+        .count();
+    if link.is_absolute() || climbs != source_depth - 1 {
+        return None;
+    }
+
+    let mut target: PathBuf = std::iter::repeat_n("..", target_depth - 1).collect();
+    target.extend(link.components().skip(climbs));
+    Some(target)
 }
 
 impl AsRef<Path> for Vault {
     fn as_ref(&self) -> &Path {
-        &self.0
+        &self.path
     }
 }
 
@@ -66,13 +796,687 @@ impl Deref for Vault {
     type Target = Path;
 
     fn deref(&self) -> &Self::Target {
-        self.0.as_path()
+        self.path.as_path()
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CrateVersion {
     pub crate_name: String,
     pub version: String,
     pub path: PathBuf,
 }
+
+impl CrateVersion {
+    /// Returns the root directory of the crate version, which contains its manifest.
+    pub fn root(&self) -> Result<&Path, Error> {
+        self.path
+            .parent()
+            .ok_or_else(|| Error::ManifestAncestry(self.path.clone()))
+    }
+
+    fn from_manifest(path: PathBuf, manifest: &Manifest) -> Self {
+        Self {
+            crate_name: manifest.crate_name().to_string(),
+            version: manifest.crate_version().to_string(),
+            path,
+        }
+    }
+}
+
+/// A crate version found by [`Vault::iter_crate_versions_lenient`].
+#[derive(Debug)]
+pub enum WalkedVersion {
+    Version(CrateVersion),
+
+    /// The manifest at `path` couldn't be read or parsed.
+    ManifestError {
+        path: PathBuf,
+        error: Error,
+    },
+}
+
+/// A crate version stored as a `.crate` file in a [`Layout::ArchiveOnly`] vault.
+#[derive(Debug, Clone, Serialize)]
+pub struct CrateArchive {
+    pub crate_name: String,
+    pub version: String,
+    pub path: PathBuf,
+}
+
+impl CrateArchive {
+    /// Recovers the crate name and version from the directories that the archive is stored in,
+    /// returning `None` if the file name doesn't match them.
+    fn from_path(path: PathBuf) -> Option<Self> {
+        let version_dir = path.parent()?;
+        let version = version_dir.file_name()?.to_str()?;
+        let crate_name = version_dir.parent()?.file_name()?.to_str()?;
+        if path.file_name()? != OsStr::new(&format!("{crate_name}-{version}.crate")) {
+            return None;
+        }
+
+        Some(Self {
+            crate_name: crate_name.to_string(),
+            version: version.to_string(),
+            path: path.clone(),
+        })
+    }
+}
+
+/// A set of crate directories whose names differ only by case.
+#[derive(Debug, Clone)]
+pub struct CaseDuplicate {
+    /// The lowercased crate name.
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+}
+
+/// A crate with directories in more than one bucket.
+#[derive(Debug, Clone)]
+pub struct BucketDuplicate {
+    /// The lowercased crate name.
+    pub name: String,
+
+    /// Where the crate's directory should be, according to [`Vault::crate_path`].
+    pub expected: PathBuf,
+
+    pub paths: Vec<PathBuf>,
+}
+
+/// Returns true if the given crate name or version can safely be used as a single path component.
+fn is_path_component(s: &str) -> bool {
+    !s.is_empty() && s != "." && !s.contains("..") && !s.contains(['/', '\\', '\0'])
+}
+
+/// Resolves a relative path of directories under `base`, matching each component case
+/// insensitively. Exact matches are preferred where there is more than one candidate.
+fn resolve_case_insensitive(base: &Path, components: &[&OsStr]) -> Result<Option<PathBuf>, Error> {
+    let Some((first, rest)) = components.split_first() else {
+        return Ok(Some(base.to_path_buf()));
+    };
+
+    for candidate in case_insensitive_matches(base, first)? {
+        if let Some(found) = resolve_case_insensitive(&candidate, rest)? {
+            return Ok(Some(found));
+        }
+    }
+
+    Ok(None)
+}
+
+fn case_insensitive_matches(dir: &Path, name: &OsStr) -> Result<Vec<PathBuf>, Error> {
+    let Some(name) = name.to_str() else {
+        return Ok(Vec::new());
+    };
+
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Ok(Vec::new());
+        }
+        Err(e) => {
+            return Err(Error::Io(dir.to_path_buf(), e));
+        }
+    };
+
+    let mut matches = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| Error::Io(dir.to_path_buf(), e))?;
+        if matches!(entry.file_name().to_str(), Some(candidate) if candidate.eq_ignore_ascii_case(name))
+            && entry.path().is_dir()
+        {
+            matches.push(entry.path());
+        }
+    }
+    matches.sort_by_key(|path| path.file_name() != Some(OsStr::new(name)));
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Write};
+
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_canonical_version_path() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf());
+
+        assert_that!(
+            vault.canonical_version_path(&"Serde".parse()?, &"1.0.0".parse()?)?,
+            eq(temp.path().join("S/Se/Serde/1.0.0"))
+        );
+
+        std::fs::create_dir_all(temp.path().join("s/se/serde/1.0.0"))?;
+        assert_that!(
+            vault.canonical_version_path(&"Serde".parse()?, &"1.0.0".parse()?)?,
+            eq(temp.path().join("s/se/serde/1.0.0"))
+        );
+        assert_that!(
+            vault.canonical_version_path(&"SERDE".parse()?, &"2.0.0".parse()?)?,
+            eq(temp.path().join("s/se/serde/2.0.0"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_crate_version_path_rejects_traversal() -> anyhow::Result<()> {
+        let vault = Vault::new(PathBuf::from("/corpus"));
+
+        for name in ["", ".", "..", "../etc", "foo/bar", "foo\\bar"] {
+            assert_that!(
+                vault.crate_path_str(name),
+                err(matches_pattern!(Error::InvalidCrateName(eq(name))))
+            );
+        }
+        for version in ["", ".", "..", "../../etc", "1.0.0/..", "1.0\0"] {
+            assert_that!(
+                vault.canonical_version_path_str("serde", version),
+                err(matches_pattern!(Error::InvalidCrateVersion(eq(version))))
+            );
+        }
+        assert_that!(
+            vault.crate_version_path(&"serde".parse()?, &"1.0.0+build.1".parse()?)?,
+            eq(PathBuf::from("/corpus/s/se/serde/1.0.0+build.1"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_versions_of() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf());
+
+        create_version(&vault, "foo", "foo", "1.0.0")?;
+        create_version(&vault, "foo", "foo", "1.0.1")?;
+        create_version(&vault, "foobar", "foobar", "1.0.0")?;
+        create_version(&vault, "Bar", "Bar", "2.0.0")?;
+        create_version(&vault, "baz", "baz", "3.0.0")?;
+
+        let names = HashSet::from(["foo".to_string(), "bar".to_string(), "quux".to_string()]);
+        let versions = vault
+            .iter_versions_of(&names)
+            .map(|result| result.map(|version| (version.crate_name, version.version)))
+            .collect::<std::result::Result<Vec<_>, Error>>()?;
+        assert_that!(
+            versions,
+            unordered_elements_are![
+                eq(("foo".to_string(), "1.0.0".to_string())),
+                eq(("foo".to_string(), "1.0.1".to_string())),
+                eq(("Bar".to_string(), "2.0.0".to_string()))
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_case_duplicates() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf());
+
+        create_version(&vault, "serde", "serde", "1.0.0")?;
+        create_version(&vault, "Serde", "serde", "1.0.0")?;
+        create_version(&vault, "Serde", "serde", "1.0.1")?;
+        create_version(&vault, "tokio", "tokio", "1.0.0")?;
+
+        let duplicates = vault.case_duplicates()?;
+        assert_that!(duplicates, len(eq(1)));
+        assert_that!(duplicates[0].name, eq("serde"));
+        assert_that!(
+            duplicates[0].paths,
+            unordered_elements_are![
+                eq(temp.path().join("s/se/serde")),
+                eq(temp.path().join("S/Se/Serde"))
+            ]
+        );
+
+        let target = vault.merge_case_duplicates(&duplicates[0])?;
+        assert_that!(target, eq(temp.path().join("s/se/serde")));
+        assert_that!(target.join("1.0.0/Cargo.toml").exists(), eq(true));
+        assert_that!(target.join("1.0.1/Cargo.toml").exists(), eq(true));
+        assert_that!(temp.path().join("S/Se/Serde").exists(), eq(false));
+        assert_that!(vault.case_duplicates()?, empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_crate() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf()).with_contents_index();
+
+        create_version(&vault, "foo", "foo", "1.0.0")?;
+        create_version(&vault, "foo", "foo", "1.0.1")?;
+        vault.record(
+            "foo",
+            "1.0.0",
+            &vault.crate_version_path(&"foo".parse()?, &"1.0.0".parse()?)?,
+        )?;
+
+        // A content addressed version, whose relative link has to climb one fewer directory
+        // once it's in the single character bucket.
+        let object = vault.object_path("abc123");
+        std::fs::create_dir_all(&object)?;
+        std::fs::write(object.join("Cargo.toml"), "")?;
+        std::os::unix::fs::symlink(
+            "../../../.objects/abc123",
+            vault.crate_version_path(&"foo".parse()?, &"2.0.0".parse()?)?,
+        )?;
+
+        create_version(&vault, "b", "b", "1.0.0")?;
+        assert_that!(
+            vault.rename_crate("foo", "b"),
+            err(matches_pattern!(Error::VersionExists(eq(temp
+                .path()
+                .join("b/b/1.0.0")))))
+        );
+        assert_that!(
+            vault
+                .crate_version_path(&"foo".parse()?, &"1.0.1".parse()?)?
+                .is_dir(),
+            eq(true)
+        );
+
+        vault.rename_crate("foo", "f")?;
+        assert_that!(temp.path().join("f/fo/foo").exists(), eq(false));
+        assert_that!(temp.path().join("f/f/1.0.0/Cargo.toml").is_file(), eq(true));
+        assert_that!(temp.path().join("f/f/1.0.1/Cargo.toml").is_file(), eq(true));
+        assert_that!(temp.path().join("f/f/2.0.0/Cargo.toml").is_file(), eq(true));
+        assert_that!(vault.contains("foo", "1.0.0")?, eq(false));
+        assert_that!(vault.contains("f", "1.0.0")?, eq(true));
+
+        // Both crates have history repositories: the target's is kept, and the source's is left
+        // where it was rather than being mistaken for a version.
+        create_version(&vault, "bar", "bar", "1.0.0")?;
+        std::fs::create_dir_all(temp.path().join("b/ba/bar/.git/objects"))?;
+        std::fs::create_dir_all(temp.path().join("f/f/.git/objects"))?;
+        std::fs::write(temp.path().join("f/f/.git/HEAD"), "f")?;
+        vault.rename_crate("bar", "f")?;
+        assert_that!(temp.path().join("f/f/1.0.0/Cargo.toml").is_file(), eq(true));
+        assert_that!(
+            std::fs::read_to_string(temp.path().join("f/f/.git/HEAD"))?,
+            eq("f")
+        );
+        assert_that!(temp.path().join("b/ba/bar/.git").is_dir(), eq(true));
+        assert_that!(vault.contains("f", ".git")?, eq(false));
+
+        // Without a history repository in the way, it moves with the crate.
+        create_version(&vault, "baz", "baz", "1.0.0")?;
+        std::fs::create_dir_all(temp.path().join("b/ba/baz/.git/objects"))?;
+        vault.rename_crate("baz", "qux")?;
+        assert_that!(temp.path().join("b/ba/baz").exists(), eq(false));
+        assert_that!(temp.path().join("q/qu/qux/.git/objects").is_dir(), eq(true));
+
+        assert_that!(
+            vault.rename_crate("missing", "f"),
+            err(matches_pattern!(Error::NotADirectory(anything())))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bucket_duplicates() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf());
+
+        create_version(&vault, "serde", "serde", "1.0.0")?;
+        create_version(&vault, "Serde", "serde", "1.0.1")?;
+        let misplaced = temp.path().join("se/rd/serde/1.0.2");
+        std::fs::create_dir_all(&misplaced)?;
+        std::fs::write(
+            misplaced.join("Cargo.toml"),
+            "[package]\nname = \"serde\"\nversion = \"1.0.2\"\n",
+        )?;
+
+        let duplicates = vault.bucket_duplicates()?;
+        assert_that!(duplicates, len(eq(1)));
+        assert_that!(duplicates[0].name, eq("serde"));
+        assert_that!(duplicates[0].expected, eq(temp.path().join("s/se/serde")));
+        assert_that!(duplicates[0].paths, len(eq(3)));
+
+        // The misplaced directory isn't a case duplicate of either of the others.
+        let duplicates = vault.case_duplicates()?;
+        assert_that!(duplicates, len(eq(1)));
+        assert_that!(duplicates[0].paths, len(eq(2)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contents_index() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        create_version(
+            &Vault::new(temp.path().to_path_buf()),
+            "serde",
+            "serde",
+            "1.0.0",
+        )?;
+
+        let vault = Vault::new(temp.path().to_path_buf());
+        assert_that!(vault.contains("Serde", "1.0.0")?, eq(true));
+        assert_that!(vault.contains("serde", "1.0.1")?, eq(false));
+
+        // Nothing has been indexed yet.
+        let vault = Vault::new(temp.path().to_path_buf()).with_contents_index();
+        assert_that!(vault.contains("serde", "1.0.0")?, eq(false));
+        assert_that!(vault.reindex()?, eq(1));
+        assert_that!(
+            vault.lookup("Serde", "1.0.0")?,
+            some(eq(temp.path().join("s/se/serde/1.0.0")))
+        );
+
+        vault.record("tokio", "1.0.0", &temp.path().join("t/to/tokio/1.0.0"))?;
+        vault.forget("serde", "1.0.0")?;
+        assert_that!(vault.contains("serde", "1.0.0")?, eq(false));
+        assert_that!(vault.contains("tokio", "1.0.0")?, eq(true));
+
+        // The log is replayed when the vault is reopened.
+        let vault = Vault::new(temp.path().to_path_buf()).with_contents_index();
+        assert_that!(vault.contains("serde", "1.0.0")?, eq(false));
+        assert_that!(
+            vault.lookup("tokio", "1.0.0")?,
+            some(eq(temp.path().join("t/to/tokio/1.0.0")))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("vault");
+
+        assert_that!(
+            Vault::open(path.clone()),
+            err(matches_pattern!(Error::Io(eq(path.clone()), anything())))
+        );
+
+        std::fs::write(&path, "")?;
+        assert_that!(
+            Vault::open(path.clone()),
+            err(matches_pattern!(Error::NotADirectory(eq(path.clone()))))
+        );
+
+        std::fs::remove_file(&path)?;
+        std::fs::create_dir(&path)?;
+        std::fs::write(path.join(".librarianignore"), "")?;
+        assert_that!(Vault::open(path.clone())?.layout(), eq(Layout::ByVersion));
+
+        std::fs::write(path.join("notes.txt"), "")?;
+        assert_that!(
+            Vault::open(path.clone()),
+            err(matches_pattern!(Error::NotAVault(eq(path.clone()))))
+        );
+
+        std::fs::create_dir_all(path.join(".objects"))?;
+        assert_that!(
+            Vault::open(path.clone())?.layout(),
+            eq(Layout::ContentAddressed)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_par_crate_versions() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf());
+        for num in ["1.0.0", "1.1.0", "2.0.0"] {
+            create_version(&vault, "foo", "foo", num)?;
+        }
+
+        let serial: Vec<String> = vault
+            .iter_crate_versions()
+            .map(|result| result.map(|version| version.version))
+            .collect::<std::result::Result<_, Error>>()?;
+        let parallel: Vec<String> = vault
+            .par_crate_versions(2)?
+            .into_iter()
+            .map(|result| result.map(|version| version.version))
+            .collect::<std::result::Result<_, Error>>()?;
+        assert_that!(parallel, eq(serial));
+        assert_that!(parallel.len(), eq(3));
+
+        Ok(())
+    }
+
+    /// Times [`Vault::par_crate_versions`] with a range of thread counts. Run with:
+    ///
+    /// ```sh
+    /// LIBRARIAN_BENCH_VAULT=/path/to/corpus cargo test -p vault --release --features bench -- \
+    ///     --nocapture bench_par_crate_versions
+    /// ```
+    ///
+    /// Without `LIBRARIAN_BENCH_VAULT`, a synthetic vault is generated.
+    #[cfg(feature = "bench")]
+    #[test]
+    fn bench_par_crate_versions() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = match std::env::var_os("LIBRARIAN_BENCH_VAULT") {
+            Some(path) => Vault::new(PathBuf::from(path)),
+            None => {
+                let vault = Vault::new(temp.path().to_path_buf());
+                for i in 0..2000 {
+                    create_version(&vault, &format!("crate{i}"), &format!("crate{i}"), "1.0.0")?;
+                }
+                vault
+            }
+        };
+
+        for num_threads in [1, 2, 4, 8, 16] {
+            let start = std::time::Instant::now();
+            let count = vault.par_crate_versions(num_threads)?.len();
+            println!(
+                "{num_threads:>2} thread(s): {count} versions in {:?}",
+                start.elapsed()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recorded_checksum() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf()).with_layout(Layout::ContentAddressed);
+
+        create_version(&vault, "foo", "foo", "1.0.0")?;
+        assert_that!(vault.recorded_checksum("foo", "1.0.0")?, none());
+        assert_that!(vault.recorded_checksum("foo", "2.0.0")?, none());
+
+        std::fs::create_dir_all(vault.object_path("abc123"))?;
+        std::os::unix::fs::symlink(
+            "../../../.objects/abc123",
+            vault.crate_version_path(&"foo".parse()?, &"1.0.1".parse()?)?,
+        )?;
+        assert_that!(vault.recorded_checksum("foo", "1.0.1")?, some(eq("abc123")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_archives() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf()).with_layout(Layout::ArchiveOnly);
+
+        for (name, version) in [("a", "1.0.0"), ("serde", "1.0.0"), ("serde", "1.0.1")] {
+            let path = vault.archive_path(name, version)?;
+            std::fs::create_dir_all(path.parent().expect("archive parent"))?;
+            std::fs::write(path, "")?;
+        }
+        std::fs::write(
+            vault
+                .crate_version_path(&"serde".parse()?, &"1.0.1".parse()?)?
+                .join("other.crate"),
+            "",
+        )?;
+        std::fs::create_dir_all(temp.path().join(".partial"))?;
+        std::fs::write(temp.path().join(".partial/serde-1.0.2.crate"), "")?;
+
+        let mut archives = vault
+            .iter_archives()
+            .map(|result| result.map(|archive| (archive.crate_name, archive.version)))
+            .collect::<std::result::Result<Vec<_>, Error>>()?;
+        archives.sort();
+        assert_that!(
+            archives,
+            eq(vec![
+                ("a".to_string(), "1.0.0".to_string()),
+                ("serde".to_string(), "1.0.0".to_string()),
+                ("serde".to_string(), "1.0.1".to_string()),
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vcs_info() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf());
+
+        create_version(&vault, "foo", "foo", "1.0.0")?;
+        create_version(&vault, "foo", "foo", "1.0.1")?;
+        create_version(&vault, "foo", "foo", "1.0.2")?;
+        std::fs::write(
+            vault
+                .crate_version_path(&"foo".parse()?, &"1.0.1".parse()?)?
+                .join(".cargo_vcs_info.json"),
+            r#"{"git": {"sha1": "abc123", "dirty": true}, "path_in_vcs": "foo"}"#,
+        )?;
+        std::fs::write(
+            vault
+                .crate_version_path(&"foo".parse()?, &"1.0.2".parse()?)?
+                .join(".cargo_vcs_info.json"),
+            "not JSON",
+        )?;
+
+        let versions: BTreeMap<String, CrateVersion> = vault
+            .iter_crate_versions()
+            .map(|result| result.map(|version| (version.version.clone(), version)))
+            .collect::<std::result::Result<_, Error>>()?;
+
+        assert_that!(vault.vcs_info(&versions["1.0.0"])?, none());
+
+        let info = vault.vcs_info(&versions["1.0.1"])?.expect("VCS info");
+        assert_that!(info.git().map(GitInfo::sha1), some(eq("abc123")));
+        assert_that!(info.git().map(GitInfo::is_dirty), some(eq(true)));
+        assert_that!(info.path_in_vcs(), some(eq("foo")));
+
+        assert_that!(
+            vault.vcs_info(&versions["1.0.2"]),
+            err(matches_pattern!(Error::VcsInfo(anything(), anything())))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_manifest_matches() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf());
+
+        create_version(&vault, "foo", "foo", "1.0.0")?;
+        vault.verify_manifest_matches("foo", "1.0.0")?;
+
+        create_version(&vault, "bar", "foo", "1.0.0")?;
+        assert_that!(
+            vault.verify_manifest_matches("bar", "1.0.0"),
+            err(matches_pattern!(Error::ManifestMismatch {
+                expected: eq("bar@1.0.0"),
+                found: eq("foo@1.0.0"),
+            }))
+        );
+
+        assert_that!(
+            vault.verify_manifest_matches("baz", "1.0.0"),
+            err(matches_pattern!(Error::ManifestOpen(
+                anything(),
+                anything()
+            )))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_crate_versions_lenient() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf());
+
+        create_version(&vault, "bar", "bar", "1.0.0")?;
+        create_version(&vault, "foo", "foo", "1.0.0")?;
+        let bad = vault
+            .crate_version_path(&"foo".parse()?, &"1.0.0".parse()?)?
+            .join("Cargo.toml");
+        std::fs::write(&bad, "[package]\nname = \"foo\"\n")?;
+
+        let walked = vault
+            .iter_crate_versions_lenient()
+            .map(|result| {
+                result.map(|walked| match walked {
+                    WalkedVersion::Version(version) => (version.path, None),
+                    WalkedVersion::ManifestError { path, error } => (path, error.manifest_issue()),
+                })
+            })
+            .collect::<std::result::Result<Vec<_>, Error>>()?;
+        assert_that!(
+            walked,
+            elements_are![
+                eq((
+                    vault
+                        .crate_version_path(&"bar".parse()?, &"1.0.0".parse()?)?
+                        .join("Cargo.toml"),
+                    None
+                )),
+                eq((bad, Some(ManifestIssue::MissingVersion)))
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_build_script() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let vault = Vault::new(temp.path().to_path_buf());
+
+        create_version(&vault, "foo", "foo", "1.0.0")?;
+        assert_that!(vault.has_build_script("foo", "1.0.0")?, eq(false));
+
+        std::fs::write(
+            vault
+                .crate_version_path(&"foo".parse()?, &"1.0.0".parse()?)?
+                .join("build.rs"),
+            "",
+        )?;
+        assert_that!(vault.has_build_script("foo", "1.0.0")?, eq(true));
+
+        Ok(())
+    }
+
+    fn create_version(
+        vault: &Vault,
+        dir_name: &str,
+        crate_name: &str,
+        version: &str,
+    ) -> anyhow::Result<()> {
+        let path = vault.crate_version_path(&dir_name.parse()?, &version.parse()?)?;
+        std::fs::create_dir_all(&path)?;
+
+        let mut file = File::create(path.join("Cargo.toml"))?;
+        writeln!(&mut file, "[package]")?;
+        writeln!(&mut file, r#"name = "{crate_name}""#)?;
+        writeln!(&mut file, r#"version = "{version}""#)?;
+
+        Ok(())
+    }
+}
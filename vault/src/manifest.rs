@@ -1,35 +1,526 @@
-use std::{fs::File, io::Read, path::Path};
+use std::{fmt, fs::File, io::Read, path::Path};
 
 use serde::Deserialize;
 
 use crate::Error;
 
-#[derive(Deserialize)]
-pub(crate) struct Manifest {
+/// The largest manifest, in bytes, that [`Manifest::parse_file`] and [`Manifest::parse_reader`]
+/// will read. Real manifests are rarely more than a few kilobytes.
+pub const DEFAULT_MAX_MANIFEST_SIZE: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Manifest {
     package: Package,
 }
 
 impl Manifest {
-    pub(crate) fn parse_file(path: &Path) -> Result<Self, Error> {
-        let mut file = File::open(path).map_err(|e| Error::ManifestOpen(path.to_path_buf(), e))?;
-        let mut s = String::new();
+    pub fn parse_file(path: &Path) -> Result<Self, Error> {
+        Self::parse_file_with_limit(path, DEFAULT_MAX_MANIFEST_SIZE)
+    }
+
+    /// Like [`Manifest::parse_file`], but fails with [`Error::ManifestTooLarge`] if the file is
+    /// larger than `limit` bytes, rather than [`DEFAULT_MAX_MANIFEST_SIZE`].
+    pub fn parse_file_with_limit(path: &Path, limit: u64) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|e| Error::ManifestOpen(path.to_path_buf(), e))?;
+        let size = file
+            .metadata()
+            .map_err(|e| Error::ManifestOpen(path.to_path_buf(), e))?
+            .len();
+        if size > limit {
+            return Err(Error::ManifestTooLarge(path.to_path_buf(), size));
+        }
+
+        Self::parse_open_file(path, file, limit)
+    }
+
+    pub fn parse_reader<R: Read>(reader: R) -> Result<Self, Error> {
+        Self::parse_reader_with_limit(reader, DEFAULT_MAX_MANIFEST_SIZE)
+    }
+
+    /// Like [`Manifest::parse_reader`], but fails with [`Error::ManifestTooLargeStream`] once more
+    /// than `limit` bytes have been read, rather than [`DEFAULT_MAX_MANIFEST_SIZE`].
+    pub fn parse_reader_with_limit<R: Read>(reader: R, limit: u64) -> Result<Self, Error> {
+        let data = Self::read_with_limit(reader, limit)?;
+        let s = String::from_utf8(data).map_err(|e| {
+            Error::ManifestReadStream(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        })?;
+        Self::parse_str(&s)
+    }
+
+    fn read_with_limit<R: Read>(reader: R, limit: u64) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        reader
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut data)
+            .map_err(Error::ManifestReadStream)?;
+        if data.len() as u64 > limit {
+            return Err(Error::ManifestTooLargeStream(data.len() as u64));
+        }
+
+        Ok(data)
+    }
+
+    pub fn parse_str(s: &str) -> Result<Self, Error> {
+        toml::from_str(s)
+            .map_err(|e| Error::ManifestParseStr(ManifestIssue::classify(s), Box::new(e)))
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    fn parse_open_file(path: &Path, file: File, limit: u64) -> Result<Self, Error> {
+        // Re-home the path-less errors from the reader so the caller knows which file was bad.
+        let data = Self::read_with_limit(file, limit).map_err(|e| match e {
+            Error::ManifestReadStream(e) => Error::ManifestRead(path.to_path_buf(), e),
+            Error::ManifestTooLargeStream(size) => {
+                Error::ManifestTooLarge(path.to_path_buf(), size)
+            }
+            e => e,
+        })?;
+
+        Self::parse_file_bytes(path, &data)
+    }
 
-        file.read_to_string(&mut s)
+    #[cfg(feature = "mmap")]
+    fn parse_open_file(path: &Path, file: File, _limit: u64) -> Result<Self, Error> {
+        // The size was checked before the file was mapped.
+        // SAFETY: the map is only read for the duration of this function. If the file is
+        // truncated underneath us we may fault, but vaults aren't expected to be modified while
+        // they're being walked.
+        let map = unsafe { memmap2::Mmap::map(&file) }
             .map_err(|e| Error::ManifestRead(path.to_path_buf(), e))?;
-        toml::from_str(&s).map_err(|e| Error::ManifestParse(path.to_path_buf(), e))
+
+        Self::parse_file_bytes(path, &map)
     }
 
-    pub(crate) fn crate_name(&self) -> &str {
+    fn parse_file_bytes(path: &Path, data: &[u8]) -> Result<Self, Error> {
+        let s =
+            std::str::from_utf8(data).map_err(|e| Error::ManifestUtf8(path.to_path_buf(), e))?;
+
+        toml::from_str(s).map_err(|e| {
+            Error::ManifestParse(path.to_path_buf(), ManifestIssue::classify(s), Box::new(e))
+        })
+    }
+
+    pub fn crate_name(&self) -> &str {
         &self.package.name
     }
 
-    pub(crate) fn crate_version(&self) -> &str {
+    pub fn crate_version(&self) -> &str {
         &self.package.version
     }
+
+    /// The Rust edition declared by the crate, if any.
+    pub fn edition(&self) -> Option<&str> {
+        self.package.edition.as_ref()?.as_str()
+    }
+
+    /// The crate's SPDX license expression, if any.
+    pub fn license(&self) -> Option<&str> {
+        self.package.license.as_ref()?.as_str()
+    }
+
+    /// The path of the crate's license file relative to the manifest, if one is declared with
+    /// `license-file`, whether or not it exists.
+    pub fn license_file(&self) -> Option<&str> {
+        self.package.license_file.as_ref()?.as_str()
+    }
+
+    /// The name of the native library the crate links to, if any.
+    pub fn links(&self) -> Option<&str> {
+        self.package.links.as_ref()?.as_str()
+    }
+
+    /// The path of the crate's build script relative to the manifest, whether or not it exists,
+    /// or `None` if build scripts have been explicitly disabled with `build = false`.
+    pub fn build_script(&self) -> Option<&str> {
+        match &self.package.build {
+            Some(toml::Value::Boolean(false)) => None,
+            Some(toml::Value::String(path)) => Some(path),
+            _ => Some("build.rs"),
+        }
+    }
+
+    /// The path of the crate's README relative to the manifest, or `None` if it has been
+    /// explicitly disabled with `readme = false`.
+    ///
+    /// If no README is declared, this is `README.md`, whether or not it exists.
+    pub fn readme(&self) -> Option<&str> {
+        match &self.package.readme {
+            Some(toml::Value::Boolean(false)) => None,
+            Some(toml::Value::String(path)) => Some(path),
+            _ => Some("README.md"),
+        }
+    }
+
+    /// Renders a manifest containing only the `[package]` fields that librarian parses, such that
+    /// [`Manifest::parse_str`] returns an equivalent manifest.
+    pub fn to_minimal_toml(&self) -> String {
+        let mut package = toml::Table::new();
+        package.insert("name".into(), self.package.name.clone().into());
+        package.insert("version".into(), self.package.version.clone().into());
+        for (key, value) in [
+            ("edition", &self.package.edition),
+            ("license", &self.package.license),
+            ("license-file", &self.package.license_file),
+            ("links", &self.package.links),
+            ("build", &self.package.build),
+            ("readme", &self.package.readme),
+        ] {
+            if let Some(value) = value {
+                package.insert(key.into(), value.clone());
+            }
+        }
+
+        let mut manifest = toml::Table::new();
+        manifest.insert("package".into(), package.into());
+        manifest.to_string()
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 struct Package {
     name: String,
     version: String,
+    // These are kept loosely typed so that an odd value doesn't make the whole manifest
+    // unparseable: they're informational, unlike the name and version.
+    #[serde(default)]
+    edition: Option<toml::Value>,
+    #[serde(default)]
+    license: Option<toml::Value>,
+    #[serde(default, rename = "license-file")]
+    license_file: Option<toml::Value>,
+    #[serde(default)]
+    links: Option<toml::Value>,
+    #[serde(default)]
+    build: Option<toml::Value>,
+    #[serde(default)]
+    readme: Option<toml::Value>,
+}
+
+/// A broad category of manifest parse failure, for triaging failures across a whole vault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ManifestIssue {
+    /// The manifest has a `[package]` table, but the `version` isn't a string. This is usually
+    /// workspace inheritance (`version.workspace = true`) that wasn't resolved when the crate was
+    /// packaged.
+    InvalidVersionType,
+
+    /// The `[package]` table has no `name`.
+    MissingName,
+
+    /// The manifest has no `[package]` table at all.
+    MissingPackage,
+
+    /// The `[package]` table has no `version`.
+    MissingVersion,
+
+    /// Anything else, including manifests that aren't valid TOML.
+    Other,
+}
+
+impl ManifestIssue {
+    /// Works out why a manifest failed to parse.
+    pub(crate) fn classify(s: &str) -> Self {
+        let Ok(table) = s.parse::<toml::Table>() else {
+            return Self::Other;
+        };
+        let Some(package) = table.get("package").and_then(toml::Value::as_table) else {
+            return Self::MissingPackage;
+        };
+
+        match (package.get("name"), package.get("version")) {
+            (None, _) => Self::MissingName,
+            (Some(_), None) => Self::MissingVersion,
+            (Some(_), Some(version)) if !version.is_str() => Self::InvalidVersionType,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl fmt::Display for ManifestIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::InvalidVersionType => "invalid version type",
+            Self::MissingName => "missing name",
+            Self::MissingPackage => "missing [package]",
+            Self::MissingVersion => "missing version",
+            Self::Other => "other",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use googletest::prelude::*;
+
+    use super::*;
+
+    const MANIFEST: &str = r#"
+        [package]
+        name = "foo"
+        version = "1.2.3"
+    "#;
+
+    #[test]
+    fn test_parse_str() -> anyhow::Result<()> {
+        let manifest = Manifest::parse_str(MANIFEST)?;
+        assert_that!(manifest.crate_name(), eq("foo"));
+        assert_that!(manifest.crate_version(), eq("1.2.3"));
+
+        assert_that!(
+            Manifest::parse_str("[package]\nname = \"foo\"\n"),
+            err(matches_pattern!(Error::ManifestParseStr(
+                eq(ManifestIssue::MissingVersion),
+                anything()
+            )))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_classify() -> anyhow::Result<()> {
+        for (manifest, issue) in [
+            ("[package", ManifestIssue::Other),
+            ("[dependencies]\n", ManifestIssue::MissingPackage),
+            (
+                "[package]\nversion = \"1.0.0\"\n",
+                ManifestIssue::MissingName,
+            ),
+            ("[package]\nname = \"foo\"\n", ManifestIssue::MissingVersion),
+            (
+                "[package]\nname = \"foo\"\nversion.workspace = true\n",
+                ManifestIssue::InvalidVersionType,
+            ),
+            (
+                "[package]\nname = 1\nversion = \"1.0.0\"\n",
+                ManifestIssue::Other,
+            ),
+        ] {
+            assert_that!(ManifestIssue::classify(manifest), eq(issue));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_links_and_build_script() -> anyhow::Result<()> {
+        let manifest = Manifest::parse_str(MANIFEST)?;
+        assert_that!(manifest.links(), none());
+        assert_that!(manifest.build_script(), some(eq("build.rs")));
+
+        let manifest = Manifest::parse_str(&format!(
+            "{MANIFEST}\nlinks = \"z\"\nbuild = \"src/build.rs\"\n"
+        ))?;
+        assert_that!(manifest.links(), some(eq("z")));
+        assert_that!(manifest.build_script(), some(eq("src/build.rs")));
+
+        let manifest = Manifest::parse_str(&format!("{MANIFEST}\nbuild = false\n"))?;
+        assert_that!(manifest.build_script(), none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_readme() -> anyhow::Result<()> {
+        let manifest = Manifest::parse_str(MANIFEST)?;
+        assert_that!(manifest.readme(), some(eq("README.md")));
+
+        let manifest = Manifest::parse_str(&format!("{MANIFEST}\nreadme = \"docs/README\"\n"))?;
+        assert_that!(manifest.readme(), some(eq("docs/README")));
+
+        let manifest = Manifest::parse_str(&format!("{MANIFEST}\nreadme = false\n"))?;
+        assert_that!(manifest.readme(), none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_minimal_toml() -> anyhow::Result<()> {
+        let manifest = Manifest::parse_str(MANIFEST)?;
+        assert_that!(
+            manifest.to_minimal_toml(),
+            eq("[package]\nname = \"foo\"\nversion = \"1.2.3\"\n")
+        );
+
+        let manifest = Manifest::parse_str(&format!(
+            "{MANIFEST}\nedition = \"2021\"\nlicense = \"MIT\"\nlicense-file = \"LICENSE\"\nbuild = false\n\n[dependencies]\nbar = \"1\"\n"
+        ))?;
+        let minimal = manifest.to_minimal_toml();
+        assert_that!(minimal, not(contains_substring("dependencies")));
+
+        let round_tripped = Manifest::parse_str(&minimal)?;
+        assert_that!(round_tripped.crate_name(), eq("foo"));
+        assert_that!(round_tripped.crate_version(), eq("1.2.3"));
+        assert_that!(round_tripped.edition(), some(eq("2021")));
+        assert_that!(round_tripped.license(), some(eq("MIT")));
+        assert_that!(round_tripped.license_file(), some(eq("LICENSE")));
+        assert_that!(round_tripped.build_script(), none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_reader() -> anyhow::Result<()> {
+        let manifest = Manifest::parse_reader(MANIFEST.as_bytes())?;
+        assert_that!(manifest.crate_name(), eq("foo"));
+        assert_that!(manifest.crate_version(), eq("1.2.3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_file() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("Cargo.toml");
+
+        assert_that!(
+            Manifest::parse_file(&path),
+            err(matches_pattern!(Error::ManifestOpen(
+                eq(path.clone()),
+                anything()
+            )))
+        );
+
+        let mut file = File::create(&path)?;
+        write!(&mut file, "[package]")?;
+        assert_that!(
+            Manifest::parse_file(&path),
+            err(matches_pattern!(Error::ManifestParse(
+                eq(path.clone()),
+                eq(ManifestIssue::MissingName),
+                anything()
+            )))
+        );
+
+        // Invalid UTF-8 is reported the same way whether or not the file is mapped.
+        std::fs::write(&path, b"[package]\nname = \"\xff\"\n")?;
+        assert_that!(
+            Manifest::parse_file(&path),
+            err(matches_pattern!(Error::ManifestUtf8(
+                eq(path.clone()),
+                anything()
+            )))
+        );
+
+        let mut file = File::create(&path)?;
+        write!(&mut file, "{MANIFEST}")?;
+        let manifest = Manifest::parse_file(&path)?;
+        assert_that!(manifest.crate_name(), eq("foo"));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_parse_file_mmap() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("Cargo.toml");
+
+        // An empty file maps to an empty slice, so it fails as a manifest without a [package]
+        // table rather than as an I/O error.
+        std::fs::write(&path, "")?;
+        assert_that!(
+            Manifest::parse_file(&path),
+            err(matches_pattern!(Error::ManifestParse(
+                eq(path.clone()),
+                eq(ManifestIssue::MissingPackage),
+                anything()
+            )))
+        );
+
+        std::fs::write(&path, MANIFEST)?;
+        let manifest = Manifest::parse_file(&path)?;
+        assert_that!(manifest.crate_name(), eq("foo"));
+        assert_that!(manifest.crate_version(), eq("1.2.3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_limit() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("Cargo.toml");
+        std::fs::write(&path, MANIFEST)?;
+        let size = MANIFEST.len() as u64;
+
+        assert_that!(
+            Manifest::parse_file_with_limit(&path, size - 1),
+            err(matches_pattern!(Error::ManifestTooLarge(
+                eq(path.clone()),
+                eq(size)
+            )))
+        );
+        assert_that!(
+            Manifest::parse_file_with_limit(&path, size)
+                .map(|manifest| manifest.crate_name().to_string()),
+            ok(eq("foo"))
+        );
+
+        assert_that!(
+            Manifest::parse_reader_with_limit(MANIFEST.as_bytes(), 10),
+            err(matches_pattern!(Error::ManifestTooLargeStream(eq(11))))
+        );
+        assert_that!(
+            Manifest::parse_reader_with_limit(MANIFEST.as_bytes(), size)
+                .map(|manifest| manifest.crate_name().to_string()),
+            ok(eq("foo"))
+        );
+
+        Ok(())
+    }
+
+    /// Times parsing a set of manifests from files with [`Manifest::parse_file`] against reading
+    /// them with [`Manifest::parse_reader`]. Run with and without the `mmap` feature to compare:
+    ///
+    /// ```sh
+    /// cargo test -p vault --release --features bench,mmap -- --nocapture bench_parse_file
+    /// ```
+    #[cfg(feature = "bench")]
+    #[test]
+    fn bench_parse_file() -> anyhow::Result<()> {
+        const MANIFESTS: usize = 2000;
+        const ROUNDS: usize = 5;
+
+        let temp = tempfile::tempdir()?;
+        // Pad the manifests out to something more like the size of a real one.
+        let padding = "# padding\n".repeat(200);
+        let paths = (0..MANIFESTS)
+            .map(|i| {
+                let path = temp.path().join(format!("Cargo{i}.toml"));
+                std::fs::write(
+                    &path,
+                    format!("[package]\nname = \"crate{i}\"\nversion = \"1.0.0\"\n{padding}"),
+                )?;
+                Ok(path)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            for path in &paths {
+                Manifest::parse_reader(File::open(path)?)?;
+            }
+        }
+        println!("parse_reader: {:?}", start.elapsed());
+
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            for path in &paths {
+                Manifest::parse_file(path)?;
+            }
+        }
+        println!(
+            "parse_file ({}): {:?}",
+            if cfg!(feature = "mmap") {
+                "mmap"
+            } else {
+                "read"
+            },
+            start.elapsed()
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,148 @@
+use std::{fmt, ops::Deref, str::FromStr};
+
+use serde::Serialize;
+
+use crate::{is_path_component, Error};
+
+/// The name of a crate, validated so that it can be used as a single path component.
+///
+/// Keeping names and versions as distinct types means that they can't be passed in the wrong
+/// order to functions that take both.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(transparent)]
+pub struct CrateName(String);
+
+impl CrateName {
+    /// Validates the given crate name.
+    ///
+    /// Names that are empty or contain path separators or `..` are rejected.
+    pub fn new(name: impl Into<String>) -> Result<Self, Error> {
+        let name = name.into();
+        if is_path_component(&name) {
+            Ok(Self(name))
+        } else {
+            Err(Error::InvalidCrateName(name))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+/// A crate version number, validated so that it can be used as a single path component.
+///
+/// Version numbers aren't required to be valid semver, since older index entries include some
+/// that aren't.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(transparent)]
+pub struct CrateVersionNum(String);
+
+impl CrateVersionNum {
+    /// Validates the given version number.
+    ///
+    /// Versions that are empty or contain path separators or `..` are rejected.
+    pub fn new(num: impl Into<String>) -> Result<Self, Error> {
+        let num = num.into();
+        if is_path_component(&num) {
+            Ok(Self(num))
+        } else {
+            Err(Error::InvalidCrateVersion(num))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+macro_rules! impl_string_wrapper {
+    ($t:ty) => {
+        impl Deref for $t {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $t {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $t {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl FromStr for $t {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<Self, Error> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<&str> for $t {
+            type Error = Error;
+
+            fn try_from(s: &str) -> Result<Self, Error> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<String> for $t {
+            type Error = Error;
+
+            fn try_from(s: String) -> Result<Self, Error> {
+                Self::new(s)
+            }
+        }
+    };
+}
+
+impl_string_wrapper!(CrateName);
+impl_string_wrapper!(CrateVersionNum);
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_validation() {
+        for name in ["", ".", "..", "../etc", "foo/bar", "foo\\bar"] {
+            assert_that!(
+                CrateName::new(name),
+                err(matches_pattern!(Error::InvalidCrateName(eq(name))))
+            );
+        }
+        for num in ["", ".", "..", "../../etc", "1.0.0/..", "1.0\0"] {
+            assert_that!(
+                num.parse::<CrateVersionNum>(),
+                err(matches_pattern!(Error::InvalidCrateVersion(eq(num))))
+            );
+        }
+
+        assert_that!(
+            CrateName::new("serde").map(|name| name.to_string()),
+            ok(eq("serde"))
+        );
+        assert_that!(
+            CrateVersionNum::try_from("1.0.0+build.1").map(CrateVersionNum::into_inner),
+            ok(eq("1.0.0+build.1"))
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+/// The operations a [`Vault`](crate::Vault) needs from whatever its files are stored on.
+///
+/// Paths are given as the vault computes them, rooted at [`Vault::path`](crate::Vault::path);
+/// a backend that doesn't have a real filesystem can strip that prefix to get a key.
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// Reads the whole of the file at `path`.
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>>;
+
+    /// Opens the file at `path` for streaming reads.
+    fn open<'a>(&'a self, path: &Path) -> std::io::Result<Box<dyn Read + 'a>>;
+
+    /// Replaces the file at `path` with `data`, creating any missing parent directories.
+    fn write(&self, path: &Path, data: &[u8]) -> std::io::Result<()>;
+
+    /// Returns true if there is a file or directory at `path`.
+    fn exists(&self, path: &Path) -> std::io::Result<bool>;
+
+    /// Removes the file at `path`, or everything beneath it if it's a directory. Removing a path
+    /// that doesn't exist is not an error.
+    fn remove(&self, path: &Path) -> std::io::Result<()>;
+
+    /// Iterates over the paths of every file beneath `path`, in sorted order.
+    fn walk<'a>(&'a self, path: &Path) -> Box<dyn Iterator<Item = std::io::Result<PathBuf>> + 'a>;
+}
+
+/// [`Storage`] on the local filesystem. This is what vaults use unless told otherwise.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalStorage;
+
+impl Storage for LocalStorage {
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn open<'a>(&'a self, path: &Path) -> std::io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, data)
+    }
+
+    fn exists(&self, path: &Path) -> std::io::Result<bool> {
+        path.try_exists()
+    }
+
+    fn remove(&self, path: &Path) -> std::io::Result<()> {
+        // Don't follow symlinks, so that removing a content addressed version only removes the
+        // link to its object.
+        let result = match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+            Ok(_) => std::fs::remove_file(path),
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn walk<'a>(&'a self, path: &Path) -> Box<dyn Iterator<Item = std::io::Result<PathBuf>> + 'a> {
+        Box::new(
+            WalkDir::new(path)
+                .follow_links(true)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_map(|result| match result {
+                    Ok(entry) if entry.file_type().is_file() => Some(Ok(entry.into_path())),
+                    Ok(_) => None,
+                    Err(e) => Some(Err(e.into())),
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use googletest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_local_storage() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let storage = LocalStorage;
+        let path = temp.path().join("f/fo/foo/1.0.0/Cargo.toml");
+
+        assert_that!(storage.exists(&path)?, eq(false));
+        storage.write(&path, b"[package]")?;
+        storage.write(&temp.path().join("f/fo/foo/1.0.0/src/lib.rs"), b"")?;
+        assert_that!(storage.exists(&path)?, eq(true));
+        assert_that!(storage.read(&path)?, eq(b"[package]".to_vec()));
+        let mut data = Vec::new();
+        storage.open(&path)?.read_to_end(&mut data)?;
+        assert_that!(data, eq(b"[package]".to_vec()));
+        assert_that!(
+            storage
+                .walk(temp.path())
+                .collect::<std::io::Result<Vec<_>>>()?,
+            eq(vec![
+                path.clone(),
+                temp.path().join("f/fo/foo/1.0.0/src/lib.rs"),
+            ])
+        );
+
+        storage.remove(&temp.path().join("f/fo/foo/1.0.0"))?;
+        assert_that!(storage.exists(&path)?, eq(false));
+        storage.remove(&path)?;
+
+        Ok(())
+    }
+}
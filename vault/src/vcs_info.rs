@@ -0,0 +1,63 @@
+use std::{io::ErrorKind, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Storage};
+
+/// The name of the file that `cargo package` records version control information in, relative to
+/// the crate version root.
+pub(crate) const FILE_NAME: &str = ".cargo_vcs_info.json";
+
+/// The version control information recorded when a crate version was packaged.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VcsInfo {
+    #[serde(default)]
+    git: Option<GitInfo>,
+    #[serde(default)]
+    path_in_vcs: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GitInfo {
+    sha1: String,
+    #[serde(default)]
+    dirty: bool,
+}
+
+impl VcsInfo {
+    /// Parses the given file, returning `None` if it doesn't exist.
+    pub(crate) fn load(storage: &dyn Storage, path: &Path) -> Result<Option<Self>, Error> {
+        let data = match storage.read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Io(path.to_path_buf(), e)),
+        };
+
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| Error::VcsInfo(path.to_path_buf(), e))
+    }
+
+    pub fn git(&self) -> Option<&GitInfo> {
+        self.git.as_ref()
+    }
+
+    /// The directory of the crate within its repository, if it isn't at the root. Only recorded
+    /// by cargo 1.58 and later.
+    pub fn path_in_vcs(&self) -> Option<&str> {
+        self.path_in_vcs.as_deref()
+    }
+}
+
+impl GitInfo {
+    /// The commit that the crate version was packaged from.
+    pub fn sha1(&self) -> &str {
+        &self.sha1
+    }
+
+    /// True if the working tree had uncommitted changes when the crate version was packaged, in
+    /// which case it doesn't exactly match [`GitInfo::sha1`].
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+}
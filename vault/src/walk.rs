@@ -10,28 +10,37 @@ use walkdir::{DirEntry, WalkDir};
 
 use crate::Error;
 
-pub(crate) fn top_level_manifests(path: &Path) -> impl Iterator<Item = Result<PathBuf, Error>> {
+/// Finds the top-level manifest of each crate version under `path`.
+///
+/// Hidden directories directly within `path` are skipped, since crate names and versions can't
+/// begin with a `.`. If `follow_links` is set, symlinked directories are walked as if they were
+/// real directories.
+pub(crate) fn top_level_manifests(
+    path: &Path,
+    follow_links: bool,
+) -> impl Iterator<Item = Result<PathBuf, Error>> {
     // Since it's possible for a crate file to include nested manifests at deeper levels, we only
     // want the _first_ manifest that we encounter as we walk through directories. We'll ensure
     // this by enforcing a sort order that puts manifests first, and then not recursing into
     // directories where we've already seen a manifest.
+    //
+    // On a case sensitive filesystem, a directory can contain both `Cargo.toml` and `cargo.toml`.
+    // Sorting manifests by name puts `Cargo.toml` first, and any later manifest in the same
+    // directory is skipped, so that each package only has one manifest.
     let mut seen = PrefixSet::default();
     WalkDir::new(path)
-        .sort_by(|a, b| {
-            if is_manifest(a) {
-                Ordering::Less
-            } else if is_manifest(b) {
-                Ordering::Greater
-            } else {
-                a.file_name().cmp(b.file_name())
-            }
+        .follow_links(follow_links)
+        .sort_by(|a, b| match (is_manifest(a), is_manifest(b)) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => a.file_name().cmp(b.file_name()),
         })
         .into_iter()
         .filter_entry(move |entry| {
             if is_manifest(entry) {
                 match manifest_parent(entry.path()) {
                     Ok(path) => {
-                        seen.insert(path);
+                        return seen.insert(path);
                     }
                     Err(e) => {
                         tracing::warn!(?e, "getting manifest parent");
@@ -41,6 +50,8 @@ pub(crate) fn top_level_manifests(path: &Path) -> impl Iterator<Item = Result<Pa
                 // If there was an error above, let's err on the side of keeping the manifest.
                 // (Sorry, no pun intended.)
                 true
+            } else if entry.depth() == 1 && entry.file_name().as_encoded_bytes().starts_with(b".") {
+                false
             } else if entry.file_type().is_dir() {
                 // Only iterate into a directory if it does _not_ contain a manifest.
                 !seen.contains(entry.path())
@@ -57,6 +68,41 @@ pub(crate) fn top_level_manifests(path: &Path) -> impl Iterator<Item = Result<Pa
         })
 }
 
+/// Finds the regular files within a crate version's root directory.
+///
+/// Symlinks are neither followed nor returned, so a crate can't lead a walk outside of its own
+/// directory, or into a loop. Hidden files and directories are included like any other.
+pub(crate) fn version_files(root: &Path) -> impl Iterator<Item = Result<PathBuf, Error>> {
+    WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|result| match result {
+            Ok(entry) if entry.file_type().is_file() => Some(Ok(entry.into_path())),
+            Ok(_entry) => None,
+            Err(e) => Some(Err(Error::from(e))),
+        })
+}
+
+/// Finds the `.crate` files under `path`, skipping hidden directories directly within it.
+pub(crate) fn archives(path: &Path) -> impl Iterator<Item = Result<PathBuf, Error>> {
+    WalkDir::new(path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            !(entry.depth() == 1 && entry.file_name().as_encoded_bytes().starts_with(b"."))
+        })
+        .filter_map(|result| match result {
+            Ok(entry)
+                if entry.file_type().is_file()
+                    && entry.path().extension() == Some(OsStr::new("crate")) =>
+            {
+                Some(Ok(entry.into_path()))
+            }
+            Ok(_entry) => None,
+            Err(e) => Some(Err(Error::from(e))),
+        })
+}
+
 #[derive(Default)]
 struct PrefixSet(BTreeSet<PathBuf>);
 
@@ -65,8 +111,9 @@ impl PrefixSet {
         path.ancestors().any(|path| self.0.contains(path))
     }
 
-    fn insert(&mut self, manifest_parent: &Path) {
-        self.0.insert(manifest_parent.to_path_buf());
+    /// Adds a manifest's parent directory, returning false if it was already present.
+    fn insert(&mut self, manifest_parent: &Path) -> bool {
+        self.0.insert(manifest_parent.to_path_buf())
     }
 }
 
@@ -105,14 +152,50 @@ mod tests {
         let b = create_manifest_at(&temp, "b")?;
         create_manifest_at(&temp, "b/c/d")?;
         let c = create_manifest_at(&temp, "c/d")?;
+        create_manifest_at(&temp, ".objects/abc")?;
 
-        let seen =
-            top_level_manifests(temp.path()).collect::<std::result::Result<Vec<_>, Error>>()?;
+        let seen = top_level_manifests(temp.path(), false)
+            .collect::<std::result::Result<Vec<_>, Error>>()?;
         assert_that!(seen, unordered_elements_are![eq(a), eq(b), eq(c)]);
 
         Ok(())
     }
 
+    #[test]
+    fn test_top_level_manifests_case_collision() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let manifest = create_manifest_at(&temp, "a/b")?;
+        std::fs::copy(&manifest, temp.path().join("a/b/cargo.toml"))?;
+        std::fs::write(temp.path().join("a/b/aardvark.rs"), "")?;
+
+        let seen = top_level_manifests(temp.path(), false)
+            .collect::<std::result::Result<Vec<_>, Error>>()?;
+        assert_that!(seen, elements_are![eq(manifest)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_files() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        create_manifest_at(&temp, "foo")?;
+        std::fs::create_dir_all(temp.path().join("foo/.github"))?;
+        std::fs::write(temp.path().join("foo/.github/ci.yml"), "")?;
+        std::os::unix::fs::symlink(temp.path(), temp.path().join("foo/loop"))?;
+
+        let seen = version_files(&temp.path().join("foo"))
+            .collect::<std::result::Result<Vec<_>, Error>>()?;
+        assert_that!(
+            seen,
+            eq(vec![
+                temp.path().join("foo/.github/ci.yml"),
+                temp.path().join("foo/Cargo.toml"),
+            ])
+        );
+
+        Ok(())
+    }
+
     fn create_manifest_at(base: &TempDir, path: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
         let path = base.path().join(path);
         std::fs::create_dir_all(&path)?;